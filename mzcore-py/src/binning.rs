@@ -0,0 +1,84 @@
+use numpy::{PyArray1, PyReadonlyArray1};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use mzcore::ms::binning::*;
+use mzcore::ms::spectrum::SpectrumData;
+
+fn _create_binning_config(
+    bin_width: f64,
+    bin_offset: f64,
+    min_mz: f64,
+    max_mz: f64,
+    aggregation: &str,
+    l2_normalize: bool
+) -> PyResult<SpectrumBinningConfig> {
+    let aggregation = match aggregation {
+        "max" => BinAggregation::Max,
+        "sum" => BinAggregation::Sum,
+        _ => return Err(PyValueError::new_err(format!("unsupported aggregation '{}', expected 'max' or 'sum'", aggregation))),
+    };
+
+    Ok(SpectrumBinningConfig::new(bin_width, bin_offset, min_mz, max_mz, aggregation, l2_normalize)?)
+}
+
+fn _create_spectrum_data(mz_list: PyReadonlyArray1<f64>, intensity_list: PyReadonlyArray1<f64>) -> PyResult<SpectrumData> {
+    let mz_list = mz_list.as_slice()?;
+    let intensity_list = intensity_list.as_slice()?;
+    if mz_list.len() != intensity_list.len() {
+        return Err(PyValueError::new_err("mz_list and intensity_list must have the same length"));
+    }
+
+    Ok(SpectrumData {
+        mz_list: mz_list.to_vec(),
+        intensity_list: intensity_list.iter().map(|&intensity| intensity as f32).collect(),
+    })
+}
+
+/// Bin a spectrum into a dense vector (Comet-style bins by default).
+#[pyfunction]
+#[pyo3(signature = (mz_list, intensity_list, bin_width=COMET_BIN_WIDTH, bin_offset=COMET_BIN_OFFSET, min_mz=0.0, max_mz=2000.0, aggregation="max", l2_normalize=false))]
+#[allow(clippy::too_many_arguments)]
+pub fn bin_spectrum<'py>(
+    py: Python<'py>,
+    mz_list: PyReadonlyArray1<f64>,
+    intensity_list: PyReadonlyArray1<f64>,
+    bin_width: f64,
+    bin_offset: f64,
+    min_mz: f64,
+    max_mz: f64,
+    aggregation: &str,
+    l2_normalize: bool,
+) -> PyResult<&'py PyArray1<f64>> {
+    let config = _create_binning_config(bin_width, bin_offset, min_mz, max_mz, aggregation, l2_normalize)?;
+    let spectrum = _create_spectrum_data(mz_list, intensity_list)?;
+
+    Ok(PyArray1::from_vec(py, bin_spectrum_dense(&spectrum, &config)))
+}
+
+/// Bin a spectrum into a sparse vector, returned as a tuple of (indices, values, n_bins).
+#[pyfunction]
+#[pyo3(signature = (mz_list, intensity_list, bin_width=COMET_BIN_WIDTH, bin_offset=COMET_BIN_OFFSET, min_mz=0.0, max_mz=2000.0, aggregation="max", l2_normalize=false))]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn bin_spectrum_sparse<'py>(
+    py: Python<'py>,
+    mz_list: PyReadonlyArray1<f64>,
+    intensity_list: PyReadonlyArray1<f64>,
+    bin_width: f64,
+    bin_offset: f64,
+    min_mz: f64,
+    max_mz: f64,
+    aggregation: &str,
+    l2_normalize: bool,
+) -> PyResult<(&'py PyArray1<usize>, &'py PyArray1<f64>, usize)> {
+    let config = _create_binning_config(bin_width, bin_offset, min_mz, max_mz, aggregation, l2_normalize)?;
+    let spectrum = _create_spectrum_data(mz_list, intensity_list)?;
+
+    let sparse_vector = mzcore::ms::binning::bin_spectrum_sparse(&spectrum, &config);
+
+    Ok((
+        PyArray1::from_vec(py, sparse_vector.indices),
+        PyArray1::from_vec(py, sparse_vector.values),
+        sparse_vector.n_bins,
+    ))
+}
@@ -1,7 +1,11 @@
 use pyo3::prelude::*;
 
+mod binning;
+
 /// Python bindings for mzcore
 #[pymodule]
-fn mzcore_py(py: Python, m: &PyModule) -> PyResult<()> {
+fn mzcore_py(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(binning::bin_spectrum, m)?)?;
+    m.add_function(wrap_pyfunction!(binning::bin_spectrum_sparse, m)?)?;
     Ok(())
 }
@@ -6,6 +6,7 @@ use anyhow::*;
 use serde::{Deserialize, Serialize};
 
 use crate::chemistry::api::*;
+use crate::chemistry::composition::ElementalComposition;
 use crate::chemistry::table::proteinogenic_amino_acid_table;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
@@ -220,6 +221,8 @@ pub struct AminoAcidDefinition {
     pub pka2: f32, // N-term pKa
     pub pka3: f32, // side chain pKa
     pub pi: f32, // isoelectric point
+    pub hydrophobicity: f32, // RP-HPLC retention coefficient (SSRCalc)
+    pub hydrophobicity_n_term: f32, // RP-HPLC retention coefficient when located at the peptide N-terminus
     pub codons: Vec<String>
 }
 
@@ -261,9 +264,17 @@ impl AminoAcidDefinition {
             pka2: 0.0,
             pka3: 0.0,
             pi: 0.0,
+            hydrophobicity: 0.0,
+            hydrophobicity_n_term: 0.0,
             codons: codons //.iter().map(|s| *s.to_string()).collect()
         })
     }
+
+    /// Residue composition parsed from the formula (Unimod notation)
+    pub fn composition(&self) -> Result<ElementalComposition> {
+        let formula = self.formula.as_ref().ok_or_else(|| anyhow!("no formula defined for amino acid '{}'", self.code1 as char))?;
+        ElementalComposition::parse_unimod_composition(formula)
+    }
 }
 
 impl HasMass for AminoAcidDefinition {
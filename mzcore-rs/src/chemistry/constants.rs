@@ -9,13 +9,16 @@ pub const ELECTRON_MASS: f64 = 0.00054857990946; // Source: NIST 2010 CODATA
 pub const PROTON_MASS: f64 = 1.007276466812; // Source: NIST 2010 CODATA
 
 pub const CO_MONO_MASS: f64 = 27.99491461956;
-pub const CO2_MONO_MASS: f64 = 0.0;  // FIXME
+pub const CO2_MONO_MASS: f64 = 43.98982923914;
 pub const H2O_MONO_MASS: f64 = 18.010565;
 pub const NH3_MONO_MASS: f64 = 17.02654910101;
 
 pub const WATER_MONO_MASS: f64 = 18.010565;
 pub const WATER_AVERAGE_MASS: f64 = 18.01525697318;
 
+pub const OXIDATION_MONO_MASS: f64 = 15.99491461956;
+pub const PHOSPHO_MONO_MASS: f64 = 79.96633052075;
+
 
 // --- Sage definition --- //
 // FIXME: some letters are sometimes used for undetermined amino acids (should we add them)
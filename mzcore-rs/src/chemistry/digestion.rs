@@ -0,0 +1,126 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum Enzyme {
+    /// Cleaves after K or R, except before P
+    Trypsin,
+    /// Cleaves after K or R, even before P
+    TrypsinP,
+    /// Cleaves after K
+    LysC,
+    /// Cleaves after R, except before P
+    ArgC,
+    /// Cleaves before D
+    AspN,
+    /// Cleaves after E
+    GluC,
+    /// Cleaves after F, W, Y or L, except before P
+    Chymotrypsin,
+    NoCleavage,
+}
+
+impl Enzyme {
+    /// Check if the bond between the `left` and `right` residues is cleaved
+    pub fn is_cleavage_site(&self, left: u8, right: u8) -> bool {
+        match self {
+            Enzyme::Trypsin => matches!(left, b'K' | b'R') && right != b'P',
+            Enzyme::TrypsinP => matches!(left, b'K' | b'R'),
+            Enzyme::LysC => left == b'K',
+            Enzyme::ArgC => left == b'R' && right != b'P',
+            Enzyme::AspN => right == b'D',
+            Enzyme::GluC => left == b'E',
+            Enzyme::Chymotrypsin => matches!(left, b'F' | b'W' | b'Y' | b'L') && right != b'P',
+            Enzyme::NoCleavage => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DigestionConfig {
+    pub enzyme: Enzyme,
+    pub max_missed_cleavages: usize,
+    pub min_length: usize,
+    pub max_length: usize,
+}
+
+impl Default for DigestionConfig {
+    fn default() -> Self {
+        DigestionConfig {
+            enzyme: Enzyme::Trypsin,
+            max_missed_cleavages: 2,
+            min_length: 7,
+            max_length: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestedPeptide {
+    pub sequence: String,
+    /// Position of the first residue in the protein sequence (starts at 0)
+    pub start: usize,
+    pub missed_cleavages: usize,
+}
+
+impl DigestionConfig {
+    /// Digest a protein sequence in silico. Peptides are returned by start position and length.
+    pub fn digest(&self, protein_sequence: &str) -> Result<Vec<DigestedPeptide>> {
+        if !protein_sequence.is_ascii() {
+            bail!("the protein sequence must only contain ASCII characters")
+        }
+
+        let residues = protein_sequence.as_bytes();
+        if residues.is_empty() {
+            return Ok(Vec::new())
+        }
+
+        // Positions where a peptide can start (after each cleavage site), plus the end of the sequence
+        let mut boundaries: Vec<usize> = vec![0];
+        boundaries.extend((1..residues.len()).filter(|&i| self.enzyme.is_cleavage_site(residues[i - 1], residues[i])));
+        boundaries.push(residues.len());
+
+        let mut peptides = Vec::new();
+        for start_idx in 0..boundaries.len() - 1 {
+            for missed_cleavages in 0..=self.max_missed_cleavages {
+                let end_idx = start_idx + missed_cleavages + 1;
+                if end_idx >= boundaries.len() {
+                    break
+                }
+
+                let (start, end) = (boundaries[start_idx], boundaries[end_idx]);
+                let length = end - start;
+                if length > self.max_length {
+                    break
+                }
+                if length >= self.min_length {
+                    peptides.push(DigestedPeptide {
+                        sequence: protein_sequence[start..end].to_string(),
+                        start,
+                        missed_cleavages,
+                    });
+                }
+            }
+        }
+
+        Ok(peptides)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tryptic_digestion() -> Result<()> {
+        let config = DigestionConfig { max_missed_cleavages: 1, min_length: 1, max_length: 50, ..Default::default() };
+        let peptides = config.digest("MKWVTFISLLRPEEK")?;
+        let sequences: Vec<&str> = peptides.iter().map(|pep| pep.sequence.as_str()).collect();
+
+        assert_eq!(sequences, vec!["MK", "MKWVTFISLLRPEEK", "WVTFISLLRPEEK"]);
+        assert_eq!(peptides[1].missed_cleavages, 1);
+        assert_eq!(peptides[2].start, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::composition::ElementalComposition;
+use crate::chemistry::constants::{AVERAGE_AA_MASS, AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF, ELECTRON_MASS};
+use crate::chemistry::element::Element;
+use crate::chemistry::table::{biomolecule_atom_table, AtomTable};
+use crate::ms::utils::mass_to_mz;
+
+// Averagine model (Senko et al., 1995): the average elemental composition of an amino acid residue
+// having an average mass of AVERAGE_AA_MASS
+pub const AVERAGINE_C: f64 = 4.9384;
+pub const AVERAGINE_H: f64 = 7.7583;
+pub const AVERAGINE_N: f64 = 1.3577;
+pub const AVERAGINE_O: f64 = 1.4773;
+pub const AVERAGINE_S: f64 = 0.0417;
+
+/// Isotopic distribution of a molecule computed at nominal mass resolution (isotopic fine structure is not resolved).
+/// The abundances of the M, M+1, M+2... peaks sum to 1.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IsotopeDistribution {
+    pub mono_mass: f64,
+    pub abundances: Vec<f64>,
+}
+
+impl IsotopeDistribution {
+
+    /// Compute the isotopic distribution of an elemental composition using the biomolecule atom table.
+    /// Element counts are rounded to the nearest integer.
+    /// Atoms having a fixed isotope (isotope_index > 0) only shift the mass and don't contribute to the distribution.
+    pub fn from_composition(composition: &ElementalComposition, max_isotopes: usize) -> Result<IsotopeDistribution> {
+        Self::from_composition_and_atom_table(composition, max_isotopes, biomolecule_atom_table())
+    }
+
+    pub fn from_composition_and_atom_table(
+        composition: &ElementalComposition,
+        max_isotopes: usize,
+        atom_table: &AtomTable
+    ) -> Result<IsotopeDistribution> {
+        if max_isotopes == 0 { bail!("max_isotopes must be a strictly positive number") }
+
+        let mut mono_mass = composition.additional_mass;
+        let mut abundances = vec![1.0];
+
+        for elc in composition.element_counts.iter() {
+            let count = elc.count.round() as i64;
+            if count == 0 { continue }
+
+            if elc.element == Element::Electron {
+                mono_mass += ELECTRON_MASS * count as f64;
+                continue
+            }
+
+            let atom = atom_table.atom_by_element.get(&elc.element).ok_or_else(|| anyhow!("unknown element {}", elc.element))?;
+            let isotope = atom.isotopes.get(elc.isotope_index as usize).ok_or_else(|| anyhow!("wrong isotope index {}", elc.isotope_index))?;
+            mono_mass += isotope.mass * count as f64;
+
+            if elc.isotope_index != 0 { continue }
+            if count < 0 { bail!("can't compute the isotopic distribution of a negative count of {}", elc.element) }
+
+            // Element isotopic pattern indexed by nominal mass offset
+            let mut element_pattern = vec![0.0; max_isotopes];
+            let abundance_sum: f64 = atom.isotopes.iter().map(|iso| iso.abundance as f64).sum();
+            for iso in atom.isotopes.iter() {
+                let offset = (iso.mass - isotope.mass).round() as usize;
+                if offset < max_isotopes {
+                    element_pattern[offset] += iso.abundance as f64 / abundance_sum;
+                }
+            }
+
+            let element_distrib = _pow_pattern(&element_pattern, count as u64, max_isotopes);
+            abundances = _convolve(&abundances, &element_distrib, max_isotopes);
+        }
+
+        abundances.resize(max_isotopes, 0.0);
+        let total: f64 = abundances.iter().sum();
+        if total > 0.0 {
+            abundances.iter_mut().for_each(|ab| *ab /= total);
+        }
+
+        Ok(IsotopeDistribution {
+            mono_mass,
+            abundances,
+        })
+    }
+
+    /// Estimate the isotopic distribution of a peptide having the provided neutral monoisotopic mass
+    pub fn averagine(mono_mass: f64, max_isotopes: usize) -> Result<IsotopeDistribution> {
+        let averagine_comp = averagine_composition(mono_mass);
+        let mut distrib = Self::from_composition(&averagine_comp, max_isotopes)?;
+        distrib.mono_mass = mono_mass;
+
+        Ok(distrib)
+    }
+
+    /// Index of the most abundant isotopic peak
+    pub fn most_abundant_index(&self) -> usize {
+        self.abundances.iter().enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(idx, _)| idx)
+            .unwrap_or(0)
+    }
+
+    /// Abundances scaled to the most abundant peak (base peak = 1.0)
+    pub fn relative_abundances(&self) -> Vec<f64> {
+        let max_ab = self.abundances.iter().copied().fold(0.0, f64::max);
+        if max_ab == 0.0 {
+            return self.abundances.clone()
+        }
+        self.abundances.iter().map(|ab| ab / max_ab).collect()
+    }
+
+    /// m/z values of the isotopic peaks at a given charge state
+    pub fn mz_values(&self, charge: i32) -> Vec<f64> {
+        let z = charge.abs().max(1) as f64;
+        let mono_mz = mass_to_mz(self.mono_mass, charge);
+        (0..self.abundances.len()).map(|idx| {
+            mono_mz + idx as f64 * AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / z
+        }).collect()
+    }
+}
+
+/// Build an averagine composition matching a given neutral mass (hydrogen atoms absorb the rounding error)
+pub fn averagine_composition(mass: f64) -> ElementalComposition {
+    let n_residues = (mass / AVERAGE_AA_MASS).max(0.0);
+
+    let c = (AVERAGINE_C * n_residues).round();
+    let n = (AVERAGINE_N * n_residues).round();
+    let o = (AVERAGINE_O * n_residues).round();
+    let s = (AVERAGINE_S * n_residues).round();
+
+    let atom_table = biomolecule_atom_table();
+    let mono_mass_of = |el: Element| atom_table.atom_by_element.get(&el).map(|atom| atom.monoisotopic_mass()).unwrap_or_default();
+
+    let cnos_mass = c * mono_mass_of(Element::C) + n * mono_mass_of(Element::N) + o * mono_mass_of(Element::O) + s * mono_mass_of(Element::S);
+    let h = ((mass - cnos_mass) / mono_mass_of(Element::H)).round().max(0.0);
+
+    ElementalComposition::from_monoisotope_tuples(&[
+        (Element::C, c as i16),
+        (Element::H, h as i16),
+        (Element::N, n as i16),
+        (Element::O, o as i16),
+        (Element::S, s as i16),
+    ])
+}
+
+fn _convolve(lhs: &[f64], rhs: &[f64], max_len: usize) -> Vec<f64> {
+    let out_len = (lhs.len() + rhs.len() - 1).min(max_len);
+    let mut out = vec![0.0; out_len];
+    for (i, a) in lhs.iter().enumerate() {
+        if *a == 0.0 { continue }
+        for (j, b) in rhs.iter().enumerate() {
+            if i + j >= out_len { break }
+            out[i + j] += a * b;
+        }
+    }
+    out
+}
+
+// Exponentiation by squaring of a truncated isotopic pattern
+fn _pow_pattern(pattern: &[f64], mut exponent: u64, max_len: usize) -> Vec<f64> {
+    let mut result = vec![1.0];
+    let mut base = pattern.to_vec();
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = _convolve(&result, &base, max_len);
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = _convolve(&base, &base, max_len);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn water_isotope_distribution() -> Result<()> {
+        let water = ElementalComposition::from_monoisotope_tuples(&[(Element::H, 2), (Element::O, 1)]);
+        let distrib = IsotopeDistribution::from_composition(&water, 3)?;
+
+        assert!((distrib.mono_mass - 18.010565).abs() < 0.0001, "unexpected water mass {}", distrib.mono_mass);
+        assert!((distrib.abundances[0] - 0.99734).abs() < 0.0001);
+        assert!((distrib.abundances[2] - 0.00205).abs() < 0.0001);
+
+        Ok(())
+    }
+
+    #[test]
+    fn averagine_isotope_distribution() -> Result<()> {
+        // Around 1800 Da, the monoisotopic peak is no longer the most abundant one
+        let distrib = IsotopeDistribution::averagine(1000.0, 6)?;
+        assert_eq!(distrib.most_abundant_index(), 0);
+
+        let distrib = IsotopeDistribution::averagine(3000.0, 6)?;
+        assert_eq!(distrib.most_abundant_index(), 1);
+
+        Ok(())
+    }
+}
@@ -5,12 +5,16 @@ pub mod amino_acid;
 pub mod atom;
 pub mod composition;
 pub mod constants;
+pub mod digestion;
 pub mod element;
 pub mod glycan;
 pub mod isotope;
+pub mod isotope_distribution;
 pub mod ptm;
 pub mod peptide;
+pub mod peptide_properties;
 pub mod table;
+pub mod translation;
 pub mod unimod;
 
 
@@ -44,4 +48,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn non_standard_amino_acids() -> Result<()> {
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        let selenocysteine = default_aa_table.aa_from_byte(&b'U')?;
+        assert_eq!(selenocysteine.code3, "Sec");
+        assert!((selenocysteine.mono_mass - 150.9536353).abs() < 1e-6);
+
+        let pyrrolysine = default_aa_table.aa_from_byte(&b'O')?;
+        assert_eq!(pyrrolysine.code3, "Pyl");
+        assert!((pyrrolysine.mono_mass - 237.1477266).abs() < 1e-6);
+
+        // Standard residues are still resolved
+        assert!((default_aa_table.aa_from_byte(&b'K')?.mono_mass - 128.09496).abs() < 1e-4);
+
+        Ok(())
+    }
 }
@@ -80,7 +80,7 @@ trait IsModification: HasMass {
 pub struct SimpleModification {
     pub id: i64, // can be used to map to set of pre-defined PTMs
     pub mono_mass: f64,
-    pub position: Option<i32>, // -1 means N-term
+    pub position: Option<i32>, // residue index starting at 0, -1 means N-term and the sequence length means C-term
 }
 
 impl SimpleModification {
@@ -0,0 +1,295 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::api::*;
+use crate::chemistry::table::{proteinogenic_amino_acid_table, AminoAcidTable};
+use crate::chemistry::constants::{WATER_AVERAGE_MASS, WATER_MONO_MASS};
+use crate::ms::MassType;
+
+const STANDARD_AMINO_ACIDS: &[u8; 20] = b"ACDEFGHIKLMNPQRSTVWY";
+
+/// Kyte & Doolittle hydropathy values, in the order of STANDARD_AMINO_ACIDS
+const KYTE_DOOLITTLE_HYDROPATHY: [f64; 20] = [
+    1.8, 2.5, -3.5, -3.5, 2.8, -0.4, -3.2, 4.5, -3.9, 3.8, 1.9, -3.5, -1.6, -3.5, -4.5, -0.8, -0.7, 4.2, -0.9, -1.3,
+];
+
+/// Dipeptide instability weight values (Guruprasad et al., 1990), rows and columns in the order of STANDARD_AMINO_ACIDS
+const DIWV: [[f64; 20]; 20] = [
+    // A      C      D      E      F      G      H      I      K      L      M      N      P      Q      R      S      T      V      W      Y
+    [1.0, 44.94, -7.49, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0, 1.0, 1.0, 1.0, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0], // A
+    [1.0, 1.0, 20.26, 1.0, 1.0, 1.0, 33.60, 1.0, 1.0, 20.26, 33.60, 1.0, 20.26, -6.54, 1.0, 1.0, 33.60, -6.54, 24.68, 1.0], // C
+    [1.0, 1.0, 1.0, 1.0, -6.54, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0, 1.0, 1.0, 1.0, -6.54, 20.26, -14.03, 1.0, 1.0, 1.0], // D
+    [1.0, 44.94, 20.26, 33.60, 1.0, 1.0, -6.54, 20.26, 1.0, 1.0, 1.0, 1.0, 20.26, 20.26, 1.0, 20.26, 1.0, 1.0, -14.03, 1.0], // E
+    [1.0, 1.0, 13.34, 1.0, 1.0, 1.0, 1.0, 1.0, -14.03, 1.0, 1.0, 1.0, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 33.601], // F
+    [-7.49, 1.0, 1.0, -6.54, 1.0, 13.34, 1.0, -7.49, -7.49, 1.0, 1.0, -7.49, 1.0, 1.0, 1.0, 1.0, -7.49, 1.0, 13.34, -7.49], // G
+    [1.0, 1.0, 1.0, 1.0, -9.37, -9.37, 1.0, 44.94, 24.68, 1.0, 1.0, 24.68, -1.88, 1.0, 1.0, 1.0, -6.54, 1.0, -1.88, 44.94], // H
+    [1.0, 1.0, 1.0, 44.94, 1.0, 1.0, 13.34, 1.0, -7.49, 20.26, 1.0, 1.0, -1.88, 1.0, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0], // I
+    [1.0, 1.0, 1.0, 1.0, 1.0, -7.49, 1.0, -7.49, 1.0, -7.49, 33.60, 1.0, -6.54, 24.64, 33.60, 1.0, 1.0, -7.49, 1.0, 1.0], // K
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0, 1.0, 20.26, 33.60, 20.26, 1.0, 1.0, 1.0, 24.68, 1.0], // L
+    [13.34, 1.0, 1.0, 1.0, 1.0, 1.0, 58.28, 1.0, 1.0, 1.0, -1.88, 1.0, 44.94, -6.54, -6.54, 44.94, -1.88, 1.0, 1.0, 24.68], // M
+    [1.0, -1.88, 1.0, 1.0, -14.03, -14.03, 1.0, 44.94, 24.68, 1.0, 1.0, 1.0, -1.88, -6.54, 1.0, 1.0, -7.49, 1.0, -9.37, 1.0], // N
+    [20.26, -6.54, -6.54, 18.38, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0, -6.54, 1.0, 20.26, 20.26, -6.54, 20.26, 1.0, 20.26, -1.88, 1.0], // P
+    [1.0, -6.54, 20.26, 20.26, -6.54, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 20.26, 20.26, 1.0, 44.94, 1.0, -6.54, 1.0, -6.54], // Q
+    [1.0, 1.0, 1.0, 1.0, 1.0, -7.49, 20.26, 1.0, 1.0, 1.0, 1.0, 13.34, 20.26, 20.26, 58.28, 44.94, 1.0, 1.0, 58.28, -6.54], // R
+    [1.0, 33.60, 1.0, 20.26, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 44.94, 20.26, 20.26, 20.26, 1.0, 1.0, 1.0, 1.0], // S
+    [1.0, 1.0, 1.0, 20.26, 13.34, -7.49, 1.0, 1.0, 1.0, 1.0, 1.0, -14.03, 1.0, -6.54, 1.0, 1.0, 1.0, 1.0, -14.03, 1.0], // T
+    [1.0, 1.0, -14.03, 1.0, 1.0, -7.49, 1.0, 1.0, -1.88, 1.0, 1.0, 1.0, 20.26, 1.0, 1.0, 1.0, -7.49, 1.0, 1.0, -6.54], // V
+    [-14.03, 1.0, 1.0, 1.0, 1.0, -9.37, 24.68, 1.0, 1.0, 13.34, 24.68, 13.34, 1.0, 1.0, 1.0, 1.0, -14.03, -7.49, 1.0, 1.0], // W
+    [24.68, 1.0, 24.68, -6.54, 1.0, -7.49, 13.34, 1.0, 1.0, 1.0, 44.94, 1.0, 13.34, 1.0, -15.91, 1.0, -7.49, 1.0, -9.37, 13.34], // Y
+];
+
+/// pKa values used to compute the charge state of a sequence
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PkaSet {
+    Emboss,
+    Lehninger,
+    /// Includes the terminal pKa values depending on the terminal residues (as in ExPASy Compute pI/Mw)
+    Bjellqvist,
+    /// pka1 (C-term), pka2 (N-term) and pka3 (side chain) fields of the proteinogenic amino acid definitions
+    AminoAcidDefinitions,
+}
+
+impl PkaSet {
+    pub fn n_term_pka(&self, residue: u8) -> Result<f64> {
+        let pka = match self {
+            PkaSet::Emboss => 8.6,
+            PkaSet::Lehninger => 9.69,
+            PkaSet::Bjellqvist => match residue {
+                b'A' => 7.59,
+                b'M' => 7.0,
+                b'S' => 6.93,
+                b'P' => 8.36,
+                b'T' => 6.82,
+                b'V' => 7.44,
+                b'E' => 7.7,
+                _ => 7.5,
+            },
+            PkaSet::AminoAcidDefinitions => proteinogenic_amino_acid_table().aa_from_byte(&residue)?.pka2 as f64,
+        };
+
+        if pka <= 0.0 { bail!("no N-terminal pKa defined for amino acid '{}'", residue as char) }
+        Ok(pka)
+    }
+
+    pub fn c_term_pka(&self, residue: u8) -> Result<f64> {
+        let pka = match self {
+            PkaSet::Emboss => 3.6,
+            PkaSet::Lehninger => 2.34,
+            PkaSet::Bjellqvist => match residue {
+                b'D' => 4.55,
+                b'E' => 4.75,
+                _ => 3.55,
+            },
+            PkaSet::AminoAcidDefinitions => proteinogenic_amino_acid_table().aa_from_byte(&residue)?.pka1 as f64,
+        };
+
+        if pka <= 0.0 { bail!("no C-terminal pKa defined for amino acid '{}'", residue as char) }
+        Ok(pka)
+    }
+
+    /// pKa of an ionizable side chain (None if the side chain is not ionizable)
+    pub fn side_chain_pka(&self, residue: u8) -> Option<f64> {
+        let pkas: [f64; 7] = match self {
+            // C, D, E, H, K, R, Y
+            PkaSet::Emboss => [8.5, 3.9, 4.1, 6.5, 10.8, 12.5, 10.1],
+            PkaSet::Lehninger => [8.18, 3.65, 4.25, 6.0, 10.53, 12.48, 10.07],
+            PkaSet::Bjellqvist => [9.0, 4.05, 4.45, 5.98, 10.0, 12.0, 10.0],
+            PkaSet::AminoAcidDefinitions => {
+                let pka = proteinogenic_amino_acid_table().aa_from_byte(&residue).ok()?.pka3 as f64;
+                return if _is_ionizable(residue) && pka > 0.0 { Some(pka) } else { None }
+            }
+        };
+
+        let idx = b"CDEHKRY".iter().position(|aa| *aa == residue)?;
+        Some(pkas[idx])
+    }
+}
+
+fn _is_ionizable(residue: u8) -> bool {
+    b"CDEHKRY".contains(&residue)
+}
+
+fn _is_basic(residue: u8) -> bool {
+    b"HKR".contains(&residue)
+}
+
+/// Net charge of a sequence at a given pH (Henderson–Hasselbalch equation)
+pub fn net_charge<S: IsAminoAcidSeq>(aa_seq: S, ph: f64, pka_set: PkaSet) -> Result<f64> {
+    _net_charge(aa_seq.amino_acids_as_bytes().as_slice(), ph, pka_set)
+}
+
+/// Isoelectric point of a sequence, computed by bisection on the net charge
+pub fn isoelectric_point<S: IsAminoAcidSeq>(aa_seq: S, pka_set: PkaSet) -> Result<f64> {
+    let residues = aa_seq.amino_acids_as_bytes().as_slice();
+
+    let (mut ph_low, mut ph_high) = (0.0, 14.0);
+    while ph_high - ph_low > 0.0001 {
+        let ph = (ph_low + ph_high) / 2.0;
+        if _net_charge(residues, ph, pka_set)? > 0.0 {
+            ph_low = ph
+        } else {
+            ph_high = ph
+        }
+    }
+
+    Ok((ph_low + ph_high) / 2.0)
+}
+
+fn _net_charge(residues: &[u8], ph: f64, pka_set: PkaSet) -> Result<f64> {
+    let (Some(first_residue), Some(last_residue)) = (residues.first(), residues.last()) else {
+        bail!("sequence is empty")
+    };
+
+    let positive_charge = |pka: f64| 1.0 / (1.0 + 10f64.powf(ph - pka));
+    let negative_charge = |pka: f64| 1.0 / (1.0 + 10f64.powf(pka - ph));
+
+    let mut charge = positive_charge(pka_set.n_term_pka(*first_residue)?) - negative_charge(pka_set.c_term_pka(*last_residue)?);
+    for residue in residues {
+        if let Some(pka) = pka_set.side_chain_pka(*residue) {
+            charge += if _is_basic(*residue) { positive_charge(pka) } else { -negative_charge(pka) };
+        }
+    }
+
+    Ok(charge)
+}
+
+/// Grand average of hydropathy (Kyte & Doolittle, 1982)
+pub fn gravy<S: IsAminoAcidSeq>(aa_seq: S) -> Result<f64> {
+    let residues = aa_seq.amino_acids_as_bytes().as_slice();
+    if residues.is_empty() {
+        bail!("sequence is empty")
+    }
+
+    let mut hydropathy_sum = 0.0;
+    for residue in residues {
+        hydropathy_sum += KYTE_DOOLITTLE_HYDROPATHY[_standard_aa_index(*residue)?];
+    }
+
+    Ok(hydropathy_sum / residues.len() as f64)
+}
+
+/// Relative volume occupied by aliphatic side chains (Ikai, 1980)
+pub fn aliphatic_index<S: IsAminoAcidSeq>(aa_seq: S) -> Result<f64> {
+    let residues = aa_seq.amino_acids_as_bytes().as_slice();
+    if residues.is_empty() {
+        bail!("sequence is empty")
+    }
+
+    let mole_percent = |aa: u8| 100.0 * residues.iter().filter(|residue| **residue == aa).count() as f64 / residues.len() as f64;
+
+    Ok(mole_percent(b'A') + 2.9 * mole_percent(b'V') + 3.9 * (mole_percent(b'I') + mole_percent(b'L')))
+}
+
+/// Instability index (Guruprasad et al., 1990): proteins with an index above 40 are predicted as unstable
+pub fn instability_index<S: IsAminoAcidSeq>(aa_seq: S) -> Result<f64> {
+    let residues = aa_seq.amino_acids_as_bytes().as_slice();
+    if residues.is_empty() {
+        bail!("sequence is empty")
+    }
+
+    let mut diwv_sum = 0.0;
+    for dipeptide in residues.windows(2) {
+        diwv_sum += DIWV[_standard_aa_index(dipeptide[0])?][_standard_aa_index(dipeptide[1])?];
+    }
+
+    Ok(10.0 * diwv_sum / residues.len() as f64)
+}
+
+/// Molar extinction coefficients at 280 nm in water (Pace et al., 1995), in M-1 cm-1
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExtinctionCoefficients {
+    /// Assuming all Cys residues are reduced
+    pub reduced: f64,
+    /// Assuming all pairs of Cys residues form cystines
+    pub cystines: f64,
+}
+
+pub fn extinction_coefficients<S: IsAminoAcidSeq>(aa_seq: S) -> ExtinctionCoefficients {
+    let residues = aa_seq.amino_acids_as_bytes().as_slice();
+    let count = |aa: u8| residues.iter().filter(|residue| **residue == aa).count() as f64;
+
+    let reduced = 5500.0 * count(b'W') + 1490.0 * count(b'Y');
+    ExtinctionCoefficients { reduced, cystines: reduced + 125.0 * (count(b'C') / 2.0).floor() }
+}
+
+/// Molecular weight of the unmodified sequence (residue masses plus water)
+pub fn molecular_weight<S: IsAminoAcidSeq>(aa_seq: S, aa_table: &AminoAcidTable, mass_type: MassType) -> Result<f64> {
+    let mut weight = match mass_type {
+        MassType::Monoisotopic => WATER_MONO_MASS,
+        MassType::Average => WATER_AVERAGE_MASS,
+    };
+    for residue in aa_seq.amino_acids_as_bytes() {
+        let aa = aa_table.aa_from_byte(residue)?;
+        weight += match mass_type {
+            MassType::Monoisotopic => aa.mono_mass,
+            MassType::Average => aa.average_mass,
+        };
+    }
+
+    Ok(weight)
+}
+
+fn _standard_aa_index(residue: u8) -> Result<usize> {
+    STANDARD_AMINO_ACIDS.iter().position(|aa| *aa == residue)
+        .ok_or_else(|| anyhow!("amino acid '{}' is not one of the 20 standard amino acids", residue as char))
+}
+
+/// Physicochemical properties of a peptide or protein sequence
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeptideProperties {
+    pub molecular_weight: f64,
+    pub isoelectric_point: f64,
+    /// Net charge at pH 7
+    pub net_charge: f64,
+    pub gravy: f64,
+    pub aliphatic_index: f64,
+    pub instability_index: f64,
+    pub extinction_coefficients: ExtinctionCoefficients,
+}
+
+impl PeptideProperties {
+    /// Compute all the properties of a sequence made of standard amino acids (molecular weight is the average one)
+    pub fn compute<S: IsAminoAcidSeq>(aa_seq: S, aa_table: &AminoAcidTable, pka_set: PkaSet) -> Result<PeptideProperties> {
+        let residues = aa_seq.amino_acids_as_bytes().as_slice();
+        let sequence = std::str::from_utf8(residues)?;
+
+        Ok(PeptideProperties {
+            molecular_weight: molecular_weight(sequence, aa_table, MassType::Average)?,
+            isoelectric_point: isoelectric_point(sequence, pka_set)?,
+            net_charge: net_charge(sequence, 7.0, pka_set)?,
+            gravy: gravy(sequence)?,
+            aliphatic_index: aliphatic_index(sequence)?,
+            instability_index: instability_index(sequence)?,
+            extinction_coefficients: extinction_coefficients(sequence),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_peptide_properties() -> Result<()> {
+        let aa_table = proteinogenic_amino_acid_table();
+
+        // Only C-terminal and N-terminal charges: pI at the middle of the two pKa values
+        assert!((isoelectric_point("GAG", PkaSet::Emboss)? - 6.1).abs() < 0.001);
+        assert!(net_charge("KKK", 7.0, PkaSet::Lehninger)? > 2.9);
+        assert!(isoelectric_point("DDEE", PkaSet::Bjellqvist)? < 4.0);
+
+        let props = PeptideProperties::compute("MKWVTFISLLLLFSSAYSRGVFRR", aa_table, PkaSet::Bjellqvist)?;
+        assert!((props.isoelectric_point - 12.0).abs() < 0.5);
+        assert!(props.net_charge > 2.0);
+        assert!((props.gravy - 16.3 / 24.0).abs() < 1e-9);
+        // 4.17 (A) + 2.9 * 8.33 (V) + 3.9 * 20.83 (I + L)
+        assert!((props.aliphatic_index - 109.583).abs() < 0.001);
+        assert_eq!(props.extinction_coefficients.reduced, 5500.0 + 1490.0);
+        assert!((props.molecular_weight - 2878.44).abs() < 0.05);
+
+        assert!((instability_index("AP")? - 10.0 * 20.26 / 2.0).abs() < 1e-9);
+        assert!(gravy("PEPTIDEX").is_err());
+
+        Ok(())
+    }
+}
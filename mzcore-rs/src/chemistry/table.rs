@@ -63,6 +63,45 @@ impl AtomTable {
             additional_mass:el_comp.additional_mass
         })
     }
+
+    /// Monoisotopic mass of a composition (atoms having a fixed isotope use the mass of this isotope)
+    pub fn calc_mono_mass(&self, el_comp: &ElementalComposition) -> Result<f64> {
+        self._calc_mass(el_comp, false)
+    }
+
+    /// Average mass of a composition (atoms having a fixed isotope use the mass of this isotope)
+    pub fn calc_average_mass(&self, el_comp: &ElementalComposition) -> Result<f64> {
+        self._calc_mass(el_comp, true)
+    }
+
+    /// Index of the isotope having the provided mass number
+    pub fn isotope_index(&self, element: El, mass_number: u16) -> Option<u8> {
+        self.atom_by_element.get(&element)?
+            .isotopes.iter()
+            .position(|isotope| isotope.mass_number == mass_number)
+            .map(|idx| idx as u8)
+    }
+
+    fn _calc_mass(&self, el_comp: &ElementalComposition, average: bool) -> Result<f64> {
+        let mut mass = el_comp.additional_mass;
+        for elc in el_comp.element_counts.iter() {
+            if elc.element == El::Electron {
+                mass += ELECTRON_MASS * elc.count as f64;
+                continue
+            }
+
+            let atom = self.atom_by_element.get(&elc.element).ok_or_else(|| anyhow!("unknown element {}", elc.element))?;
+            let atom_mass = if average && elc.isotope_index == 0 {
+                atom.calc_average_mass()
+            } else {
+                atom.isotopes.get(elc.isotope_index as usize).ok_or_else(|| anyhow!("wrong isotope index {}", elc.isotope_index))?.mass
+            };
+
+            mass += atom_mass * elc.count as f64;
+        }
+
+        Ok(mass)
+    }
 }
 
 static BIOMOLECULE_ATOM_TABLE: OnceLock<AtomTable> = OnceLock::new();
@@ -184,6 +223,7 @@ pub fn standard_amino_acid_table() -> &'static AminoAcidTable {
 // Sources:
 // - https://proteomicsresource.washington.edu/tools/masses.php
 // - http://www.matrixscience.com/help/aa_help.html
+// - hydrophobicity: retention coefficients of Krokhin et al., Mol. Cell. Proteomics 2004 (SSRCalc)
 fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
     AminoAcidTable::new(
         vec![
@@ -199,6 +239,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.87,
                 pka3: 0.0,
                 pi: 6.01,
+                hydrophobicity: 0.8,
+                hydrophobicity_n_term: -1.5,
                 codons: svec!["GCA", "GCC", "GCG", "GCU"]
             },
             AminoAcidDefinition {
@@ -213,6 +255,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 8.99,
                 pka3: 12.48,
                 pi: 10.76,
+                hydrophobicity: -1.3,
+                hydrophobicity_n_term: 8.0,
                 codons: svec!["AGA", "AGG", "CGA", "CGC", "CGG", "CGU"]
             },
             AminoAcidDefinition {
@@ -227,6 +271,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 8.72,
                 pka3: 0.0,
                 pi: 5.41,
+                hydrophobicity: -1.2,
+                hydrophobicity_n_term: 5.0,
                 codons: svec!["AAC", "AAU"]
             },
             AminoAcidDefinition {
@@ -241,6 +287,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.9,
                 pka3: 3.9,
                 pi: 2.85,
+                hydrophobicity: -0.5,
+                hydrophobicity_n_term: 9.0,
                 codons: svec!["GAC", "GAU"]
             },
             AminoAcidDefinition {
@@ -255,6 +303,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 10.7,
                 pka3: 8.18,
                 pi: 5.05,
+                hydrophobicity: -0.8,
+                hydrophobicity_n_term: 4.0,
                 codons: svec!["UGC", "UGU"]
             },
             AminoAcidDefinition {
@@ -269,6 +319,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.47,
                 pka3: 4.07,
                 pi: 3.15,
+                hydrophobicity: 0.0,
+                hydrophobicity_n_term: 7.0,
                 codons: svec!["GAA", "GAG"]
             },
             AminoAcidDefinition {
@@ -283,6 +335,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.13,
                 pka3: 0.0,
                 pi: 5.65,
+                hydrophobicity: -0.9,
+                hydrophobicity_n_term: 1.0,
                 codons: svec!["CAA", "CAG"]
             },
             AminoAcidDefinition {
@@ -297,6 +351,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.78,
                 pka3: 0.0,
                 pi: 6.06,
+                hydrophobicity: -0.9,
+                hydrophobicity_n_term: 5.0,
                 codons: svec!["GGA", "GGC", "GGG", "GGU"]
             },
             AminoAcidDefinition {
@@ -311,6 +367,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.33,
                 pka3: 6.04,
                 pi: 7.6,
+                hydrophobicity: -1.3,
+                hydrophobicity_n_term: 4.0,
                 codons: svec!["CAC", "CAU"]
             },
             AminoAcidDefinition {
@@ -325,6 +383,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.76,
                 pka3: 0.0,
                 pi: 6.05,
+                hydrophobicity: 8.4,
+                hydrophobicity_n_term: -8.0,
                 codons: svec!["AUA", "AUC", "AUU"]
             },
             AminoAcidDefinition {
@@ -339,6 +399,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.74,
                 pka3: 0.0,
                 pi: 6.01,
+                hydrophobicity: 9.6,
+                hydrophobicity_n_term: -9.0,
                 codons: svec!["CUA", "CUC", "CUG", "CUU", "UUA", "UUG"]
             },
             AminoAcidDefinition {
@@ -353,6 +415,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.06,
                 pka3: 10.54,
                 pi: 9.6,
+                hydrophobicity: -1.9,
+                hydrophobicity_n_term: 4.6,
                 codons: svec!["AAA", "AAG"]
             },
             AminoAcidDefinition {
@@ -367,6 +431,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.28,
                 pka3: 0.0,
                 pi: 5.74,
+                hydrophobicity: 5.8,
+                hydrophobicity_n_term: -5.5,
                 codons: svec!["AUG"]
             },
             AminoAcidDefinition {
@@ -381,6 +447,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.31,
                 pka3: 0.0,
                 pi: 5.49,
+                hydrophobicity: 10.5,
+                hydrophobicity_n_term: -7.0,
                 codons: svec!["UUC", "UUU"]
             },
             AminoAcidDefinition {
@@ -395,6 +463,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 10.64,
                 pka3: 0.0,
                 pi: 6.3,
+                hydrophobicity: 0.2,
+                hydrophobicity_n_term: 4.0,
                 codons: svec!["CCA", "CCC", "CCG", "CCU"]
             },
             AminoAcidDefinition {
@@ -409,6 +479,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.21,
                 pka3: 5.68,
                 pi: 5.68,
+                hydrophobicity: -0.8,
+                hydrophobicity_n_term: 5.0,
                 codons: svec!["AGC", "AGU", "UCA", "UCC", "UCG", "UCU"]
             },
             AminoAcidDefinition {
@@ -423,6 +495,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.1,
                 pka3: 5.53,
                 pi: 5.6,
+                hydrophobicity: 0.4,
+                hydrophobicity_n_term: 5.0,
                 codons: svec!["ACA", "ACC", "ACG", "ACU"]
             },
             AminoAcidDefinition {
@@ -437,6 +511,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.41,
                 pka3: 5.885,
                 pi: 5.89,
+                hydrophobicity: 11.0,
+                hydrophobicity_n_term: -4.0,
                 codons: svec!["UGG"]
             },
             AminoAcidDefinition {
@@ -451,6 +527,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.21,
                 pka3: 10.46,
                 pi: 5.64,
+                hydrophobicity: 4.0,
+                hydrophobicity_n_term: -3.0,
                 codons: svec!["UAC", "UAU"]
             },
             AminoAcidDefinition {
@@ -465,6 +543,8 @@ fn _create_standard_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 9.74,
                 pka3: 0.0,
                 pi: 6.0,
+                hydrophobicity: 5.0,
+                hydrophobicity_n_term: -5.5,
                 codons: svec!["GUA", "GUC", "GUG", "GUU"]
             }
         ]
@@ -475,7 +555,7 @@ static PROTEINOGENIC_AMINO_ACID_TABLE: OnceLock<AminoAcidTable> = OnceLock::new(
 
 pub fn proteinogenic_amino_acid_table() -> &'static AminoAcidTable {
     PROTEINOGENIC_AMINO_ACID_TABLE.get_or_init(|| {
-        _create_standard_amino_acid_table().unwrap()
+        _create_proteinogenic_amino_acid_table().unwrap()
     })
 }
 
@@ -497,6 +577,8 @@ fn _create_proteinogenic_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 0.0,
                 pka3: 0.0,
                 pi: 0.0,
+                hydrophobicity: -0.85,
+                hydrophobicity_n_term: 7.0,
                 codons: vec![]
             },
             AminoAcidDefinition {
@@ -511,6 +593,8 @@ fn _create_proteinogenic_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 0.0,
                 pka3: 0.0,
                 pi: 0.0,
+                hydrophobicity: 9.0,
+                hydrophobicity_n_term: -8.5,
                 codons: vec![]
             },
             AminoAcidDefinition {
@@ -525,6 +609,8 @@ fn _create_proteinogenic_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 0.0,
                 pka3: 0.0,
                 pi: 0.0,
+                hydrophobicity: -1.9,
+                hydrophobicity_n_term: 4.6,
                 codons: svec!["UAG"]
             },
             AminoAcidDefinition {
@@ -539,6 +625,8 @@ fn _create_proteinogenic_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 0.0,
                 pka3: 5.73,
                 pi: 5.47,
+                hydrophobicity: -0.8,
+                hydrophobicity_n_term: 4.0,
                 codons: svec!["UGA"]
             },
             AminoAcidDefinition {
@@ -553,6 +641,8 @@ fn _create_proteinogenic_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 0.0,
                 pka3: 0.0,
                 pi: 0.0,
+                hydrophobicity: 0.0,
+                hydrophobicity_n_term: 0.0,
                 codons: vec![]
             },
             AminoAcidDefinition {
@@ -567,6 +657,8 @@ fn _create_proteinogenic_amino_acid_table() -> Result<AminoAcidTable> {
                 pka2: 0.0,
                 pka3: 0.0,
                 pi: 0.0,
+                hydrophobicity: -0.45,
+                hydrophobicity_n_term: 4.0,
                 codons: vec![]
             },
         ];
@@ -0,0 +1,356 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::table::proteinogenic_amino_acid_table;
+
+/// Nucleotides in the order used by the NCBI genetic code tables (T is written as U)
+const NUCLEOTIDES: &[u8; 4] = b"UCAG";
+
+const STOP_SYMBOL: u8 = b'*';
+const UNKNOWN_AA: u8 = b'X';
+
+/// Name, codon reassignments relative to the standard code and start codons of a NCBI genetic code
+type GeneticCodeDefinition = (&'static str, Vec<(&'static str, u8)>, Vec<&'static str>);
+
+fn _ncbi_genetic_code_definition(id: u8) -> Result<GeneticCodeDefinition> {
+    let definition = match id {
+        1 => ("Standard", vec![], vec!["UUG", "CUG", "AUG"]),
+        2 => (
+            "Vertebrate Mitochondrial",
+            vec![("AGA", b'*'), ("AGG", b'*'), ("AUA", b'M'), ("UGA", b'W')],
+            vec!["AUU", "AUC", "AUA", "AUG", "GUG"],
+        ),
+        3 => (
+            "Yeast Mitochondrial",
+            vec![("AUA", b'M'), ("CUU", b'T'), ("CUC", b'T'), ("CUA", b'T'), ("CUG", b'T'), ("UGA", b'W')],
+            vec!["AUA", "AUG", "GUG"],
+        ),
+        4 => (
+            "Mold, Protozoan, and Coelenterate Mitochondrial and Mycoplasma/Spiroplasma",
+            vec![("UGA", b'W')],
+            vec!["UUA", "UUG", "CUG", "AUU", "AUC", "AUA", "AUG", "GUG"],
+        ),
+        5 => (
+            "Invertebrate Mitochondrial",
+            vec![("AGA", b'S'), ("AGG", b'S'), ("AUA", b'M'), ("UGA", b'W')],
+            vec!["UUG", "AUU", "AUC", "AUA", "AUG", "GUG"],
+        ),
+        6 => ("Ciliate, Dasycladacean and Hexamita Nuclear", vec![("UAA", b'Q'), ("UAG", b'Q')], vec!["AUG"]),
+        11 => ("Bacterial, Archaeal and Plant Plastid", vec![], vec!["UUG", "CUG", "AUU", "AUC", "AUA", "AUG", "GUG"]),
+        _ => bail!("unsupported NCBI genetic code table {}", id),
+    };
+
+    Ok(definition)
+}
+
+/// Mapping of the 64 codons to amino acids (stop codons are translated as '*')
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeneticCode {
+    /// NCBI translation table identifier
+    pub id: u8,
+    pub name: String,
+    amino_acids: Vec<u8>,
+    start_codons: Vec<bool>,
+}
+
+/// Translation of one reading frame. Frames 1 to 3 start at offsets 0 to 2 of the forward strand,
+/// frames -1 to -3 at offsets 0 to 2 of the reverse complement strand.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranslatedFrame {
+    pub frame: i8,
+    pub protein: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenReadingFrame {
+    pub frame: i8,
+    /// Nucleotide range on the forward strand (0-based, end excluded), including the stop codon
+    pub start: usize,
+    pub end: usize,
+    /// Translated sequence (the start codon is always translated as M, the stop codon is not included)
+    pub protein: String,
+}
+
+/// In-frame stop codon which may be recoded as selenocysteine (UGA) or pyrrolysine (UAG)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecodingCandidate {
+    pub frame: i8,
+    /// Index of the stop symbol in the translated frame
+    pub protein_index: usize,
+    pub codon: String,
+    /// U or O
+    pub residue: u8,
+    /// Number of sense codons read before the next stop codon
+    pub downstream_codons: usize,
+}
+
+impl GeneticCode {
+    /// Standard genetic code, built from the codons of the 20 standard amino acid definitions
+    pub fn standard() -> GeneticCode {
+        let mut amino_acids = vec![STOP_SYMBOL; 64];
+        for aa_def in proteinogenic_amino_acid_table().amino_acids.iter().filter(|aa_def| !matches!(aa_def.code1, b'U' | b'O')) {
+            for codon in aa_def.codons.iter() {
+                // Codons of the definitions are validated at construction
+                amino_acids[_codon_index(codon.as_bytes()).unwrap()] = aa_def.code1;
+            }
+        }
+
+        let mut start_codons = vec![false; 64];
+        for start_codon in ["UUG", "CUG", "AUG"] {
+            start_codons[_codon_index(start_codon.as_bytes()).unwrap()] = true;
+        }
+
+        GeneticCode { id: 1, name: "Standard".to_string(), amino_acids, start_codons }
+    }
+
+    pub fn from_ncbi_id(id: u8) -> Result<GeneticCode> {
+        let (name, reassignments, start_codons) = _ncbi_genetic_code_definition(id)?;
+
+        let mut genetic_code = GeneticCode::standard();
+        genetic_code.id = id;
+        genetic_code.name = name.to_string();
+        for (codon, aa) in reassignments {
+            genetic_code.amino_acids[_codon_index(codon.as_bytes()).unwrap()] = aa;
+        }
+        genetic_code.start_codons = vec![false; 64];
+        for start_codon in start_codons {
+            genetic_code.start_codons[_codon_index(start_codon.as_bytes()).unwrap()] = true;
+        }
+
+        Ok(genetic_code)
+    }
+
+    /// Amino acids of the 64 codons as a NCBI formatted string (codons sorted in TCAG order)
+    pub fn to_ncbi_string(&self) -> String {
+        String::from_utf8_lossy(&self.amino_acids).into_owned()
+    }
+
+    /// Returns X for codons containing ambiguous nucleotides
+    pub fn translate_codon(&self, codon: &[u8]) -> u8 {
+        _codon_index(codon).map(|idx| self.amino_acids[idx]).unwrap_or(UNKNOWN_AA)
+    }
+
+    pub fn is_start_codon(&self, codon: &[u8]) -> bool {
+        _codon_index(codon).is_some_and(|idx| self.start_codons[idx])
+    }
+
+    pub fn is_stop_codon(&self, codon: &[u8]) -> bool {
+        self.translate_codon(codon) == STOP_SYMBOL
+    }
+
+    /// Translate a DNA or RNA sequence from its first nucleotide (incomplete trailing codons are ignored)
+    pub fn translate(&self, nucleotides: &str) -> Result<String> {
+        self.translate_frame(nucleotides, 1)
+    }
+
+    pub fn translate_frame(&self, nucleotides: &str, frame: i8) -> Result<String> {
+        let strand = _frame_strand(&normalize_nucleotides(nucleotides)?, frame)?;
+        Ok(self._translate_strand(&strand).into_iter().map(char::from).collect())
+    }
+
+    /// Translation of the three forward frames, or of the six frames if `include_reverse` is true
+    pub fn translate_frames(&self, nucleotides: &str, include_reverse: bool) -> Result<Vec<TranslatedFrame>> {
+        _frames(include_reverse).map(|frame| {
+            Ok(TranslatedFrame { frame, protein: self.translate_frame(nucleotides, frame)? })
+        }).collect()
+    }
+
+    /// Find the ORFs starting with a start codon and ending with a stop codon, with at least `min_length` amino acids.
+    /// Only the longest ORF is reported when several start codons share the same stop codon.
+    pub fn find_orfs(&self, nucleotides: &str, min_length: usize, include_reverse: bool) -> Result<Vec<OpenReadingFrame>> {
+        let forward_strand = normalize_nucleotides(nucleotides)?;
+        let n_nucleotides = forward_strand.len();
+
+        let mut orfs = Vec::new();
+        for frame in _frames(include_reverse) {
+            let strand = _frame_strand(&forward_strand, frame)?;
+            let offset = (frame.unsigned_abs() - 1) as usize;
+
+            let mut orf_start: Option<usize> = None;
+            for (codon_idx, codon) in strand.chunks_exact(3).enumerate() {
+                match orf_start {
+                    None if self.is_start_codon(codon) => orf_start = Some(codon_idx),
+                    Some(start_idx) if self.is_stop_codon(codon) => {
+                        if codon_idx - start_idx >= min_length {
+                            let mut protein = self._translate_strand(&strand[start_idx * 3..codon_idx * 3]);
+                            protein[0] = b'M';
+
+                            // Coordinates on the frame strand, converted to the forward strand
+                            let (start, end) = (offset + start_idx * 3, offset + (codon_idx + 1) * 3);
+                            let (start, end) = if frame > 0 { (start, end) } else { (n_nucleotides - end, n_nucleotides - start) };
+
+                            orfs.push(OpenReadingFrame { frame, start, end, protein: protein.into_iter().map(char::from).collect() });
+                        }
+                        orf_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(orfs)
+    }
+
+    /// Possible codons (RNA) of each residue of a peptide.
+    /// Selenocysteine and pyrrolysine are mapped to the stop codons defined for them in the amino acid table.
+    pub fn reverse_translate(&self, peptide: &str) -> Result<Vec<Vec<String>>> {
+        let aa_table = proteinogenic_amino_acid_table();
+
+        peptide.bytes().map(|residue| {
+            let residue = residue.to_ascii_uppercase();
+            let codons: Vec<String> = if matches!(residue, b'U' | b'O') {
+                aa_table.aa_by_code1.get(&residue).map(|aa_def| aa_def.codons.clone()).unwrap_or_default()
+            } else {
+                (0..64).filter(|&idx| self.amino_acids[idx] == residue).map(_codon_from_index).collect()
+            };
+
+            if codons.is_empty() {
+                bail!("no codon encodes amino acid '{}' in the genetic code '{}'", residue as char, self.name)
+            }
+            Ok(codons)
+        }).collect()
+    }
+
+    /// Number of distinct nucleotide sequences encoding a peptide
+    pub fn count_encoding_sequences(&self, peptide: &str) -> Result<u128> {
+        self.reverse_translate(peptide)?.iter().try_fold(1u128, |count, codons| {
+            count.checked_mul(codons.len() as u128)
+                .ok_or_else(|| anyhow!("too many nucleotide sequences encoding the peptide to be counted"))
+        })
+    }
+
+    /// Find the in-frame UGA (selenocysteine) and UAG (pyrrolysine) stop codons followed by at least
+    /// `min_downstream_codons` sense codons, which suggests a translational read-through
+    pub fn find_recoding_candidates(&self, nucleotides: &str, frame: i8, min_downstream_codons: usize) -> Result<Vec<RecodingCandidate>> {
+        let aa_table = proteinogenic_amino_acid_table();
+        let recoded_codons: Vec<(u8, String)> = [b'U', b'O'].iter()
+            .filter_map(|residue| aa_table.aa_by_code1.get(residue))
+            .flat_map(|aa_def| aa_def.codons.iter().map(|codon| (aa_def.code1, codon.clone())))
+            .collect();
+
+        let strand = _frame_strand(&normalize_nucleotides(nucleotides)?, frame)?;
+        let protein = self._translate_strand(&strand);
+
+        let mut candidates = Vec::new();
+        for (protein_index, codon) in strand.chunks_exact(3).enumerate() {
+            if protein[protein_index] != STOP_SYMBOL {
+                continue
+            }
+            let Some((residue, codon)) = recoded_codons.iter().find(|(_, recoded_codon)| recoded_codon.as_bytes() == codon) else { continue };
+
+            let downstream_codons = protein[protein_index + 1..].iter().take_while(|aa| **aa != STOP_SYMBOL).count();
+            if downstream_codons >= min_downstream_codons {
+                candidates.push(RecodingCandidate { frame, protein_index, codon: codon.clone(), residue: *residue, downstream_codons });
+            }
+        }
+
+        Ok(candidates)
+    }
+
+    fn _translate_strand(&self, strand: &[u8]) -> Vec<u8> {
+        strand.chunks_exact(3).map(|codon| self.translate_codon(codon)).collect()
+    }
+}
+
+impl Default for GeneticCode {
+    fn default() -> Self {
+        GeneticCode::standard()
+    }
+}
+
+/// Convert a DNA or RNA sequence to upper case RNA (ambiguous nucleotides are converted to N, whitespaces are removed)
+pub fn normalize_nucleotides(nucleotides: &str) -> Result<Vec<u8>> {
+    nucleotides.bytes().filter(|n| !n.is_ascii_whitespace()).map(|n| {
+        match n.to_ascii_uppercase() {
+            b'T' | b'U' => Ok(b'U'),
+            b'A' => Ok(b'A'),
+            b'C' => Ok(b'C'),
+            b'G' => Ok(b'G'),
+            b'N' | b'R' | b'Y' | b'S' | b'W' | b'K' | b'M' | b'B' | b'D' | b'H' | b'V' => Ok(b'N'),
+            _ => bail!("invalid nucleotide '{}'", n as char),
+        }
+    }).collect()
+}
+
+/// Reverse complement of a DNA or RNA sequence, returned as RNA
+pub fn reverse_complement(nucleotides: &str) -> Result<String> {
+    Ok(_reverse_complement(&normalize_nucleotides(nucleotides)?).into_iter().map(char::from).collect())
+}
+
+fn _reverse_complement(rna: &[u8]) -> Vec<u8> {
+    rna.iter().rev().map(|n| match n {
+        b'A' => b'U',
+        b'U' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        _ => b'N',
+    }).collect()
+}
+
+fn _frames(include_reverse: bool) -> impl Iterator<Item = i8> {
+    [1, 2, 3, -1, -2, -3].into_iter().take(if include_reverse { 6 } else { 3 })
+}
+
+fn _frame_strand(rna: &[u8], frame: i8) -> Result<Vec<u8>> {
+    if frame == 0 || frame.abs() > 3 {
+        bail!("frame must be in the [-3, -1] or [1, 3] range")
+    }
+
+    let strand = if frame > 0 { rna.to_vec() } else { _reverse_complement(rna) };
+    let offset = ((frame.unsigned_abs() - 1) as usize).min(strand.len());
+
+    Ok(strand[offset..].to_vec())
+}
+
+fn _codon_index(codon: &[u8]) -> Option<usize> {
+    if codon.len() != 3 {
+        return None
+    }
+
+    codon.iter().try_fold(0, |idx, n| {
+        let n = match n.to_ascii_uppercase() {
+            b'T' => b'U',
+            other => other,
+        };
+        NUCLEOTIDES.iter().position(|nucleotide| *nucleotide == n).map(|n_idx| idx * 4 + n_idx)
+    })
+}
+
+fn _codon_from_index(idx: usize) -> String {
+    [idx / 16, (idx / 4) % 4, idx % 4].iter().map(|n_idx| NUCLEOTIDES[*n_idx] as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_nucleotide_sequences() -> Result<()> {
+        let standard_code = GeneticCode::standard();
+        assert_eq!(standard_code.to_ncbi_string(), "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG");
+        assert_eq!(GeneticCode::from_ncbi_id(2)?.to_ncbi_string(), "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG");
+
+        let dna = "ATGGCCATTGTAATGGGCCGCTGAAAGGGTGCCCGATAG";
+        assert_eq!(standard_code.translate(dna)?, "MAIVMGR*KGAR*");
+        assert_eq!(standard_code.translate_frame(dna, -1)?, "LSGTLSAAHYNGH");
+        assert_eq!(reverse_complement("ATGC")?, "GCAU");
+        assert_eq!(standard_code.translate_frames(dna, true)?.len(), 6);
+
+        let orfs = standard_code.find_orfs(&format!("CC{dna}"), 5, true)?;
+        assert_eq!(orfs, vec![OpenReadingFrame { frame: 3, start: 2, end: 26, protein: "MAIVMGR".to_string() }]);
+
+        let codons = standard_code.reverse_translate("MWU")?;
+        assert_eq!(codons, vec![vec!["AUG".to_string()], vec!["UGG".to_string()], vec!["UGA".to_string()]]);
+        assert_eq!(standard_code.count_encoding_sequences("LK")?, 12);
+        // Six-fold degenerate residues overflow the count of a long peptide
+        assert_eq!(standard_code.count_encoding_sequences(&"L".repeat(49))?, 6u128.pow(49));
+        assert!(standard_code.count_encoding_sequences(&"L".repeat(50)).is_err());
+
+        // Selenoprotein-like read-through of the UGA codon
+        let candidates = standard_code.find_recoding_candidates(dna, 1, 3)?;
+        assert_eq!(candidates.len(), 1);
+        assert_eq!((candidates[0].protein_index, candidates[0].residue, candidates[0].downstream_codons), (7, b'U', 4));
+
+        Ok(())
+    }
+}
@@ -11,6 +11,7 @@ use anyhow::*;
 use crate::chemistry::composition::{ElementalComposition, ElementCount};
 use crate::chemistry::element::Element;
 use crate::chemistry::glycan::{GlycanComposition, MonoSaccharide};
+use crate::chemistry::table::biomolecule_atom_table;
 
 enum Brick {
     Element(Element),
@@ -18,10 +19,13 @@ enum Brick {
     MonoSaccharide(MonoSaccharide),
 }
 
+/// Parse a Unimod composition such as `H(-2) C(-6) 13C(6) N(-2) 15N(2) O`
+/// (isotopes are specified by prefixing the element with its mass number)
 pub fn parse_unimod_composition(composition: &str) -> Result<(ElementalComposition, GlycanComposition)> {
     let mut elem_comp = ElementalComposition::new(&[]);
     let mut monosaccharides: GlycanComposition = Vec::new();
 
+    let mut last_mass_number = String::new();
     let mut last_name = String::new();
     let mut last_number = String::new();
     let mut minus = 1;
@@ -32,40 +36,84 @@ pub fn parse_unimod_composition(composition: &str) -> Result<(ElementalCompositi
             b')' => {
                 let parsed_number = last_number.parse::<i16>()?;
                 let num = parsed_number * minus;
-                match parse_unimod_composition_brick(last_name.as_str())? {
-                    Brick::Formula(f) => elem_comp += &(f * num),
-                    Brick::Element(e) => elem_comp.add(ElementCount::new_monoisotope(e, num as f32)),
-                    Brick::MonoSaccharide(m) => monosaccharides.push((m, num)),
-                }
+                _add_unimod_composition_brick(&mut elem_comp, &mut monosaccharides, &last_mass_number, &last_name, num)?;
+                last_mass_number.clear();
                 last_name.clear();
                 last_number.clear();
                 minus = 1;
             }
             b' ' => {
                 if !last_name.is_empty() {
-                    match parse_unimod_composition_brick(last_name.as_str())? {
-                        Brick::Formula(f) => elem_comp += &f,
-                        Brick::Element(e) => elem_comp.add(ElementCount::new_monoisotope(e, 1f32)),
-                        Brick::MonoSaccharide(m) => monosaccharides.push((m, 1)),
-                    }
+                    _add_unimod_composition_brick(&mut elem_comp, &mut monosaccharides, &last_mass_number, &last_name, 1)?;
+                    last_mass_number.clear();
                     last_name.clear();
                 }
             }
+            n if n.is_ascii_digit() && last_name.is_empty() => last_mass_number.push(n as char),
             n if n.is_ascii_digit() => last_number.push(n as char),
             n if n.is_ascii_alphabetic() => last_name.push(n as char),
             _ => panic!("Weird formula composition: {composition}"),
         }
     }
     if !last_name.is_empty() {
-        match parse_unimod_composition_brick(last_name.as_str())? {
-            Brick::Formula(f) => elem_comp += &f,
-            Brick::Element(e) => elem_comp.add(ElementCount::new_monoisotope(e, 1f32)),
-            Brick::MonoSaccharide(m) => monosaccharides.push((m, 1)),
-        }
+        _add_unimod_composition_brick(&mut elem_comp, &mut monosaccharides, &last_mass_number, &last_name, 1)?;
     }
     Ok((elem_comp, monosaccharides))
 }
 
+/// Format a composition using the Unimod notation (inverse of `parse_unimod_composition`)
+pub fn format_unimod_composition(composition: &ElementalComposition) -> Result<String> {
+    if composition.additional_mass != 0.0 {
+        bail!("a composition having an additional mass can't be expressed using the Unimod notation")
+    }
+
+    let atom_table = biomolecule_atom_table();
+    let bricks = composition.element_counts.iter().map(|elc| {
+        if elc.count.fract() != 0.0 {
+            bail!("can't format a non-integer count of {}", elc.element)
+        }
+
+        let mass_number = if elc.isotope_index == 0 {
+            String::new()
+        } else {
+            let atom = atom_table.atom_by_element.get(&elc.element).ok_or_else(|| anyhow!("unknown element {}", elc.element))?;
+            let isotope = atom.isotopes.get(elc.isotope_index as usize).ok_or_else(|| anyhow!("wrong isotope index {}", elc.isotope_index))?;
+            isotope.mass_number.to_string()
+        };
+
+        let count = elc.count as i32;
+        Ok(if count == 1 { format!("{}{}", mass_number, elc.element) } else { format!("{}{}({})", mass_number, elc.element, count) })
+    }).collect::<Result<Vec<String>>>()?;
+
+    Ok(bricks.join(" "))
+}
+
+fn _add_unimod_composition_brick(
+    elem_comp: &mut ElementalComposition,
+    monosaccharides: &mut GlycanComposition,
+    mass_number: &str,
+    name: &str,
+    num: i16,
+) -> Result<()> {
+    let brick = parse_unimod_composition_brick(name)?;
+
+    if !mass_number.is_empty() {
+        let Brick::Element(e) = brick else { bail!("isotope mass number can only be specified for elements: `{}{}`", mass_number, name) };
+        let mass_number = mass_number.parse::<u16>()?;
+        let isotope_index = biomolecule_atom_table().isotope_index(e, mass_number)
+            .ok_or_else(|| anyhow!("unknown isotope `{}{}`", mass_number, name))?;
+        elem_comp.add(ElementCount::new(e, isotope_index, num as f32));
+        return Ok(())
+    }
+
+    match brick {
+        Brick::Formula(f) => *elem_comp += &(f * num),
+        Brick::Element(e) => elem_comp.add(ElementCount::new_monoisotope(e, num as f32)),
+        Brick::MonoSaccharide(m) => monosaccharides.push((m, num)),
+    }
+
+    Ok(())
+}
 
 fn parse_unimod_composition_brick(name: &str) -> Result<Brick> {
     match name.to_lowercase().as_str() {
@@ -1 +1,3 @@
 pub mod error;
+pub mod regression;
+pub mod stats;
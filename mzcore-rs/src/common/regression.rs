@@ -0,0 +1,336 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::stats::median;
+
+/// Maximum number of points where the local LOWESS regressions are evaluated (other values are interpolated)
+const MAX_LOWESS_KNOTS: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RegressionMethod {
+    /// Ordinary least squares
+    Linear,
+    /// Robust locally weighted regression (Cleveland, 1979).
+    /// `span` is the fraction of the points used for each local fit.
+    Lowess { span: f64, robustness_iterations: usize },
+    /// Robust piecewise-linear fit through the medians of equally populated segments
+    PiecewiseLinear { n_segments: usize },
+    /// Natural cubic spline through the medians of equally populated segments
+    CubicSpline { n_knots: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RegressionModel {
+    Linear { slope: f64, intercept: f64 },
+    /// Linear interpolation between (x, y) knots sorted by x, extrapolated using the slopes of the terminal segments
+    Interpolation { knots: Vec<(f64, f64)> },
+    /// Natural cubic spline defined by its (x, y) knots sorted by x and the second derivatives at these knots,
+    /// extrapolated linearly
+    CubicSpline { knots: Vec<(f64, f64)>, second_derivatives: Vec<f64> },
+}
+
+impl RegressionModel {
+
+    pub fn fit(x: &[f64], y: &[f64], method: RegressionMethod) -> Result<RegressionModel> {
+        if x.len() != y.len() {
+            bail!("x and y must have the same length")
+        }
+        if x.iter().chain(y).any(|v| !v.is_finite()) {
+            bail!("x and y must contain finite values only")
+        }
+        if x.len() < 2 {
+            bail!("at least two points are required to fit a regression model")
+        }
+
+        let model = match method {
+            RegressionMethod::Linear => {
+                let weights = vec![1.0; x.len()];
+                let (slope, intercept) = _weighted_linear_fit(x, y, &weights)
+                    .ok_or_else(|| anyhow!("can't fit a linear model on constant x values"))?;
+                RegressionModel::Linear { slope, intercept }
+            }
+            RegressionMethod::Lowess { span, robustness_iterations } => {
+                if span <= 0.0 || span > 1.0 { bail!("LOWESS span must be in the (0, 1] range") }
+                RegressionModel::Interpolation { knots: _fit_lowess(x, y, span, robustness_iterations) }
+            }
+            RegressionMethod::PiecewiseLinear { n_segments } => {
+                if n_segments == 0 { bail!("the number of segments must be strictly positive") }
+                RegressionModel::Interpolation { knots: _fit_piecewise_linear(x, y, n_segments) }
+            }
+            RegressionMethod::CubicSpline { n_knots } => {
+                if n_knots < 2 { bail!("at least two knots are required to fit a cubic spline") }
+                let knots = _fit_piecewise_linear(x, y, n_knots);
+                let second_derivatives = _natural_spline_second_derivatives(&knots);
+                RegressionModel::CubicSpline { knots, second_derivatives }
+            }
+        };
+
+        Ok(model)
+    }
+
+    pub fn predict(&self, x: f64) -> f64 {
+        match self {
+            RegressionModel::Linear { slope, intercept } => slope * x + intercept,
+            RegressionModel::Interpolation { knots } => _interpolate(knots, x),
+            RegressionModel::CubicSpline { knots, second_derivatives } => _evaluate_spline(knots, second_derivatives, x),
+        }
+    }
+
+    /// Observed minus predicted values
+    pub fn residuals(&self, x: &[f64], y: &[f64]) -> Vec<f64> {
+        x.iter().zip(y).map(|(&xi, &yi)| yi - self.predict(xi)).collect()
+    }
+}
+
+/// Weighted least squares fit, returned as (slope, intercept). None if all x values are equal.
+fn _weighted_linear_fit(x: &[f64], y: &[f64], weights: &[f64]) -> Option<(f64, f64)> {
+    let sum_w: f64 = weights.iter().sum();
+    if sum_w <= 0.0 {
+        return None
+    }
+
+    let mean_x = x.iter().zip(weights).map(|(xi, w)| xi * w).sum::<f64>() / sum_w;
+    let mean_y = y.iter().zip(weights).map(|(yi, w)| yi * w).sum::<f64>() / sum_w;
+
+    let mut sxx = 0.0;
+    let mut sxy = 0.0;
+    for ((xi, yi), w) in x.iter().zip(y).zip(weights) {
+        sxx += w * (xi - mean_x).powi(2);
+        sxy += w * (xi - mean_x) * (yi - mean_y);
+    }
+
+    if sxx <= f64::EPSILON * sum_w * mean_x.abs().max(1.0) {
+        return None
+    }
+
+    let slope = sxy / sxx;
+    Some((slope, mean_y - slope * mean_x))
+}
+
+fn _sort_points(x: &[f64], y: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let mut order: Vec<usize> = (0..x.len()).collect();
+    order.sort_by(|&a, &b| x[a].total_cmp(&x[b]));
+    (order.iter().map(|&i| x[i]).collect(), order.iter().map(|&i| y[i]).collect())
+}
+
+fn _fit_lowess(x: &[f64], y: &[f64], span: f64, robustness_iterations: usize) -> Vec<(f64, f64)> {
+    let (xs, ys) = _sort_points(x, y);
+    let n = xs.len();
+    let n_neighbors = ((span * n as f64).ceil() as usize).clamp(2, n);
+
+    let mut eval_x: Vec<f64> = if n <= MAX_LOWESS_KNOTS {
+        xs.clone()
+    } else {
+        (0..MAX_LOWESS_KNOTS).map(|i| xs[i * (n - 1) / (MAX_LOWESS_KNOTS - 1)]).collect()
+    };
+    eval_x.dedup();
+
+    let mut robustness_weights = vec![1.0; n];
+    let mut knots = Vec::new();
+    for iteration in 0..=robustness_iterations {
+        knots = eval_x.iter()
+            .map(|&x0| (x0, _lowess_local_fit(&xs, &ys, &robustness_weights, x0, n_neighbors)))
+            .collect();
+
+        if iteration == robustness_iterations {
+            break
+        }
+
+        // Bisquare robustness weights computed from the residuals
+        let abs_residuals: Vec<f64> = xs.iter().zip(&ys).map(|(&xi, &yi)| (yi - _interpolate(&knots, xi)).abs()).collect();
+        let scale = 6.0 * median(&abs_residuals).unwrap_or(0.0);
+        if scale <= 0.0 {
+            break
+        }
+
+        robustness_weights = abs_residuals.iter().map(|r| {
+            let u = r / scale;
+            if u < 1.0 { (1.0 - u * u).powi(2) } else { 0.0 }
+        }).collect();
+    }
+
+    knots
+}
+
+fn _lowess_local_fit(xs: &[f64], ys: &[f64], robustness_weights: &[f64], x0: f64, n_neighbors: usize) -> f64 {
+    let n = xs.len();
+
+    // Expand a window around x0 until it contains the n nearest neighbors
+    let pos = xs.partition_point(|&xi| xi < x0);
+    let (mut lo, mut hi) = (pos, pos);
+    while hi - lo < n_neighbors {
+        if lo == 0 {
+            hi += 1
+        } else if hi == n || x0 - xs[lo - 1] <= xs[hi] - x0 {
+            lo -= 1
+        } else {
+            hi += 1
+        }
+    }
+
+    let max_dist = (x0 - xs[lo]).max(xs[hi - 1] - x0) * 1.000_001;
+    let weights: Vec<f64> = (lo..hi).map(|i| {
+        let tricube = if max_dist > 0.0 { (1.0 - ((xs[i] - x0).abs() / max_dist).powi(3)).powi(3) } else { 1.0 };
+        tricube * robustness_weights[i]
+    }).collect();
+
+    match _weighted_linear_fit(&xs[lo..hi], &ys[lo..hi], &weights) {
+        Some((slope, intercept)) => slope * x0 + intercept,
+        None => {
+            // Degenerated neighborhood: fallback to the weighted mean
+            let sum_w: f64 = weights.iter().sum();
+            if sum_w > 0.0 {
+                ys[lo..hi].iter().zip(&weights).map(|(yi, w)| yi * w).sum::<f64>() / sum_w
+            } else {
+                ys[lo..hi].iter().sum::<f64>() / (hi - lo) as f64
+            }
+        }
+    }
+}
+
+fn _fit_piecewise_linear(x: &[f64], y: &[f64], n_segments: usize) -> Vec<(f64, f64)> {
+    let (xs, ys) = _sort_points(x, y);
+    let n = xs.len();
+    let n_segments = n_segments.min(n);
+
+    let mut knots: Vec<(f64, f64)> = Vec::with_capacity(n_segments);
+    for segment_idx in 0..n_segments {
+        let (start, end) = (segment_idx * n / n_segments, (segment_idx + 1) * n / n_segments);
+        let knot_x = median(&xs[start..end]).unwrap(); // safe because segments are not empty
+        let knot_y = median(&ys[start..end]).unwrap();
+
+        match knots.last_mut() {
+            Some(last_knot) if last_knot.0 == knot_x => last_knot.1 = (last_knot.1 + knot_y) / 2.0,
+            _ => knots.push((knot_x, knot_y)),
+        }
+    }
+
+    knots
+}
+
+fn _interpolate(knots: &[(f64, f64)], x: f64) -> f64 {
+    match knots.len() {
+        0 => return f64::NAN,
+        1 => return knots[0].1,
+        _ => {}
+    }
+
+    // Index of the segment used for interpolation (terminal segments are used for extrapolation)
+    let upper_idx = knots.partition_point(|knot| knot.0 < x).clamp(1, knots.len() - 1);
+    let (x1, y1) = knots[upper_idx - 1];
+    let (x2, y2) = knots[upper_idx];
+
+    y1 + (x - x1) * (y2 - y1) / (x2 - x1)
+}
+
+fn _natural_spline_second_derivatives(knots: &[(f64, f64)]) -> Vec<f64> {
+    let n = knots.len();
+    if n < 3 {
+        return vec![0.0; n]
+    }
+
+    // Tridiagonal system of the continuity equations of the first derivative at the inner knots
+    let h: Vec<f64> = knots.windows(2).map(|w| w[1].0 - w[0].0).collect();
+    let n_inner = n - 2;
+    let mut matrix = vec![vec![0.0; n_inner]; n_inner];
+    let mut rhs = vec![0.0; n_inner];
+    for (row, (matrix_row, rhs_value)) in matrix.iter_mut().zip(rhs.iter_mut()).enumerate() {
+        let i = row + 1;
+        if row > 0 { matrix_row[row - 1] = h[i - 1] }
+        matrix_row[row] = 2.0 * (h[i - 1] + h[i]);
+        if row + 1 < n_inner { matrix_row[row + 1] = h[i] }
+        *rhs_value = 6.0 * ((knots[i + 1].1 - knots[i].1) / h[i] - (knots[i].1 - knots[i - 1].1) / h[i - 1]);
+    }
+
+    let mut second_derivatives = vec![0.0];
+    second_derivatives.extend(solve_linear_system(matrix, rhs));
+    second_derivatives.push(0.0);
+
+    second_derivatives
+}
+
+fn _evaluate_spline(knots: &[(f64, f64)], second_derivatives: &[f64], x: f64) -> f64 {
+    let n = knots.len();
+    if n < 2 {
+        return _interpolate(knots, x)
+    }
+
+    let (first, last) = (knots[0], knots[n - 1]);
+    if x < first.0 {
+        let h = knots[1].0 - first.0;
+        let slope = (knots[1].1 - first.1) / h - h * (2.0 * second_derivatives[0] + second_derivatives[1]) / 6.0;
+        return first.1 + (x - first.0) * slope
+    }
+    if x > last.0 {
+        let h = last.0 - knots[n - 2].0;
+        let slope = (last.1 - knots[n - 2].1) / h + h * (second_derivatives[n - 2] + 2.0 * second_derivatives[n - 1]) / 6.0;
+        return last.1 + (x - last.0) * slope
+    }
+
+    let upper_idx = knots.partition_point(|knot| knot.0 < x).clamp(1, n - 1);
+    let ((x1, y1), (x2, y2)) = (knots[upper_idx - 1], knots[upper_idx]);
+    let (m1, m2) = (second_derivatives[upper_idx - 1], second_derivatives[upper_idx]);
+    let h = x2 - x1;
+    let (a, b) = ((x2 - x) / h, (x - x1) / h);
+
+    a * y1 + b * y2 + ((a.powi(3) - a) * m1 + (b.powi(3) - b) * m2) * h * h / 6.0
+}
+
+/// Gaussian elimination with partial pivoting (the matrix is assumed to be invertible)
+pub(crate) fn solve_linear_system(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Vec<f64> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot_row = (col..n).max_by(|&a, &b| matrix[a][col].abs().total_cmp(&matrix[b][col].abs())).unwrap();
+        matrix.swap(col, pivot_row);
+        rhs.swap(col, pivot_row);
+
+        for row in col + 1..n {
+            let factor = matrix[row][col] / matrix[col][col];
+            let (upper_rows, lower_rows) = matrix.split_at_mut(row);
+            for (value, pivot_value) in lower_rows[0][col..].iter_mut().zip(&upper_rows[col][col..]) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[col];
+        }
+    }
+
+    let mut solution = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+
+    solution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn robust_fits() -> Result<()> {
+        let x: Vec<f64> = (0..200).map(|i| i as f64).collect();
+        let mut y: Vec<f64> = x.iter().map(|xi| 2.0 * xi + 5.0).collect();
+        // Outliers
+        y[20] += 500.0;
+        y[120] -= 500.0;
+
+        let lowess = RegressionModel::fit(&x, &y, RegressionMethod::Lowess { span: 0.3, robustness_iterations: 3 })?;
+        assert!((lowess.predict(120.0) - 245.0).abs() < 0.5);
+        assert!((lowess.predict(250.0) - 505.0).abs() < 1.0);
+
+        let piecewise = RegressionModel::fit(&x, &y, RegressionMethod::PiecewiseLinear { n_segments: 10 })?;
+        assert!((piecewise.predict(100.0) - 205.0).abs() < 1.0);
+
+        let quadratic_y: Vec<f64> = x.iter().map(|xi| 0.01 * xi * xi).collect();
+        let spline = RegressionModel::fit(&x, &quadratic_y, RegressionMethod::CubicSpline { n_knots: 20 })?;
+        assert!((spline.predict(100.0) - 100.0).abs() < 1.0);
+
+        let linear = RegressionModel::fit(&x, &x.iter().map(|xi| 2.0 * xi + 5.0).collect::<Vec<f64>>(), RegressionMethod::Linear)?;
+        assert_eq!(linear, RegressionModel::Linear { slope: 2.0, intercept: 5.0 });
+
+        assert!(RegressionModel::fit(&[1.0], &[1.0], RegressionMethod::Linear).is_err());
+
+        Ok(())
+    }
+}
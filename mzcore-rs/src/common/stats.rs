@@ -0,0 +1,53 @@
+/// Median of a list of values (NaN values are ignored)
+pub fn median(values: &[f64]) -> Option<f64> {
+    quantile(values, 0.5)
+}
+
+/// Quantile of a list of values using linear interpolation between closest ranks (NaN values are ignored)
+pub fn quantile(values: &[f64], q: f64) -> Option<f64> {
+    let mut sorted_values: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+    if sorted_values.is_empty() {
+        return None
+    }
+    sorted_values.sort_by(|a, b| a.total_cmp(b));
+
+    Some(sorted_quantile(&sorted_values, q))
+}
+
+pub fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Sample standard deviation
+pub fn standard_deviation(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None
+    }
+    let mean = mean(values)?;
+    let sum_sq: f64 = values.iter().map(|v| (v - mean).powi(2)).sum();
+    Some((sum_sq / (values.len() - 1) as f64).sqrt())
+}
+
+/// Median absolute deviation (not scaled)
+pub fn median_absolute_deviation(values: &[f64]) -> Option<f64> {
+    let median = median(values)?;
+    let abs_deviations: Vec<f64> = values.iter().map(|v| (v - median).abs()).collect();
+    self::median(&abs_deviations)
+}
+
+/// Robust estimation of the standard deviation (MAD scaled to be consistent with a normal distribution)
+pub fn robust_standard_deviation(values: &[f64]) -> Option<f64> {
+    median_absolute_deviation(values).map(|mad| 1.4826 * mad)
+}
+
+pub(crate) fn sorted_quantile(sorted_values: &[f64], q: f64) -> f64 {
+    let q = q.clamp(0.0, 1.0);
+    let pos = q * (sorted_values.len() - 1) as f64;
+    let lo_idx = pos.floor() as usize;
+    let hi_idx = pos.ceil() as usize;
+    let frac = pos - lo_idx as f64;
+    sorted_values[lo_idx] + frac * (sorted_values[hi_idx] - sorted_values[lo_idx])
+}
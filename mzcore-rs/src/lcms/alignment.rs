@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::regression::{RegressionMethod, RegressionModel};
+use crate::common::stats::*;
+use crate::lcms::feature_finder::{match_features, Feature, FeatureMatchTarget};
+use crate::ms::utils::MassTolWindow;
+
+/// Retention times of the anchors (identified peptide ions or matched features) of a run, indexed by a key shared between runs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunRtAnchors {
+    pub run_name: String,
+    pub anchors: HashMap<String, f32>,
+}
+
+impl RunRtAnchors {
+    pub fn new(run_name: &str, anchors: HashMap<String, f32>) -> RunRtAnchors {
+        RunRtAnchors { run_name: run_name.to_string(), anchors }
+    }
+
+    /// RT pairs (this run, other run) of the anchors shared with another run, sorted by key
+    pub fn shared_rt_pairs(&self, other: &RunRtAnchors) -> Vec<(f32, f32)> {
+        let mut shared_keys: Vec<&String> = self.anchors.keys().filter(|key| other.anchors.contains_key(*key)).collect();
+        shared_keys.sort();
+        shared_keys.into_iter().map(|key| (self.anchors[key], other.anchors[key])).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RtAlignmentConfig {
+    pub method: RegressionMethod,
+    pub min_anchors: usize,
+    /// Anchors whose residual after a first fit exceeds this number of robust standard deviations are discarded
+    pub max_residual_std_devs: f64,
+}
+
+impl Default for RtAlignmentConfig {
+    fn default() -> Self {
+        RtAlignmentConfig {
+            method: RegressionMethod::Lowess { span: 0.3, robustness_iterations: 3 },
+            min_anchors: 10,
+            max_residual_std_devs: 4.0,
+        }
+    }
+}
+
+/// Summary statistics of the RT residuals of the anchors used for an alignment
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlignmentResidualStatistics {
+    pub n_anchors: usize,
+    pub n_outliers: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+    /// Standard deviation estimated from the median absolute deviation (robust to outliers)
+    pub robust_std_dev: f64,
+    pub max_abs: f64,
+}
+
+impl AlignmentResidualStatistics {
+    /// Returns None if less than two residuals are provided
+    pub fn compute(residuals: &[f64], n_outliers: usize) -> Option<AlignmentResidualStatistics> {
+        Some(AlignmentResidualStatistics {
+            n_anchors: residuals.len(),
+            n_outliers,
+            mean: mean(residuals)?,
+            median: median(residuals)?,
+            std_dev: standard_deviation(residuals)?,
+            robust_std_dev: robust_standard_deviation(residuals)?,
+            max_abs: residuals.iter().fold(0.0, |max, r| r.abs().max(max)),
+        })
+    }
+}
+
+/// Mapping between the retention times of a run and those of the reference run
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RtAlignment {
+    pub run_name: String,
+    pub reference_run_name: String,
+    /// Predicts the reference RT from the RT of this run
+    pub to_reference: RegressionModel,
+    /// Predicts the RT of this run from the reference RT
+    pub from_reference: RegressionModel,
+    /// Statistics of the residuals (reference RT minus predicted reference RT) of the inlier anchors
+    pub residual_statistics: AlignmentResidualStatistics,
+}
+
+impl RtAlignment {
+    /// Align a run on a reference run using their shared anchors
+    pub fn fit(run_anchors: &RunRtAnchors, reference_anchors: &RunRtAnchors, config: &RtAlignmentConfig) -> Result<RtAlignment> {
+        let rt_pairs = run_anchors.shared_rt_pairs(reference_anchors);
+        if rt_pairs.len() < config.min_anchors.max(2) {
+            bail!(
+                "not enough anchors shared by runs '{}' and '{}' ({} < {})",
+                run_anchors.run_name, reference_anchors.run_name, rt_pairs.len(), config.min_anchors.max(2)
+            )
+        }
+
+        let mut run_rts: Vec<f64> = rt_pairs.iter().map(|pair| pair.0 as f64).collect();
+        let mut reference_rts: Vec<f64> = rt_pairs.iter().map(|pair| pair.1 as f64).collect();
+        let mut to_reference = RegressionModel::fit(&run_rts, &reference_rts, config.method)?;
+
+        // Discard the outliers and refit
+        let residuals = to_reference.residuals(&run_rts, &reference_rts);
+        let robust_std_dev = robust_standard_deviation(&residuals).unwrap_or(0.0);
+        let mut n_outliers = 0;
+        if robust_std_dev > 0.0 {
+            let max_residual = config.max_residual_std_devs * robust_std_dev;
+            let is_inlier: Vec<bool> = residuals.iter().map(|r| r.abs() <= max_residual).collect();
+            n_outliers = is_inlier.iter().filter(|inlier| !**inlier).count();
+
+            if n_outliers > 0 && rt_pairs.len() - n_outliers >= 2 {
+                run_rts = run_rts.iter().zip(&is_inlier).filter(|(_, inlier)| **inlier).map(|(rt, _)| *rt).collect();
+                reference_rts = reference_rts.iter().zip(&is_inlier).filter(|(_, inlier)| **inlier).map(|(rt, _)| *rt).collect();
+                to_reference = RegressionModel::fit(&run_rts, &reference_rts, config.method)?;
+            } else {
+                n_outliers = 0;
+            }
+        }
+
+        let from_reference = RegressionModel::fit(&reference_rts, &run_rts, config.method)?;
+        let residual_statistics = AlignmentResidualStatistics::compute(&to_reference.residuals(&run_rts, &reference_rts), n_outliers)
+            .ok_or_else(|| anyhow!("can't compute the statistics of the alignment residuals"))?;
+
+        Ok(RtAlignment {
+            run_name: run_anchors.run_name.clone(),
+            reference_run_name: reference_anchors.run_name.clone(),
+            to_reference,
+            from_reference,
+            residual_statistics,
+        })
+    }
+
+    /// Alignment of the reference run on itself
+    pub fn identity(reference_run_name: &str) -> RtAlignment {
+        let identity_model = RegressionModel::Linear { slope: 1.0, intercept: 0.0 };
+        RtAlignment {
+            run_name: reference_run_name.to_string(),
+            reference_run_name: reference_run_name.to_string(),
+            to_reference: identity_model.clone(),
+            from_reference: identity_model,
+            residual_statistics: AlignmentResidualStatistics {
+                n_anchors: 0, n_outliers: 0, mean: 0.0, median: 0.0, std_dev: 0.0, robust_std_dev: 0.0, max_abs: 0.0
+            },
+        }
+    }
+
+    pub fn to_reference_rt(&self, rt: f32) -> f32 {
+        self.to_reference.predict(rt as f64) as f32
+    }
+
+    pub fn from_reference_rt(&self, reference_rt: f32) -> f32 {
+        self.from_reference.predict(reference_rt as f64) as f32
+    }
+
+    /// Map a RT of this run to the RT scale of another run aligned on the same reference
+    pub fn map_rt(&self, rt: f32, other: &RtAlignment) -> f32 {
+        other.from_reference_rt(self.to_reference_rt(rt))
+    }
+}
+
+/// Index of the run sharing the largest number of anchors with the other runs
+pub fn select_reference_run(runs: &[RunRtAnchors]) -> Option<usize> {
+    (0..runs.len()).max_by_key(|&run_idx| {
+        let n_shared: usize = runs.iter().enumerate()
+            .filter(|(other_idx, _)| *other_idx != run_idx)
+            .map(|(_, other)| runs[run_idx].anchors.keys().filter(|key| other.anchors.contains_key(*key)).count())
+            .sum();
+        // Ties are resolved in favor of the first run
+        (n_shared, std::cmp::Reverse(run_idx))
+    })
+}
+
+/// Align all the runs on the reference run (which gets an identity alignment)
+pub fn align_runs(runs: &[RunRtAnchors], reference_index: usize, config: &RtAlignmentConfig) -> Result<Vec<RtAlignment>> {
+    let reference = runs.get(reference_index).ok_or_else(|| anyhow!("invalid reference run index {}", reference_index))?;
+
+    runs.iter().enumerate().map(|(run_idx, run)| {
+        if run_idx == reference_index {
+            Ok(RtAlignment::identity(&reference.run_name))
+        } else {
+            RtAlignment::fit(run, reference, config)
+        }
+    }).collect()
+}
+
+/// Identification of a donor run to be transferred to an acceptor run
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IdentificationToTransfer {
+    pub key: String,
+    pub mono_mass: f64,
+    pub charge: i8,
+    /// RT in the donor run
+    pub rt: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IdentificationTransferConfig {
+    pub mass_tolerance: MassTolWindow,
+    /// The RT tolerance spans this number of robust standard deviations of the combined alignment residuals
+    pub n_rt_std_devs: f64,
+    pub min_rt_tolerance: f32,
+    pub max_rt_tolerance: f32,
+}
+
+impl Default for IdentificationTransferConfig {
+    fn default() -> Self {
+        IdentificationTransferConfig {
+            mass_tolerance: MassTolWindow::ppm(-10.0, 10.0),
+            n_rt_std_devs: 3.0,
+            min_rt_tolerance: 0.1,
+            max_rt_tolerance: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferredIdentification {
+    pub identification_index: usize,
+    pub feature_index: usize,
+    /// RT predicted in the acceptor run
+    pub predicted_rt: f32,
+    pub rt_tolerance: f32,
+    pub mass_error_ppm: f64,
+    /// Predicted RT minus feature apex RT
+    pub rt_error: f32,
+}
+
+/// RT tolerance used to transfer identifications between two runs aligned on the same reference
+pub fn transfer_rt_tolerance(donor: &RtAlignment, acceptor: &RtAlignment, config: &IdentificationTransferConfig) -> f32 {
+    let combined_std_dev = (donor.residual_statistics.robust_std_dev.powi(2) + acceptor.residual_statistics.robust_std_dev.powi(2)).sqrt();
+    ((config.n_rt_std_devs * combined_std_dev) as f32).clamp(config.min_rt_tolerance, config.max_rt_tolerance.max(config.min_rt_tolerance))
+}
+
+/// Transfer identifications of a donor run to the features of an acceptor run (match-between-runs):
+/// the donor RTs are mapped to the acceptor RT scale through the reference run.
+pub fn transfer_identifications(
+    identifications: &[IdentificationToTransfer],
+    donor: &RtAlignment,
+    acceptor: &RtAlignment,
+    acceptor_features: &[Feature],
+    config: &IdentificationTransferConfig,
+) -> Result<Vec<TransferredIdentification>> {
+    if donor.reference_run_name != acceptor.reference_run_name {
+        bail!("donor and acceptor runs must be aligned on the same reference run")
+    }
+
+    let rt_tolerance = transfer_rt_tolerance(donor, acceptor, config);
+    let targets: Vec<FeatureMatchTarget> = identifications.iter().map(|identification| FeatureMatchTarget {
+        mono_mass: identification.mono_mass,
+        charge: Some(identification.charge),
+        rt: donor.map_rt(identification.rt, acceptor),
+    }).collect();
+
+    let transferred_identifications = match_features(acceptor_features, &targets, config.mass_tolerance, rt_tolerance)
+        .into_iter()
+        .map(|feature_match| TransferredIdentification {
+            identification_index: feature_match.target_index,
+            feature_index: feature_match.feature_index,
+            predicted_rt: targets[feature_match.target_index].rt,
+            rt_tolerance,
+            mass_error_ppm: feature_match.mass_error_ppm,
+            rt_error: feature_match.rt_error,
+        })
+        .collect();
+
+    Ok(transferred_identifications)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ms::utils::mass_to_mz;
+
+    #[test]
+    fn align_runs_and_transfer_identifications() -> Result<()> {
+        // Nonlinear RT shift between the two runs
+        let shift_rt = |rt: f32| 1.05 * rt + 2.0 + 1.5 * (rt / 15.0).sin();
+        let n_peptides = 120;
+
+        let mut anchors_a = HashMap::new();
+        let mut anchors_b = HashMap::new();
+        for pep_idx in 0..n_peptides {
+            let rt = 10.0 + pep_idx as f32 * 0.8;
+            let noise = 0.05 * ((pep_idx * 7919) % 11) as f32 / 10.0 - 0.025;
+            anchors_a.insert(format!("PEP{pep_idx}"), rt);
+            anchors_b.insert(format!("PEP{pep_idx}"), shift_rt(rt) + noise);
+        }
+        // Misidentified anchor
+        anchors_b.insert("PEP50".to_string(), 5.0);
+
+        let runs = vec![RunRtAnchors::new("A", anchors_a), RunRtAnchors::new("B", anchors_b)];
+        assert_eq!(select_reference_run(&runs), Some(0));
+
+        for method in [
+            RegressionMethod::Lowess { span: 0.2, robustness_iterations: 3 },
+            RegressionMethod::PiecewiseLinear { n_segments: 30 },
+            RegressionMethod::CubicSpline { n_knots: 15 },
+        ] {
+            let config = RtAlignmentConfig { method, ..Default::default() };
+            let alignments = align_runs(&runs, 0, &config)?;
+            let stats = alignments[1].residual_statistics;
+            assert!(stats.n_outliers >= 1 && stats.max_abs < 0.5, "{:?}: {:?}", method, stats);
+            assert!(stats.robust_std_dev < 0.1, "{:?}: {:?}", method, stats);
+            assert!((alignments[1].to_reference_rt(shift_rt(50.0)) - 50.0).abs() < 0.2, "{:?}", method);
+            assert!((alignments[1].from_reference_rt(50.0) - shift_rt(50.0)).abs() < 0.2, "{:?}", method);
+        }
+
+        let alignments = align_runs(&runs, 0, &RtAlignmentConfig::default())?;
+        assert!(RtAlignment::fit(&runs[1], &runs[0], &RtAlignmentConfig { min_anchors: 500, ..Default::default() }).is_err());
+
+        // Transfer an identification of run A to the features of run B
+        let feature = |mono_mass: f64, apex_rt: f32| Feature {
+            mono_mz: mass_to_mz(mono_mass, 2),
+            charge: 2,
+            apex_rt,
+            start_rt: apex_rt - 0.1,
+            end_rt: apex_rt + 0.1,
+            apex_intensity: 1e5,
+            intensity: 1e6,
+            isotope_mz_values: Vec::new(),
+            isotope_intensities: Vec::new(),
+            score: 1.0,
+        };
+        let features_b = vec![feature(1200.6, shift_rt(40.0) - 3.0), feature(1200.6, shift_rt(40.0) + 0.05), feature(1500.0, shift_rt(40.0))];
+        let identifications = [IdentificationToTransfer { key: "NEWPEP".to_string(), mono_mass: 1200.6, charge: 2, rt: 40.0 }];
+
+        let transferred = transfer_identifications(&identifications, &alignments[0], &alignments[1], &features_b, &Default::default())?;
+        assert_eq!(transferred.len(), 1);
+        assert_eq!(transferred[0].feature_index, 1);
+        assert!((transferred[0].predicted_rt - shift_rt(40.0)).abs() < 0.2);
+
+        Ok(())
+    }
+}
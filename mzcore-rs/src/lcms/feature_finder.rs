@@ -0,0 +1,446 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::constants::AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF;
+use crate::chemistry::isotope_distribution::IsotopeDistribution;
+use crate::lcms::run::LcMsRun;
+use crate::ms::similarity::{pearson_correlation, vector_cosine_similarity};
+use crate::ms::utils::{mz_to_mass, MassTolWindow};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeatureFinderConfig {
+    /// Tolerance used to link peaks across consecutive scans and to find isotopic traces
+    pub mz_tolerance: MassTolWindow,
+    pub min_peak_intensity: f32,
+    /// Number of consecutive scans where a trace can be missing before being closed
+    pub max_missing_scans: usize,
+    pub min_trace_length: usize,
+    pub min_charge: i8,
+    pub max_charge: i8,
+    /// Minimum number of consecutive isotopic traces (including the monoisotopic one)
+    pub min_isotopes: usize,
+    pub max_isotopes: usize,
+    /// Minimum elution profile correlation between an isotopic trace and the most intense trace of the feature
+    pub min_trace_correlation: f32,
+    /// Minimum quality score (averagine cosine similarity multiplied by the mean elution profile correlation)
+    pub min_score: f32,
+}
+
+impl Default for FeatureFinderConfig {
+    fn default() -> Self {
+        FeatureFinderConfig {
+            mz_tolerance: MassTolWindow::ppm(-10.0, 10.0),
+            min_peak_intensity: 0.0,
+            max_missing_scans: 1,
+            min_trace_length: 3,
+            min_charge: 1,
+            max_charge: 6,
+            min_isotopes: 2,
+            max_isotopes: 6,
+            min_trace_correlation: 0.6,
+            min_score: 0.5,
+        }
+    }
+}
+
+/// Centroided peaks of the same ion linked over consecutive MS1 scans
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MassTrace {
+    /// Indices of the spectra in the run (sorted)
+    pub spectrum_indices: Vec<usize>,
+    pub rt_values: Vec<f32>,
+    pub mz_values: Vec<f64>,
+    pub intensities: Vec<f32>,
+}
+
+impl MassTrace {
+    pub fn len(&self) -> usize {
+        self.intensities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intensities.is_empty()
+    }
+
+    /// Intensity-weighted mean m/z
+    pub fn centroid_mz(&self) -> f64 {
+        let total_intensity: f64 = self.intensities.iter().map(|ab| *ab as f64).sum();
+        if total_intensity <= 0.0 {
+            return self.mz_values.iter().sum::<f64>() / self.len() as f64
+        }
+        self.mz_values.iter().zip(&self.intensities).map(|(mz, ab)| mz * *ab as f64).sum::<f64>() / total_intensity
+    }
+
+    pub fn apex_index(&self) -> usize {
+        self.intensities.iter().enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(idx, _)| idx)
+            .unwrap_or(0)
+    }
+
+    pub fn apex_rt(&self) -> f32 {
+        self.rt_values[self.apex_index()]
+    }
+
+    pub fn apex_intensity(&self) -> f32 {
+        self.intensities[self.apex_index()]
+    }
+
+    pub fn start_rt(&self) -> f32 {
+        self.rt_values[0]
+    }
+
+    pub fn end_rt(&self) -> f32 {
+        self.rt_values[self.len() - 1]
+    }
+
+    /// Trapezoidal integration of the intensities over RT
+    pub fn area(&self) -> f64 {
+        if self.len() == 1 {
+            return self.intensities[0] as f64
+        }
+
+        self.rt_values.windows(2).zip(self.intensities.windows(2)).map(|(rt, ab)| {
+            (rt[1] - rt[0]) as f64 * (ab[0] as f64 + ab[1] as f64) / 2.0
+        }).sum()
+    }
+}
+
+/// Isotope-pattern feature assembled from co-eluting mass traces
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Feature {
+    pub mono_mz: f64,
+    pub charge: i8,
+    pub apex_rt: f32,
+    pub start_rt: f32,
+    pub end_rt: f32,
+    pub apex_intensity: f32,
+    /// Sum of the areas of the isotopic traces
+    pub intensity: f64,
+    pub isotope_mz_values: Vec<f64>,
+    pub isotope_intensities: Vec<f64>,
+    pub score: f32,
+}
+
+impl Feature {
+    pub fn mono_mass(&self) -> f64 {
+        mz_to_mass(self.mono_mz, self.charge as i32)
+    }
+
+    pub fn n_isotopes(&self) -> usize {
+        self.isotope_mz_values.len()
+    }
+}
+
+/// Link the MS1 peaks of a run into mass traces: peaks are assigned by decreasing intensity
+/// to the closest active trace, or start a new trace when no active trace matches.
+pub fn detect_mass_traces(run: &LcMsRun, config: &FeatureFinderConfig) -> Vec<MassTrace> {
+    let mut closed_traces: Vec<MassTrace> = Vec::new();
+    // Active traces with the m/z of their last peak, sorted by m/z
+    let mut active_traces: Vec<(f64, MassTrace)> = Vec::new();
+
+    for (spectrum_idx, spectrum) in run.spectra().iter().enumerate() {
+        let data = &spectrum.data;
+
+        let mut peak_order: Vec<usize> = (0..data.mz_list.len())
+            .filter(|&i| data.intensity_list[i] > 0.0 && data.intensity_list[i] >= config.min_peak_intensity)
+            .collect();
+        peak_order.sort_by(|&a, &b| data.intensity_list[b].total_cmp(&data.intensity_list[a]));
+
+        let mut is_trace_extended = vec![false; active_traces.len()];
+        let mut new_traces: Vec<(f64, MassTrace)> = Vec::new();
+
+        for peak_idx in peak_order {
+            let (mz, intensity) = (data.mz_list[peak_idx], data.intensity_list[peak_idx]);
+            let (lo, hi) = config.mz_tolerance.bounds(mz);
+
+            let first_idx = active_traces.partition_point(|(trace_mz, _)| *trace_mz < lo);
+            let nearest_trace_idx = (first_idx..active_traces.len())
+                .take_while(|&i| active_traces[i].0 <= hi)
+                .filter(|&i| !is_trace_extended[i])
+                .min_by(|&a, &b| (active_traces[a].0 - mz).abs().total_cmp(&(active_traces[b].0 - mz).abs()));
+
+            match nearest_trace_idx {
+                Some(trace_idx) => {
+                    is_trace_extended[trace_idx] = true;
+                    let trace = &mut active_traces[trace_idx].1;
+                    trace.spectrum_indices.push(spectrum_idx);
+                    trace.rt_values.push(spectrum.rt);
+                    trace.mz_values.push(mz);
+                    trace.intensities.push(intensity);
+                }
+                None => new_traces.push((mz, MassTrace {
+                    spectrum_indices: vec![spectrum_idx],
+                    rt_values: vec![spectrum.rt],
+                    mz_values: vec![mz],
+                    intensities: vec![intensity],
+                })),
+            }
+        }
+
+        // Close the traces which have been missing for too many scans
+        let mut still_active_traces = Vec::with_capacity(active_traces.len() + new_traces.len());
+        for (_, trace) in active_traces {
+            let last_spectrum_idx = *trace.spectrum_indices.last().unwrap(); // safe because traces are never empty
+            if spectrum_idx - last_spectrum_idx > config.max_missing_scans {
+                closed_traces.push(trace);
+            } else {
+                still_active_traces.push((*trace.mz_values.last().unwrap(), trace));
+            }
+        }
+        still_active_traces.extend(new_traces);
+        still_active_traces.sort_by(|a, b| a.0.total_cmp(&b.0));
+        active_traces = still_active_traces;
+    }
+
+    closed_traces.extend(active_traces.into_iter().map(|(_, trace)| trace));
+    closed_traces.retain(|trace| trace.len() >= config.min_trace_length.max(1));
+    closed_traces.sort_by(|a, b| a.centroid_mz().total_cmp(&b.centroid_mz()));
+
+    closed_traces
+}
+
+/// Assemble mass traces into isotope-pattern features. The most intense unassigned traces are used as seeds,
+/// and every (charge, isotope position of the seed) hypothesis is scored against an averagine envelope.
+pub fn assemble_features(traces: &[MassTrace], config: &FeatureFinderConfig) -> Vec<Feature> {
+    let centroid_mz_values: Vec<f64> = traces.iter().map(|trace| trace.centroid_mz()).collect();
+
+    let mut seed_order: Vec<usize> = (0..traces.len()).collect();
+    seed_order.sort_by(|&a, &b| traces[b].apex_intensity().total_cmp(&traces[a].apex_intensity()));
+
+    let mut is_trace_assigned = vec![false; traces.len()];
+    let mut features = Vec::new();
+
+    for seed_idx in seed_order {
+        if is_trace_assigned[seed_idx] {
+            continue
+        }
+        let seed_trace = &traces[seed_idx];
+
+        // Best hypothesis: (score, charge, isotopic trace indices)
+        let mut best_hypothesis: Option<(f32, i8, Vec<usize>)> = None;
+
+        for charge in config.min_charge.max(1)..=config.max_charge {
+            let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+
+            for seed_position in 0..config.max_isotopes {
+                let mono_mz = centroid_mz_values[seed_idx] - seed_position as f64 * isotope_spacing;
+
+                // Consecutive co-eluting isotopic traces starting at the monoisotopic position
+                let mut isotope_trace_indices: Vec<usize> = Vec::with_capacity(config.max_isotopes);
+                let mut correlations: Vec<f64> = Vec::with_capacity(config.max_isotopes);
+                for isotope_idx in 0..config.max_isotopes {
+                    let trace_idx = if isotope_idx == seed_position {
+                        Some(seed_idx)
+                    } else {
+                        _find_isotopic_trace(traces, &centroid_mz_values, &is_trace_assigned, seed_trace, mono_mz + isotope_idx as f64 * isotope_spacing, config)
+                    };
+
+                    let Some(trace_idx) = trace_idx else { break };
+                    let (correlation, _) = _elution_profile_similarity(seed_trace, &traces[trace_idx]);
+                    isotope_trace_indices.push(trace_idx);
+                    correlations.push(correlation);
+                }
+
+                if isotope_trace_indices.len() <= seed_position || isotope_trace_indices.len() < config.min_isotopes.max(1) {
+                    continue
+                }
+
+                let observed_envelope: Vec<f64> = isotope_trace_indices.iter()
+                    .map(|&trace_idx| _elution_profile_similarity(seed_trace, &traces[trace_idx]).1)
+                    .collect();
+
+                let mono_mass = mz_to_mass(mono_mz, charge as i32);
+                let Ok(theo_distrib) = IsotopeDistribution::averagine(mono_mass, observed_envelope.len()) else { continue };
+
+                let cosine = vector_cosine_similarity(&observed_envelope, &theo_distrib.abundances);
+                let mean_correlation = correlations.iter().sum::<f64>() / correlations.len() as f64;
+                let score = (cosine * mean_correlation) as f32;
+
+                if score >= config.min_score && best_hypothesis.as_ref().is_none_or(|best| score > best.0) {
+                    best_hypothesis = Some((score, charge, isotope_trace_indices));
+                }
+            }
+        }
+
+        let Some((score, charge, isotope_trace_indices)) = best_hypothesis else { continue };
+
+        for &trace_idx in isotope_trace_indices.iter() {
+            is_trace_assigned[trace_idx] = true;
+        }
+
+        let isotope_traces: Vec<&MassTrace> = isotope_trace_indices.iter().map(|&idx| &traces[idx]).collect();
+        let isotope_intensities: Vec<f64> = isotope_traces.iter().map(|trace| trace.area()).collect();
+
+        features.push(Feature {
+            mono_mz: centroid_mz_values[isotope_trace_indices[0]],
+            charge,
+            apex_rt: seed_trace.apex_rt(),
+            start_rt: isotope_traces.iter().map(|trace| trace.start_rt()).fold(f32::INFINITY, f32::min),
+            end_rt: isotope_traces.iter().map(|trace| trace.end_rt()).fold(f32::NEG_INFINITY, f32::max),
+            apex_intensity: seed_trace.apex_intensity(),
+            intensity: isotope_intensities.iter().sum(),
+            isotope_mz_values: isotope_trace_indices.iter().map(|&idx| centroid_mz_values[idx]).collect(),
+            isotope_intensities,
+            score,
+        });
+    }
+
+    features.sort_by(|a, b| a.mono_mz.total_cmp(&b.mono_mz));
+    features
+}
+
+/// Detect mass traces and assemble them into isotope-pattern features
+pub fn find_features(run: &LcMsRun, config: &FeatureFinderConfig) -> Vec<Feature> {
+    assemble_features(&detect_mass_traces(run, config), config)
+}
+
+/// Identified peptide to be matched against the detected features
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeatureMatchTarget {
+    pub mono_mass: f64,
+    /// If defined, only features having the same charge state are considered
+    pub charge: Option<i8>,
+    pub rt: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeatureMatch {
+    pub target_index: usize,
+    pub feature_index: usize,
+    pub mass_error_ppm: f64,
+    /// Target RT minus feature apex RT
+    pub rt_error: f32,
+}
+
+/// Match each target to the feature having the closest apex RT among the features matching its mass.
+/// A feature matches in RT if the target RT is included in its elution range extended by `rt_tolerance`.
+pub fn match_features(
+    features: &[Feature],
+    targets: &[FeatureMatchTarget],
+    mass_tolerance: MassTolWindow,
+    rt_tolerance: f32,
+) -> Vec<FeatureMatch> {
+    let mut feature_order: Vec<usize> = (0..features.len()).collect();
+    feature_order.sort_by(|&a, &b| features[a].mono_mass().total_cmp(&features[b].mono_mass()));
+    let sorted_masses: Vec<f64> = feature_order.iter().map(|&idx| features[idx].mono_mass()).collect();
+
+    targets.iter().enumerate().filter_map(|(target_index, target)| {
+        let (lo, hi) = mass_tolerance.bounds(target.mono_mass);
+        let start = sorted_masses.partition_point(|&mass| mass < lo);
+        let end = sorted_masses.partition_point(|&mass| mass <= hi);
+
+        feature_order[start..end].iter()
+            .map(|&feature_index| (feature_index, &features[feature_index]))
+            .filter(|(_, feature)| target.charge.is_none_or(|charge| charge == feature.charge))
+            .filter(|(_, feature)| target.rt >= feature.start_rt - rt_tolerance && target.rt <= feature.end_rt + rt_tolerance)
+            .min_by(|a, b| (target.rt - a.1.apex_rt).abs().total_cmp(&(target.rt - b.1.apex_rt).abs()))
+            .map(|(feature_index, feature)| FeatureMatch {
+                target_index,
+                feature_index,
+                mass_error_ppm: (feature.mono_mass() - target.mono_mass) / target.mono_mass * 1e6,
+                rt_error: target.rt - feature.apex_rt,
+            })
+    }).collect()
+}
+
+fn _find_isotopic_trace(
+    traces: &[MassTrace],
+    centroid_mz_values: &[f64],
+    is_trace_assigned: &[bool],
+    seed_trace: &MassTrace,
+    expected_mz: f64,
+    config: &FeatureFinderConfig,
+) -> Option<usize> {
+    let (lo, hi) = config.mz_tolerance.bounds(expected_mz);
+    let start = centroid_mz_values.partition_point(|&mz| mz < lo);
+
+    (start..traces.len())
+        .take_while(|&idx| centroid_mz_values[idx] <= hi)
+        .filter(|&idx| !is_trace_assigned[idx])
+        .map(|idx| (idx, _elution_profile_similarity(seed_trace, &traces[idx]).0))
+        .filter(|(_, correlation)| *correlation >= config.min_trace_correlation as f64)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(idx, _)| idx)
+}
+
+/// Pearson correlation of the intensities of two traces over the scans where the reference trace is observed,
+/// returned with the summed intensity of the other trace over these scans
+fn _elution_profile_similarity(reference: &MassTrace, other: &MassTrace) -> (f64, f64) {
+    let mut reference_intensities = Vec::with_capacity(reference.len());
+    let mut other_intensities = Vec::with_capacity(reference.len());
+
+    let mut other_idx = 0;
+    for (&spectrum_idx, &ref_intensity) in reference.spectrum_indices.iter().zip(&reference.intensities) {
+        while other_idx < other.len() && other.spectrum_indices[other_idx] < spectrum_idx {
+            other_idx += 1;
+        }
+        let other_intensity = if other_idx < other.len() && other.spectrum_indices[other_idx] == spectrum_idx {
+            other.intensities[other_idx] as f64
+        } else {
+            0.0
+        };
+
+        reference_intensities.push(ref_intensity as f64);
+        other_intensities.push(other_intensity);
+    }
+
+    let overlap_intensity: f64 = other_intensities.iter().sum();
+    if overlap_intensity <= 0.0 {
+        return (0.0, 0.0)
+    }
+
+    let correlation = if std::ptr::eq(reference, other) { 1.0 } else { pearson_correlation(&reference_intensities, &other_intensities) };
+
+    (correlation, overlap_intensity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lcms::run::Ms1Spectrum;
+    use crate::ms::spectrum::{Peak, SpectrumData};
+
+    #[test]
+    fn find_and_match_isotopic_features() -> anyhow::Result<()> {
+        let distrib = IsotopeDistribution::averagine(1500.0, 4)?;
+        let envelope_mz_values = distrib.mz_values(2);
+
+        let spectra: Vec<Ms1Spectrum> = (0..30).map(|scan_idx| {
+            let rt = scan_idx as f32 * 0.1;
+            let elution = (-0.5 * ((rt - 1.5) / 0.3).powi(2)).exp() as f64;
+
+            let mut peaks: Vec<Peak> = envelope_mz_values.iter().zip(&distrib.abundances)
+                .map(|(&mz, &ab)| Peak { mz, intensity: (1e6 * ab * elution) as f32 })
+                .filter(|peak| peak.intensity > 100.0)
+                .collect();
+            // Constant background ion
+            peaks.push(Peak { mz: 445.2, intensity: 5e3 });
+            peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+
+            Ms1Spectrum { scan_number: scan_idx + 1, rt, data: SpectrumData::from_peaks(&peaks) }
+        }).collect();
+        let run = LcMsRun::new("test", spectra)?;
+
+        let config = FeatureFinderConfig::default();
+        let traces = detect_mass_traces(&run, &config);
+        assert_eq!(traces.len(), 5);
+
+        let features = assemble_features(&traces, &config);
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0].charge, 2);
+        assert_eq!(features[0].n_isotopes(), 4);
+        assert!((features[0].mono_mass() - 1500.0).abs() < 0.001);
+        assert!((features[0].apex_rt - 1.5).abs() < 0.01);
+        assert!(features[0].score > 0.9);
+
+        let targets = [
+            FeatureMatchTarget { mono_mass: 1500.001, charge: None, rt: 1.6 },
+            FeatureMatchTarget { mono_mass: 1500.001, charge: None, rt: 10.0 },
+        ];
+        let matches = match_features(&features, &targets, MassTolWindow::ppm(-10.0, 10.0), 0.5);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].target_index, 0);
+
+        Ok(())
+    }
+}
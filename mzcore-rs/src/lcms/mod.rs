@@ -0,0 +1,5 @@
+pub mod alignment;
+pub mod feature_finder;
+pub mod rt_prediction;
+pub mod run;
+pub mod xic;
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::api::AminoAcidFactory;
+use crate::chemistry::peptide::{LinearPeptide, SimpleModification};
+use crate::chemistry::table::AminoAcidTable;
+use crate::common::regression::{RegressionMethod, RegressionModel};
+use crate::common::stats::{median, robust_standard_deviation};
+
+/// iRT values of the Biognosys iRT kit peptides
+pub const BIOGNOSYS_IRT_PEPTIDES: [(&str, f64); 11] = [
+    ("LGGNEQVTR", -24.92),
+    ("GAGSSEPVTGLDAK", 0.00),
+    ("VEATFGVDESNK", 12.39),
+    ("YILAGVENSK", 19.79),
+    ("TPVISGGPYEYR", 28.71),
+    ("TPVITGAPYEYR", 33.38),
+    ("DGLDAASYYAPVR", 42.26),
+    ("ADVTPADFSEWSK", 54.62),
+    ("GTFIIDPGGVIR", 70.52),
+    ("GTFIIDPAAVIR", 87.23),
+    ("LFLQFGAQGSPFLK", 100.00),
+];
+
+/// Parameters of the SSRCalc-like hydrophobicity model (Krokhin et al., 2004).
+/// The residue retention coefficients are read from the `hydrophobicity` fields of the amino acid table.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RtPredictorConfig {
+    /// Weights of the N-terminal retention coefficients of the first residues
+    pub n_term_weights: Vec<f32>,
+    /// Weights of the retention coefficients of the last residues (starting from the C-terminal one)
+    pub c_term_weights: Vec<f32>,
+    /// The index of shorter peptides is decreased by `short_peptide_factor` per missing residue
+    pub short_peptide_length: usize,
+    pub short_peptide_factor: f32,
+    /// The index of longer peptides is decreased by `long_peptide_factor` per additional residue
+    pub long_peptide_length: usize,
+    pub long_peptide_factor: f32,
+    /// Indices above this threshold are compressed by `high_hydrophobicity_factor`
+    pub hydrophobicity_threshold: f32,
+    pub high_hydrophobicity_factor: f32,
+    /// Contributions of the modifications, indexed by modification id
+    pub modification_contributions: HashMap<i64, f32>,
+}
+
+impl Default for RtPredictorConfig {
+    fn default() -> Self {
+        RtPredictorConfig {
+            n_term_weights: vec![0.42, 0.22, 0.05],
+            c_term_weights: Vec::new(),
+            short_peptide_length: 10,
+            short_peptide_factor: 0.027,
+            long_peptide_length: 20,
+            long_peptide_factor: 0.014,
+            hydrophobicity_threshold: 38.0,
+            high_hydrophobicity_factor: 0.3,
+            modification_contributions: HashMap::new(),
+        }
+    }
+}
+
+/// Hydrophobicity index of a peptide sequence, which is expected to be linearly related to its retention time
+pub fn predict_hydrophobicity_index(
+    sequence: &[u8],
+    mods: &[SimpleModification],
+    aa_table: &AminoAcidTable,
+    config: &RtPredictorConfig,
+) -> Result<f64> {
+    if sequence.is_empty() {
+        bail!("sequence is empty")
+    }
+
+    let mut sum_rc = 0.0;
+    for residue in sequence {
+        sum_rc += aa_table.aa_from_byte(residue)?.hydrophobicity as f64;
+    }
+    for (residue, weight) in sequence.iter().zip(&config.n_term_weights) {
+        sum_rc += *weight as f64 * aa_table.aa_from_byte(residue)?.hydrophobicity_n_term as f64;
+    }
+    for (residue, weight) in sequence.iter().rev().zip(&config.c_term_weights) {
+        sum_rc += *weight as f64 * aa_table.aa_from_byte(residue)?.hydrophobicity as f64;
+    }
+    sum_rc += mods.iter()
+        .filter_map(|m| config.modification_contributions.get(&m.id))
+        .map(|contribution| *contribution as f64)
+        .sum::<f64>();
+
+    let length = sequence.len();
+    let length_factor = if length < config.short_peptide_length {
+        1.0 - config.short_peptide_factor as f64 * (config.short_peptide_length - length) as f64
+    } else if length > config.long_peptide_length {
+        1.0 - config.long_peptide_factor as f64 * (length - config.long_peptide_length) as f64
+    } else {
+        1.0
+    };
+
+    let mut index = length_factor * sum_rc;
+    let threshold = config.hydrophobicity_threshold as f64;
+    if index > threshold {
+        index -= config.high_hydrophobicity_factor as f64 * (index - threshold);
+    }
+
+    Ok(index)
+}
+
+pub fn peptide_hydrophobicity_index(peptide: &LinearPeptide, aa_table: &AminoAcidTable, config: &RtPredictorConfig) -> Result<f64> {
+    predict_hydrophobicity_index(&peptide.sequence, &peptide.mods, aa_table, config)
+}
+
+/// Linear mapping between a RT scale (hydrophobicity index or iRT) and the observed retention times of a run
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RtCalibration {
+    pub slope: f64,
+    pub intercept: f64,
+    pub n_points: usize,
+    pub n_outliers: usize,
+    pub r_squared: f64,
+    /// Robust standard deviation of the RT residuals of the inliers
+    pub residual_std_dev: f64,
+}
+
+impl RtCalibration {
+    /// Fit observed RTs of confident identifications against their predicted scores (hydrophobicity indices or iRT values).
+    /// Points whose residual exceeds `max_residual_std_devs` robust standard deviations are discarded before a second fit.
+    pub fn fit(scores: &[f64], observed_rts: &[f32], max_residual_std_devs: f64) -> Result<RtCalibration> {
+        let mut x = scores.to_vec();
+        let mut y: Vec<f64> = observed_rts.iter().map(|rt| *rt as f64).collect();
+        let mut model = RegressionModel::fit(&x, &y, RegressionMethod::Linear)?;
+
+        // Outliers bias the least squares fit, so the deviations are computed relative to the median residual
+        let residuals = model.residuals(&x, &y);
+        let median_residual = median(&residuals).unwrap_or(0.0);
+        let max_deviation = max_residual_std_devs * robust_standard_deviation(&residuals).unwrap_or(0.0);
+        let is_inlier: Vec<bool> = residuals.iter().map(|r| (r - median_residual).abs() <= max_deviation).collect();
+        let n_outliers = if max_deviation > 0.0 { is_inlier.iter().filter(|inlier| !**inlier).count() } else { 0 };
+        if n_outliers > 0 && x.len() - n_outliers >= 2 {
+            x = x.iter().zip(&is_inlier).filter(|(_, inlier)| **inlier).map(|(v, _)| *v).collect();
+            y = y.iter().zip(&is_inlier).filter(|(_, inlier)| **inlier).map(|(v, _)| *v).collect();
+            model = RegressionModel::fit(&x, &y, RegressionMethod::Linear)?;
+        }
+
+        let RegressionModel::Linear { slope, intercept } = model else { unreachable!() };
+        let residuals = model.residuals(&x, &y);
+        let mean_y = y.iter().sum::<f64>() / y.len() as f64;
+        let total_ss: f64 = y.iter().map(|v| (v - mean_y).powi(2)).sum();
+        let residual_ss: f64 = residuals.iter().map(|r| r * r).sum();
+
+        Ok(RtCalibration {
+            slope,
+            intercept,
+            n_points: x.len(),
+            n_outliers: if x.len() < scores.len() { n_outliers } else { 0 },
+            r_squared: if total_ss > 0.0 { 1.0 - residual_ss / total_ss } else { 1.0 },
+            residual_std_dev: robust_standard_deviation(&residuals).unwrap_or(0.0),
+        })
+    }
+
+    pub fn predict_rt(&self, score: f64) -> f32 {
+        (self.slope * score + self.intercept) as f32
+    }
+
+    /// Convert an observed RT into the calibrated scale (e.g. RT to iRT)
+    pub fn rt_to_score(&self, rt: f32) -> f64 {
+        (rt as f64 - self.intercept) / self.slope
+    }
+
+    /// Observed minus predicted RT
+    pub fn rt_error(&self, score: f64, observed_rt: f32) -> f32 {
+        observed_rt - self.predict_rt(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chemistry::table::proteinogenic_amino_acid_table;
+
+    #[test]
+    fn predict_and_calibrate_rt() -> Result<()> {
+        let aa_table = proteinogenic_amino_acid_table();
+        let config = RtPredictorConfig::default();
+
+        let index = |sequence: &str| predict_hydrophobicity_index(sequence.as_bytes(), &[], aa_table, &config).unwrap();
+        // Sum of the coefficients + N-terminal correction (S, I, D), no length correction
+        assert!((index("SIDEAGTFAK") - (16.8 + 0.42 * 5.0 - 0.22 * 8.0 + 0.05 * 9.0)).abs() < 1e-4);
+        // Short peptide: length factor of 1 - 0.027 * 4
+        assert!((index("AAGLAK") - 0.892 * (9.2 - 0.42 * 1.5 - 0.22 * 1.5 + 0.05 * 5.0)).abs() < 1e-4);
+        assert!(index("LFLQFGAQGSPFLK") > index("GAGSSEPVTGLDAK"));
+
+        let oxidation = SimpleModification { id: 35, mono_mass: 15.9949, position: Some(0) };
+        let config_with_mods = RtPredictorConfig { modification_contributions: HashMap::from([(35, -2.5)]), ..Default::default() };
+        let ox_index = predict_hydrophobicity_index(b"MFLDAGSTPK", &[oxidation], aa_table, &config_with_mods)?;
+        assert!((ox_index - predict_hydrophobicity_index(b"MFLDAGSTPK", &[], aa_table, &config_with_mods)? + 2.5).abs() < 1e-4);
+
+        // iRT calibration with one misassigned peptide
+        let irt_values: Vec<f64> = BIOGNOSYS_IRT_PEPTIDES.iter().map(|(_, irt)| *irt).collect();
+        let mut observed_rts: Vec<f32> = irt_values.iter().map(|irt| (0.25 * irt + 30.0) as f32).collect();
+        observed_rts[5] += 10.0;
+
+        let calibration = RtCalibration::fit(&irt_values, &observed_rts, 4.0)?;
+        assert_eq!(calibration.n_outliers, 1);
+        assert!((calibration.slope - 0.25).abs() < 1e-4);
+        assert!((calibration.predict_rt(50.0) - 42.5).abs() < 1e-3);
+        assert!((calibration.rt_to_score(42.5) - 50.0).abs() < 1e-3);
+        assert!(calibration.r_squared > 0.999);
+
+        Ok(())
+    }
+}
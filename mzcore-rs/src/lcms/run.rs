@@ -0,0 +1,86 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ms::spectrum::SpectrumData;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ms1Spectrum {
+    pub scan_number: u32,
+    /// Retention time (the unit is left to the caller but must be consistent within a run)
+    pub rt: f32,
+    pub data: SpectrumData,
+}
+
+/// MS1 spectra of an LC-MS run, sorted by RT, each spectrum having its peaks sorted by m/z
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LcMsRun {
+    pub name: String,
+    spectra: Vec<Ms1Spectrum>,
+}
+
+impl LcMsRun {
+    pub fn new(name: &str, mut spectra: Vec<Ms1Spectrum>) -> Result<LcMsRun> {
+        for spectrum in spectra.iter_mut() {
+            if !spectrum.rt.is_finite() {
+                bail!("invalid RT for spectrum with scan number {}", spectrum.scan_number)
+            }
+
+            let data = &mut spectrum.data;
+            if data.mz_list.len() != data.intensity_list.len() {
+                bail!("m/z and intensity lists have different lengths in spectrum with scan number {}", spectrum.scan_number)
+            }
+
+            if !data.mz_list.windows(2).all(|w| w[0] <= w[1]) {
+                let mut peaks = data.to_peaks();
+                peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+                *data = SpectrumData::from_peaks(&peaks);
+            }
+        }
+
+        spectra.sort_by(|a, b| a.rt.total_cmp(&b.rt));
+
+        Ok(LcMsRun {
+            name: name.to_string(),
+            spectra,
+        })
+    }
+
+    pub fn spectra(&self) -> &[Ms1Spectrum] {
+        &self.spectra
+    }
+
+    pub fn len(&self) -> usize {
+        self.spectra.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spectra.is_empty()
+    }
+
+    pub fn rt_range(&self) -> Option<(f32, f32)> {
+        Some((self.spectra.first()?.rt, self.spectra.last()?.rt))
+    }
+
+    /// Index of the spectrum acquired the closest to the provided RT
+    pub fn nearest_spectrum_index(&self, rt: f32) -> Option<usize> {
+        if self.spectra.is_empty() {
+            return None
+        }
+
+        let idx = self.spectra.partition_point(|spectrum| spectrum.rt < rt);
+        if idx == 0 {
+            Some(0)
+        } else if idx == self.spectra.len() || rt - self.spectra[idx - 1].rt <= self.spectra[idx].rt - rt {
+            Some(idx - 1)
+        } else {
+            Some(idx)
+        }
+    }
+
+    /// Range of spectrum indices whose RT is included in [min_rt, max_rt]
+    pub fn spectrum_index_range(&self, min_rt: f32, max_rt: f32) -> std::ops::Range<usize> {
+        let start = self.spectra.partition_point(|spectrum| spectrum.rt < min_rt);
+        let end = self.spectra.partition_point(|spectrum| spectrum.rt <= max_rt);
+        start..end.max(start)
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::regression::solve_linear_system;
+use crate::lcms::run::LcMsRun;
+use crate::ms::utils::MassTolWindow;
+
+/// How the intensities of the peaks matching a target in a given spectrum are combined
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum XicAggregation {
+    Max,
+    Sum,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ChromatogramSmoothing {
+    /// Local polynomial fitting over `window_size` (odd) consecutive scans
+    SavitzkyGolay { window_size: usize, polynomial_order: usize },
+    /// Gaussian kernel whose standard deviation is expressed in RT units
+    Gaussian { sigma: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeakDetectionConfig {
+    pub min_apex_intensity: f32,
+    /// A peak ends when the intensity falls below this fraction of the apex intensity (or at the first valley)
+    pub boundary_intensity_ratio: f32,
+    pub min_scans: usize,
+}
+
+impl Default for PeakDetectionConfig {
+    fn default() -> Self {
+        PeakDetectionConfig {
+            min_apex_intensity: 0.0,
+            boundary_intensity_ratio: 0.05,
+            min_scans: 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChromatographicPeak {
+    pub apex_index: usize,
+    pub apex_rt: f32,
+    pub apex_intensity: f32,
+    /// Index of the first scan of the peak in the chromatogram
+    pub start_index: usize,
+    /// Index of the last scan of the peak in the chromatogram
+    pub end_index: usize,
+    pub start_rt: f32,
+    pub end_rt: f32,
+    /// Trapezoidal integration of the intensities over RT
+    pub area: f64,
+}
+
+impl ChromatographicPeak {
+    pub fn n_scans(&self) -> usize {
+        self.end_index - self.start_index + 1
+    }
+}
+
+/// Intensities of an m/z target as a function of RT (one point per MS1 spectrum, zero when no signal was found)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Chromatogram {
+    pub target_mz: f64,
+    pub rt_values: Vec<f32>,
+    pub intensities: Vec<f32>,
+    /// Index of the first spectrum of the chromatogram in the run
+    pub first_spectrum_index: usize,
+}
+
+impl Chromatogram {
+    pub fn len(&self) -> usize {
+        self.intensities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intensities.is_empty()
+    }
+
+    /// Index of the most intense point (None if the chromatogram only contains zeros)
+    pub fn apex_index(&self) -> Option<usize> {
+        self.intensities.iter().enumerate()
+            .filter(|(_, ab)| **ab > 0.0)
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(idx, _)| idx)
+    }
+
+    pub fn smooth(&self, smoothing: ChromatogramSmoothing) -> Result<Chromatogram> {
+        let intensities = match smoothing {
+            ChromatogramSmoothing::SavitzkyGolay { window_size, polynomial_order } => {
+                savitzky_golay_smoothing(&self.intensities, window_size, polynomial_order)?
+            }
+            ChromatogramSmoothing::Gaussian { sigma } => {
+                gaussian_smoothing(&self.rt_values, &self.intensities, sigma)?
+            }
+        };
+
+        Ok(Chromatogram { intensities, ..self.clone() })
+    }
+
+    /// Trapezoidal integration of the intensities over RT between two indices (inclusive)
+    pub fn integrate(&self, start_index: usize, end_index: usize) -> f64 {
+        let end_index = end_index.min(self.len().saturating_sub(1));
+        if start_index >= end_index {
+            return 0.0
+        }
+
+        (start_index..end_index).map(|i| {
+            let dt = (self.rt_values[i + 1] - self.rt_values[i]) as f64;
+            dt * (self.intensities[i] as f64 + self.intensities[i + 1] as f64) / 2.0
+        }).sum()
+    }
+
+    /// Detect chromatographic peaks by decreasing apex intensity, each peak spanning from its apex down to the
+    /// boundary intensity threshold or to the nearest valley. Peaks are returned sorted by RT.
+    /// Smoothing the chromatogram beforehand avoids splitting peaks on noisy data.
+    pub fn detect_peaks(&self, config: &PeakDetectionConfig) -> Vec<ChromatographicPeak> {
+        let n = self.len();
+        let intensities = &self.intensities;
+
+        let mut apex_candidates: Vec<usize> = (0..n).filter(|&i| {
+            let ab = intensities[i];
+            ab > 0.0 && ab >= config.min_apex_intensity
+                && (i == 0 || intensities[i - 1] <= ab)
+                && (i + 1 == n || intensities[i + 1] <= ab)
+        }).collect();
+        apex_candidates.sort_by(|&a, &b| intensities[b].total_cmp(&intensities[a]));
+
+        let mut is_assigned = vec![false; n];
+        let mut peaks = Vec::new();
+        for apex_index in apex_candidates {
+            if is_assigned[apex_index] {
+                continue
+            }
+
+            let apex_intensity = intensities[apex_index];
+            let threshold = apex_intensity * config.boundary_intensity_ratio;
+
+            let mut start_index = apex_index;
+            while start_index > 0 && !is_assigned[start_index - 1] && intensities[start_index - 1] <= intensities[start_index] {
+                start_index -= 1;
+                if intensities[start_index] < threshold { break }
+            }
+
+            let mut end_index = apex_index;
+            while end_index + 1 < n && !is_assigned[end_index + 1] && intensities[end_index + 1] <= intensities[end_index] {
+                end_index += 1;
+                if intensities[end_index] < threshold { break }
+            }
+
+            is_assigned[start_index..=end_index].iter_mut().for_each(|assigned| *assigned = true);
+
+            if end_index - start_index + 1 < config.min_scans {
+                continue
+            }
+
+            peaks.push(ChromatographicPeak {
+                apex_index,
+                apex_rt: self.rt_values[apex_index],
+                apex_intensity,
+                start_index,
+                end_index,
+                start_rt: self.rt_values[start_index],
+                end_rt: self.rt_values[end_index],
+                area: self.integrate(start_index, end_index),
+            });
+        }
+
+        peaks.sort_by_key(|peak| peak.apex_index);
+        peaks
+    }
+}
+
+/// Extract the chromatogram of a single m/z target, optionally restricted to an RT range
+pub fn extract_xic(run: &LcMsRun, target_mz: f64, tolerance: MassTolWindow, rt_range: Option<(f32, f32)>, aggregation: XicAggregation) -> Chromatogram {
+    extract_xics(run, &[target_mz], tolerance, rt_range, aggregation).pop().unwrap() // safe because one target is provided
+}
+
+/// Extract the chromatograms of many m/z targets in a single pass over the run.
+/// Targets are processed by increasing m/z so that each spectrum is scanned only once.
+/// Chromatograms are returned in the same order than the targets.
+pub fn extract_xics(run: &LcMsRun, targets: &[f64], tolerance: MassTolWindow, rt_range: Option<(f32, f32)>, aggregation: XicAggregation) -> Vec<Chromatogram> {
+    let spectra_range = match rt_range {
+        Some((min_rt, max_rt)) => run.spectrum_index_range(min_rt, max_rt),
+        None => 0..run.len(),
+    };
+    let spectra = &run.spectra()[spectra_range.clone()];
+
+    let mut target_order: Vec<usize> = (0..targets.len()).collect();
+    target_order.sort_by(|&a, &b| targets[a].total_cmp(&targets[b]));
+    let target_bounds: Vec<(f64, f64)> = target_order.iter().map(|&idx| tolerance.bounds(targets[idx])).collect();
+
+    let mut xic_intensities = vec![Vec::with_capacity(spectra.len()); targets.len()];
+    for spectrum in spectra {
+        let mz_list = &spectrum.data.mz_list;
+        let intensity_list = &spectrum.data.intensity_list;
+
+        let mut first_peak_idx = 0;
+        for (&target_idx, &(lo, hi)) in target_order.iter().zip(&target_bounds) {
+            first_peak_idx += mz_list[first_peak_idx..].partition_point(|&mz| mz < lo);
+
+            let mut intensity = 0.0f32;
+            for (_, &peak_intensity) in mz_list[first_peak_idx..].iter().zip(&intensity_list[first_peak_idx..]).take_while(|(&mz, _)| mz <= hi) {
+                intensity = match aggregation {
+                    XicAggregation::Max => intensity.max(peak_intensity),
+                    XicAggregation::Sum => intensity + peak_intensity,
+                };
+            }
+
+            xic_intensities[target_idx].push(intensity);
+        }
+    }
+
+    let rt_values: Vec<f32> = spectra.iter().map(|spectrum| spectrum.rt).collect();
+    targets.iter().zip(xic_intensities).map(|(&target_mz, intensities)| {
+        Chromatogram {
+            target_mz,
+            rt_values: rt_values.clone(),
+            intensities,
+            first_spectrum_index: spectra_range.start,
+        }
+    }).collect()
+}
+
+/// Savitzky-Golay smoothing (the polynomial is fitted on asymmetric windows at the edges).
+/// Negative smoothed values are set to zero.
+pub fn savitzky_golay_smoothing(values: &[f32], window_size: usize, polynomial_order: usize) -> Result<Vec<f32>> {
+    if window_size < 3 || window_size.is_multiple_of(2) {
+        bail!("Savitzky-Golay window size must be an odd number greater than 1")
+    }
+    if polynomial_order >= window_size {
+        bail!("Savitzky-Golay polynomial order must be less than the window size")
+    }
+
+    let n = values.len();
+    let window_size = window_size.min(n);
+    if window_size <= polynomial_order + 1 {
+        return Ok(values.to_vec())
+    }
+    let half_window = window_size / 2;
+
+    // Coefficients only depend on the position of the smoothed point in its window
+    let mut coefficients_cache: Vec<Option<Vec<f64>>> = vec![None; window_size];
+
+    let smoothed = (0..n).map(|i| {
+        let window_start = i.saturating_sub(half_window).min(n - window_size);
+        let eval_pos = i - window_start;
+
+        let coefficients = coefficients_cache[eval_pos]
+            .get_or_insert_with(|| _savitzky_golay_coefficients(window_size, polynomial_order, eval_pos));
+
+        let value: f64 = coefficients.iter().zip(&values[window_start..window_start + window_size])
+            .map(|(c, v)| c * *v as f64)
+            .sum();
+
+        value.max(0.0) as f32
+    }).collect();
+
+    Ok(smoothed)
+}
+
+/// Gaussian kernel smoothing accounting for irregular RT sampling (the kernel is truncated at 3 sigmas)
+pub fn gaussian_smoothing(rt_values: &[f32], values: &[f32], sigma: f32) -> Result<Vec<f32>> {
+    if sigma <= 0.0 {
+        bail!("Gaussian smoothing sigma must be strictly positive")
+    }
+    if rt_values.len() != values.len() {
+        bail!("RT values and intensities must have the same length")
+    }
+
+    let max_dist = 3.0 * sigma;
+    let smoothed = rt_values.iter().map(|&rt| {
+        let start = rt_values.partition_point(|&other_rt| other_rt < rt - max_dist);
+        let end = rt_values.partition_point(|&other_rt| other_rt <= rt + max_dist);
+
+        let mut sum_w = 0.0;
+        let mut sum_wv = 0.0;
+        for (other_rt, value) in rt_values[start..end].iter().zip(&values[start..end]) {
+            let w = (-0.5 * ((other_rt - rt) / sigma).powi(2)).exp() as f64;
+            sum_w += w;
+            sum_wv += w * *value as f64;
+        }
+
+        if sum_w > 0.0 { (sum_wv / sum_w) as f32 } else { 0.0 }
+    }).collect();
+
+    Ok(smoothed)
+}
+
+/// Convolution coefficients giving the value at `eval_pos` of the polynomial fitted to a window of `window_size` points
+fn _savitzky_golay_coefficients(window_size: usize, polynomial_order: usize, eval_pos: usize) -> Vec<f64> {
+    let n_params = polynomial_order + 1;
+    let offsets: Vec<f64> = (0..window_size).map(|k| k as f64 - eval_pos as f64).collect();
+
+    // Normal equations matrix (J^T J) of the Vandermonde matrix J
+    let mut normal_matrix = vec![vec![0.0; n_params]; n_params];
+    for (row_idx, row) in normal_matrix.iter_mut().enumerate() {
+        for (col_idx, value) in row.iter_mut().enumerate() {
+            *value = offsets.iter().map(|t| t.powi((row_idx + col_idx) as i32)).sum();
+        }
+    }
+
+    // The fitted value at offset 0 is the constant term: h = J (J^T J)^-1 e0
+    let mut e0 = vec![0.0; n_params];
+    e0[0] = 1.0;
+    let z = solve_linear_system(normal_matrix, e0);
+
+    offsets.iter().map(|t| {
+        z.iter().enumerate().map(|(j, zj)| zj * t.powi(j as i32)).sum()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lcms::run::Ms1Spectrum;
+    use crate::ms::spectrum::SpectrumData;
+
+    fn _gaussian_peak(rt: f32, apex_rt: f32, height: f32) -> f32 {
+        height * (-0.5 * ((rt - apex_rt) / 2.0).powi(2)).exp()
+    }
+
+    #[test]
+    fn extract_and_integrate_xics() -> Result<()> {
+        let spectra: Vec<Ms1Spectrum> = (0..60).map(|scan_idx| {
+            let rt = scan_idx as f32;
+            Ms1Spectrum {
+                scan_number: scan_idx + 1,
+                rt,
+                data: SpectrumData {
+                    mz_list: vec![400.0, 500.001, 500.002, 600.0],
+                    intensity_list: vec![
+                        _gaussian_peak(rt, 20.0, 1000.0),
+                        _gaussian_peak(rt, 40.0, 300.0),
+                        _gaussian_peak(rt, 40.0, 200.0),
+                        100.0,
+                    ],
+                },
+            }
+        }).collect();
+        let run = LcMsRun::new("test", spectra)?;
+
+        let tolerance = MassTolWindow::ppm(-10.0, 10.0);
+        let xics = extract_xics(&run, &[500.0, 400.0, 700.0], tolerance, None, XicAggregation::Sum);
+        assert_eq!(xics.len(), 3);
+        assert_eq!(xics[0].apex_index(), Some(40));
+        assert!((xics[0].intensities[40] - 500.0).abs() < 1e-3);
+        assert_eq!(xics[1].apex_index(), Some(20));
+        assert_eq!(xics[2].apex_index(), None);
+
+        let max_xic = extract_xic(&run, 500.0, tolerance, Some((30.0, 50.0)), XicAggregation::Max);
+        assert_eq!(max_xic.len(), 21);
+        assert_eq!(max_xic.first_spectrum_index, 30);
+        assert!((max_xic.intensities[10] - 300.0).abs() < 1e-3);
+
+        let smoothed_xic = xics[1].smooth(ChromatogramSmoothing::SavitzkyGolay { window_size: 7, polynomial_order: 3 })?;
+        let peaks = smoothed_xic.detect_peaks(&PeakDetectionConfig { min_apex_intensity: 10.0, ..Default::default() });
+        assert_eq!(peaks.len(), 1);
+        assert_eq!(peaks[0].apex_index, 20);
+
+        // Area of a Gaussian peak: height * sigma * sqrt(2 * pi)
+        let expected_area = 1000.0 * 2.0 * (2.0 * std::f64::consts::PI).sqrt();
+        assert!((peaks[0].area - expected_area).abs() / expected_area < 0.02);
+
+        let gaussian_smoothed_xic = xics[1].smooth(ChromatogramSmoothing::Gaussian { sigma: 1.0 })?;
+        assert_eq!(gaussian_smoothed_xic.apex_index(), Some(20));
+
+        Ok(())
+    }
+}
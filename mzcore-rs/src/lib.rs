@@ -1,7 +1,9 @@
 pub mod chemistry;
 pub mod common;
+pub mod lcms;
 pub mod ms;
 pub mod msms;
+pub mod quant;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,195 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ms::spectrum::SpectrumData;
+
+/// Comet default fragment bin width (`fragment_bin_tol`)
+pub const COMET_BIN_WIDTH: f64 = 1.0005079;
+/// Comet default fragment bin offset (`fragment_bin_offset`)
+pub const COMET_BIN_OFFSET: f64 = 0.4;
+
+/// How peaks falling in the same bin are combined
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum BinAggregation {
+    Max,
+    Sum,
+}
+
+/// Bins are defined as `floor((mz - min_mz) / bin_width + (1 - bin_offset))`, following the Comet convention
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpectrumBinningConfig {
+    pub bin_width: f64,
+    pub bin_offset: f64,
+    pub min_mz: f64,
+    pub max_mz: f64,
+    pub aggregation: BinAggregation,
+    pub l2_normalize: bool,
+}
+
+impl SpectrumBinningConfig {
+    pub fn new(bin_width: f64, bin_offset: f64, min_mz: f64, max_mz: f64, aggregation: BinAggregation, l2_normalize: bool) -> Result<SpectrumBinningConfig> {
+        if bin_width <= 0.0 { bail!("bin_width must be a strictly positive number") }
+        if !(0.0..1.0).contains(&bin_offset) { bail!("bin_offset must be in the [0, 1) range") }
+        if max_mz <= min_mz { bail!("max_mz must be greater than min_mz") }
+
+        Ok(SpectrumBinningConfig {
+            bin_width,
+            bin_offset,
+            min_mz,
+            max_mz,
+            aggregation,
+            l2_normalize,
+        })
+    }
+
+    /// Comet-style binning of the [0, max_mz] m/z range
+    pub fn comet(max_mz: f64) -> SpectrumBinningConfig {
+        SpectrumBinningConfig {
+            bin_width: COMET_BIN_WIDTH,
+            bin_offset: COMET_BIN_OFFSET,
+            min_mz: 0.0,
+            max_mz,
+            aggregation: BinAggregation::Max,
+            l2_normalize: false,
+        }
+    }
+
+    pub fn n_bins(&self) -> usize {
+        self._raw_bin_index(self.max_mz).max(0.0) as usize + 1
+    }
+
+    /// Index of the bin containing the provided m/z value (None if outside of the [min_mz, max_mz] range)
+    pub fn bin_index(&self, mz: f64) -> Option<usize> {
+        if mz < self.min_mz || mz > self.max_mz {
+            return None
+        }
+        let raw_idx = self._raw_bin_index(mz);
+        if raw_idx < 0.0 { None } else { Some(raw_idx as usize) }
+    }
+
+    fn _raw_bin_index(&self, mz: f64) -> f64 {
+        ((mz - self.min_mz) / self.bin_width + (1.0 - self.bin_offset)).floor()
+    }
+}
+
+impl Default for SpectrumBinningConfig {
+    fn default() -> Self {
+        Self::comet(2000.0)
+    }
+}
+
+/// Sparse representation of a binned spectrum (indices are sorted and unique)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SparseSpectrumVector {
+    pub n_bins: usize,
+    pub indices: Vec<usize>,
+    pub values: Vec<f64>,
+}
+
+impl SparseSpectrumVector {
+    pub fn to_dense(&self) -> Vec<f64> {
+        let mut dense = vec![0.0; self.n_bins];
+        for (&idx, &value) in self.indices.iter().zip(&self.values) {
+            dense[idx] = value;
+        }
+        dense
+    }
+
+    pub fn l2_norm(&self) -> f64 {
+        self.values.iter().map(|v| v * v).sum::<f64>().sqrt()
+    }
+
+    pub fn dot(&self, other: &SparseSpectrumVector) -> f64 {
+        let (mut i, mut j) = (0, 0);
+        let mut dot = 0.0;
+        while i < self.indices.len() && j < other.indices.len() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    dot += self.values[i] * other.values[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        dot
+    }
+
+    pub fn cosine_similarity(&self, other: &SparseSpectrumVector) -> f64 {
+        let norms = self.l2_norm() * other.l2_norm();
+        if norms == 0.0 { 0.0 } else { self.dot(other) / norms }
+    }
+}
+
+pub fn bin_spectrum_sparse(spectrum: &SpectrumData, config: &SpectrumBinningConfig) -> SparseSpectrumVector {
+    let mut binned_values: Vec<(usize, f64)> = spectrum.mz_list.iter().zip(&spectrum.intensity_list)
+        .filter_map(|(&mz, &intensity)| config.bin_index(mz).map(|idx| (idx, intensity as f64)))
+        .collect();
+    binned_values.sort_by_key(|v| v.0);
+
+    let mut indices: Vec<usize> = Vec::with_capacity(binned_values.len());
+    let mut values: Vec<f64> = Vec::with_capacity(binned_values.len());
+    for (bin_idx, intensity) in binned_values {
+        if indices.last() == Some(&bin_idx) {
+            let last_value = values.last_mut().unwrap(); // safe because indices and values have the same length
+            *last_value = match config.aggregation {
+                BinAggregation::Max => last_value.max(intensity),
+                BinAggregation::Sum => *last_value + intensity,
+            };
+        } else {
+            indices.push(bin_idx);
+            values.push(intensity);
+        }
+    }
+
+    let mut sparse_vector = SparseSpectrumVector {
+        n_bins: config.n_bins(),
+        indices,
+        values,
+    };
+
+    if config.l2_normalize {
+        let norm = sparse_vector.l2_norm();
+        if norm > 0.0 {
+            sparse_vector.values.iter_mut().for_each(|v| *v /= norm);
+        }
+    }
+
+    sparse_vector
+}
+
+pub fn bin_spectrum_dense(spectrum: &SpectrumData, config: &SpectrumBinningConfig) -> Vec<f64> {
+    bin_spectrum_sparse(spectrum, config).to_dense()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ms::similarity::pearson_correlation;
+
+    #[test]
+    fn comet_binning() -> Result<()> {
+        let spectrum = SpectrumData {
+            mz_list: vec![100.1, 100.3, 500.2, 2500.0],
+            intensity_list: vec![3.0, 4.0, 10.0, 1.0],
+        };
+
+        let config = SpectrumBinningConfig { aggregation: BinAggregation::Sum, ..SpectrumBinningConfig::comet(1000.0) };
+        let sparse_vector = bin_spectrum_sparse(&spectrum, &config);
+
+        assert_eq!(sparse_vector.indices, vec![config.bin_index(100.1).unwrap(), config.bin_index(500.2).unwrap()]);
+        assert_eq!(sparse_vector.values, vec![7.0, 10.0]);
+        assert_eq!(sparse_vector.to_dense().len(), config.n_bins());
+
+        let normalized_config = SpectrumBinningConfig { l2_normalize: true, ..config };
+        let normalized_vector = bin_spectrum_sparse(&spectrum, &normalized_config);
+        assert!((normalized_vector.l2_norm() - 1.0).abs() < 1e-9);
+        assert!((normalized_vector.cosine_similarity(&sparse_vector) - 1.0).abs() < 1e-9);
+
+        let dense_vector = bin_spectrum_dense(&spectrum, &normalized_config);
+        assert!((pearson_correlation(&dense_vector, &sparse_vector.to_dense()) - 1.0).abs() < 1e-9);
+
+        Ok(())
+    }
+}
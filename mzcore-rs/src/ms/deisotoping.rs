@@ -0,0 +1,309 @@
+//
+// The greedy deisotoping procedure is inspired by [Sage](https://github.com/lazear/sage/blob/master/crates/sage/src/deisotope.rs)
+// Copyright (c) 2022 Michael Lazear
+// SPDX-License-Identifier: MIT
+//
+
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::constants::AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF;
+use crate::chemistry::isotope_distribution::IsotopeDistribution;
+use crate::ms::similarity::vector_cosine_similarity;
+use crate::ms::spectrum::{Peak, SpectrumData};
+use crate::ms::utils::{binary_search_slice, mass_to_mz, mz_to_mass, MassTolWindow};
+
+/// Strategy used to group the isotopic envelopes
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum DeisotopingMode {
+    /// Sage-style: peaks are linked from high to low m/z as soon as their m/z difference matches an isotopic spacing
+    Greedy,
+    /// THRASH-like: envelopes are seeded from the most intense peaks and the best charge/monoisotope hypothesis
+    /// is selected by fitting an averagine isotopic distribution
+    ScoreBased,
+}
+
+/// Values reported in the m/z list of a deconvoluted spectrum
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum DeconvolutionOutput {
+    /// Monoisotopic m/z at the original charge state
+    MonoisotopicMz,
+    /// Monoisotopic m/z converted to a singly charged ion ([M+H]+)
+    SinglyChargedMz,
+    /// Neutral monoisotopic mass
+    NeutralMass,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeisotopingConfig {
+    pub mode: DeisotopingMode,
+    pub mz_tolerance: MassTolWindow,
+    pub min_charge: i8,
+    pub max_charge: i8,
+    pub max_isotopes: usize,
+    /// Minimum averagine fitting score (cosine similarity) required to accept an envelope (ScoreBased mode only)
+    pub min_score: f32,
+    pub output: DeconvolutionOutput,
+}
+
+impl Default for DeisotopingConfig {
+    fn default() -> Self {
+        DeisotopingConfig {
+            mode: DeisotopingMode::Greedy,
+            mz_tolerance: MassTolWindow::ppm(-10.0, 10.0),
+            min_charge: 1,
+            max_charge: 4,
+            max_isotopes: 6,
+            min_score: 0.8,
+            output: DeconvolutionOutput::MonoisotopicMz,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeisotopedPeak {
+    /// Monoisotopic m/z (original m/z if no charge state could be assigned)
+    pub mz: f64,
+    /// Summed intensity of the isotopic envelope
+    pub intensity: f32,
+    pub charge: Option<i8>,
+    pub n_isotopes: usize,
+    /// Averagine fitting score (0 if not computed)
+    pub score: f32,
+}
+
+impl DeisotopedPeak {
+    fn from_peak(peak: &Peak) -> DeisotopedPeak {
+        DeisotopedPeak {
+            mz: peak.mz,
+            intensity: peak.intensity,
+            charge: None,
+            n_isotopes: 1,
+            score: 0.0,
+        }
+    }
+
+    /// Convert the monoisotopic m/z according to the requested output (unassigned peaks are considered as singly charged)
+    pub fn output_value(&self, output: DeconvolutionOutput) -> f64 {
+        let charge = self.charge.unwrap_or(1) as i32;
+        match output {
+            DeconvolutionOutput::MonoisotopicMz => self.mz,
+            DeconvolutionOutput::SinglyChargedMz => mass_to_mz(mz_to_mass(self.mz, charge), 1),
+            DeconvolutionOutput::NeutralMass => mz_to_mass(self.mz, charge),
+        }
+    }
+}
+
+/// Group the isotopic envelopes of a centroided peak list (sorted by m/z) and assign their charge states
+pub fn deisotope_peaks(peaks: &[Peak], config: &DeisotopingConfig) -> Vec<DeisotopedPeak> {
+    let mut deisotoped_peaks = match config.mode {
+        DeisotopingMode::Greedy => _deisotope_greedy(peaks, config),
+        DeisotopingMode::ScoreBased => _deisotope_score_based(peaks, config),
+    };
+
+    deisotoped_peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+    deisotoped_peaks
+}
+
+/// Deisotope a spectrum and report the deconvoluted values defined by `config.output`
+pub fn deconvolute_spectrum(spectrum: &SpectrumData, config: &DeisotopingConfig) -> SpectrumData {
+    let deisotoped_peaks = deisotope_peaks(&spectrum.to_peaks(), config);
+
+    let mut values: Vec<(f64, f32)> = deisotoped_peaks.iter().map(|peak| {
+        (peak.output_value(config.output), peak.intensity)
+    }).collect();
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    SpectrumData {
+        mz_list: values.iter().map(|v| v.0).collect(),
+        intensity_list: values.iter().map(|v| v.1).collect(),
+    }
+}
+
+fn _deisotope_greedy(peaks: &[Peak], config: &DeisotopingConfig) -> Vec<DeisotopedPeak> {
+    let n_peaks = peaks.len();
+    let mut deisotoped: Vec<DeisotopedPeak> = peaks.iter().map(DeisotopedPeak::from_peak).collect();
+    let mut is_isotope = vec![false; n_peaks];
+
+    let min_charge = config.min_charge.max(1);
+
+    // Iterate from the highest m/z: each peak is merged into the lighter peak located one isotopic spacing before
+    for i in (0..n_peaks).rev() {
+        let mz_i = peaks[i].mz;
+        let max_delta = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / min_charge as f64 + _max_abs_tolerance(config.mz_tolerance, mz_i);
+
+        let mut j = i;
+        'lighter_peaks: while j > 0 {
+            j -= 1;
+            if mz_i - peaks[j].mz > max_delta { break }
+
+            for charge in (min_charge..=config.max_charge).rev() {
+                let expected_mz = peaks[j].mz + AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+                if !config.mz_tolerance.contains(expected_mz, mz_i) { continue }
+
+                // Only extend envelopes having a consistent charge state
+                if deisotoped[i].charge.is_some_and(|c| c != charge) { continue }
+                if deisotoped[j].charge.is_some_and(|c| c != charge) { continue }
+
+                deisotoped[j].charge = Some(charge);
+                deisotoped[j].intensity += deisotoped[i].intensity;
+                deisotoped[j].n_isotopes += deisotoped[i].n_isotopes;
+                is_isotope[i] = true;
+
+                break 'lighter_peaks;
+            }
+        }
+    }
+
+    deisotoped.into_iter().zip(is_isotope)
+        .filter(|(_, is_iso)| !is_iso)
+        .map(|(peak, _)| peak)
+        .collect()
+}
+
+fn _deisotope_score_based(peaks: &[Peak], config: &DeisotopingConfig) -> Vec<DeisotopedPeak> {
+    let n_peaks = peaks.len();
+    let max_isotopes = config.max_isotopes.max(2);
+    let min_charge = config.min_charge.max(1);
+
+    let mut intensity_order: Vec<usize> = (0..n_peaks).collect();
+    intensity_order.sort_by(|&a, &b| peaks[b].intensity.total_cmp(&peaks[a].intensity));
+
+    let mut is_used = vec![false; n_peaks];
+    let mut deisotoped = Vec::with_capacity(n_peaks);
+
+    for seed_idx in intensity_order {
+        if is_used[seed_idx] { continue }
+        let seed_mz = peaks[seed_idx].mz;
+
+        // (score, charge, matched peak indices by isotope index)
+        let mut best_hypothesis: Option<(f64, i8, Vec<Option<usize>>)> = None;
+
+        for charge in min_charge..=config.max_charge {
+            let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+
+            // The seed may be any of the first isotopic peaks of the envelope
+            for seed_isotope_idx in 0..max_isotopes.min(3) {
+                let mono_mz = seed_mz - seed_isotope_idx as f64 * isotope_spacing;
+                let mono_mass = mz_to_mass(mono_mz, charge as i32);
+                if mono_mass <= 0.0 { continue }
+
+                let theo_distrib = match IsotopeDistribution::averagine(mono_mass, max_isotopes) {
+                    Result::Ok(distrib) => distrib,
+                    Err(_) => continue,
+                };
+
+                let matched_indices: Vec<Option<usize>> = (0..max_isotopes).map(|isotope_idx| {
+                    let isotope_mz = mono_mz + isotope_idx as f64 * isotope_spacing;
+                    _find_nearest_unused_peak(peaks, &is_used, isotope_mz, config.mz_tolerance)
+                }).collect();
+
+                if matched_indices[seed_isotope_idx] != Some(seed_idx) { continue }
+                if matched_indices.iter().flatten().count() < 2 { continue }
+
+                let observed: Vec<f64> = matched_indices.iter().map(|idx_opt| {
+                    idx_opt.map(|idx| peaks[idx].intensity as f64).unwrap_or(0.0)
+                }).collect();
+                let score = vector_cosine_similarity(&observed, &theo_distrib.abundances);
+
+                let is_better = best_hypothesis.as_ref().is_none_or(|(best_score, _, _)| score > *best_score);
+                if is_better {
+                    best_hypothesis = Some((score, charge, matched_indices));
+                }
+            }
+        }
+
+        match best_hypothesis {
+            Some((score, charge, matched_indices)) if score >= config.min_score as f64 => {
+                let mono_mz = match matched_indices[0] {
+                    Some(mono_idx) => peaks[mono_idx].mz,
+                    None => {
+                        let (first_iso_idx, first_peak_idx) = matched_indices.iter().enumerate()
+                            .find_map(|(iso_idx, idx_opt)| idx_opt.map(|idx| (iso_idx, idx)))
+                            .unwrap(); // safe because at least two peaks have been matched
+                        peaks[first_peak_idx].mz - first_iso_idx as f64 * AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64
+                    }
+                };
+
+                let mut envelope_intensity = 0.0;
+                let mut n_isotopes = 0;
+                for peak_idx in matched_indices.into_iter().flatten() {
+                    is_used[peak_idx] = true;
+                    envelope_intensity += peaks[peak_idx].intensity;
+                    n_isotopes += 1;
+                }
+
+                deisotoped.push(DeisotopedPeak {
+                    mz: mono_mz,
+                    intensity: envelope_intensity,
+                    charge: Some(charge),
+                    n_isotopes,
+                    score: score as f32,
+                });
+            }
+            _ => {
+                is_used[seed_idx] = true;
+                deisotoped.push(DeisotopedPeak::from_peak(&peaks[seed_idx]));
+            }
+        }
+    }
+
+    deisotoped
+}
+
+fn _find_nearest_unused_peak(peaks: &[Peak], is_used: &[bool], mz: f64, tolerance: MassTolWindow) -> Option<usize> {
+    let (lo, hi) = tolerance.bounds(mz);
+    let (i, j) = binary_search_slice(peaks, |peak, query| peak.mz.total_cmp(query), lo, hi);
+
+    (i..j)
+        .filter(|&idx| !is_used[idx] && peaks[idx].mz >= lo && peaks[idx].mz <= hi)
+        .min_by(|&a, &b| (peaks[a].mz - mz).abs().total_cmp(&(peaks[b].mz - mz).abs()))
+}
+
+fn _max_abs_tolerance(tolerance: MassTolWindow, mz: f64) -> f64 {
+    let (lo, hi) = tolerance.bounds(mz);
+    (hi - mz).max(mz - lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _envelope_peaks(mono_mass: f64, charge: i32, intensity: f32) -> Vec<Peak> {
+        let distrib = IsotopeDistribution::averagine(mono_mass, 4).unwrap();
+        distrib.mz_values(charge).into_iter().zip(distrib.abundances.iter())
+            .map(|(mz, ab)| Peak { mz, intensity: intensity * *ab as f32 })
+            .collect()
+    }
+
+    fn _test_spectrum() -> Vec<Peak> {
+        let mut peaks = _envelope_peaks(1500.0, 2, 1000.0);
+        peaks.push(Peak { mz: 300.2, intensity: 50.0 });
+        peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+        peaks
+    }
+
+    #[test]
+    fn greedy_deisotoping() {
+        let config = DeisotopingConfig::default();
+        let deisotoped = deisotope_peaks(&_test_spectrum(), &config);
+
+        assert_eq!(deisotoped.len(), 2);
+        assert_eq!(deisotoped[0].charge, None);
+        assert_eq!(deisotoped[1].charge, Some(2));
+        assert!((deisotoped[1].output_value(DeconvolutionOutput::NeutralMass) - 1500.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn score_based_deisotoping() {
+        let config = DeisotopingConfig {
+            mode: DeisotopingMode::ScoreBased,
+            ..Default::default()
+        };
+        let deisotoped = deisotope_peaks(&_test_spectrum(), &config);
+
+        assert_eq!(deisotoped.len(), 2);
+        assert_eq!(deisotoped[1].charge, Some(2));
+        assert_eq!(deisotoped[1].n_isotopes, 4);
+        assert!(deisotoped[1].score > 0.99);
+    }
+}
@@ -1,8 +1,17 @@
+pub mod binning;
+pub mod deisotoping;
 pub mod mass_calc;
+pub mod precursor;
+pub mod preprocessing;
 pub mod spectrum;
 pub mod processing;
+pub mod recalibration;
+pub mod similarity;
 pub mod utils;
 
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum MassType {
    Monoisotopic,
    Average
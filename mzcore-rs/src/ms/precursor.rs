@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::constants::AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF;
+use crate::chemistry::isotope_distribution::IsotopeDistribution;
+use crate::ms::processing::select_most_intense_peak;
+use crate::ms::similarity::vector_cosine_similarity;
+use crate::ms::spectrum::{Peak, Precursor};
+use crate::ms::utils::{mz_to_mass, MassTolWindow};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PrecursorRefinementConfig {
+    pub mz_tolerance: MassTolWindow,
+    /// Charge states tested when the charge is re-estimated
+    pub min_charge: i8,
+    pub max_charge: i8,
+    /// Maximum number of isotopic peaks between the reported m/z and the true monoisotopic peak
+    pub max_isotope_offset: u8,
+    /// Number of isotopic peaks used to fit the averagine envelope
+    pub max_isotopes: usize,
+    /// Minimum averagine fitting score (cosine similarity)
+    pub min_score: f32,
+}
+
+impl Default for PrecursorRefinementConfig {
+    fn default() -> Self {
+        PrecursorRefinementConfig {
+            mz_tolerance: MassTolWindow::ppm(-10.0, 10.0),
+            min_charge: 1,
+            max_charge: 6,
+            max_isotope_offset: 3,
+            max_isotopes: 5,
+            min_score: 0.7,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RefinedPrecursor {
+    pub mono_mz: f64,
+    pub charge: i8,
+    /// Number of isotopic peaks between the reported m/z and the monoisotopic peak (0 if the reported m/z was correct)
+    pub isotope_offset: u8,
+    pub score: f32,
+    /// Fraction of the isolation window intensity belonging to the precursor isotopic envelope
+    pub isolation_purity: Option<f32>,
+}
+
+impl RefinedPrecursor {
+    pub fn mono_mass(&self) -> f64 {
+        mz_to_mass(self.mono_mz, self.charge as i32)
+    }
+}
+
+/// Detect the monoisotopic peak of a precursor in its MS1 spectrum (peaks sorted by m/z) by fitting averagine envelopes.
+/// The reported charge is kept when its fit is as good as the best alternative charge state.
+/// The isolation purity is computed if an isolation window (lower and upper m/z bounds) is provided.
+pub fn refine_precursor(
+    ms1_peaks: &[Peak],
+    precursor: &Precursor,
+    isolation_window: Option<(f64, f64)>,
+    config: &PrecursorRefinementConfig,
+) -> Option<RefinedPrecursor> {
+
+    // Reported peak must be present in the MS1 spectrum
+    select_most_intense_peak(ms1_peaks, precursor.mz, config.mz_tolerance, None)?;
+
+    let mut best_refinement: Option<RefinedPrecursor> = None;
+    for charge in config.min_charge.max(1)..=config.max_charge {
+        let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+
+        for isotope_offset in 0..=config.max_isotope_offset {
+            let mono_mz = precursor.mz - isotope_offset as f64 * isotope_spacing;
+            let observed = _observed_envelope(ms1_peaks, mono_mz, charge, config.max_isotopes.max(isotope_offset as usize + 1), config.mz_tolerance);
+
+            // The monoisotopic peak must be observed
+            if observed[0] <= 0.0 { continue }
+
+            let mono_mass = mz_to_mass(mono_mz, charge as i32);
+            let Result::Ok(theo_distrib) = IsotopeDistribution::averagine(mono_mass, observed.len()) else { continue };
+
+            let score = vector_cosine_similarity(&observed, &theo_distrib.abundances) as f32;
+            if score < config.min_score { continue }
+
+            let is_reported_charge = precursor.charge == Some(charge);
+            let is_better = match best_refinement {
+                None => true,
+                Some(best) => {
+                    let best_is_reported_charge = precursor.charge == Some(best.charge);
+                    if is_reported_charge != best_is_reported_charge && (score - best.score).abs() < 0.01 {
+                        is_reported_charge
+                    } else {
+                        score > best.score
+                    }
+                }
+            };
+
+            if is_better {
+                best_refinement = Some(RefinedPrecursor {
+                    mono_mz,
+                    charge,
+                    isotope_offset,
+                    score,
+                    isolation_purity: None,
+                });
+            }
+        }
+    }
+
+    best_refinement.map(|mut refinement| {
+        refinement.isolation_purity = isolation_window.map(|window| {
+            compute_isolation_purity(ms1_peaks, refinement.mono_mz, refinement.charge, window, config.mz_tolerance, config.max_isotopes)
+        });
+        refinement
+    })
+}
+
+/// Fraction of the intensity observed in the isolation window which belongs to the isotopic envelope of the target
+pub fn compute_isolation_purity(
+    ms1_peaks: &[Peak],
+    mono_mz: f64,
+    charge: i8,
+    isolation_window: (f64, f64),
+    mz_tolerance: MassTolWindow,
+    max_isotopes: usize,
+) -> f32 {
+    let (lo, hi) = isolation_window;
+    let total_intensity: f32 = ms1_peaks.iter()
+        .filter(|peak| peak.mz >= lo && peak.mz <= hi)
+        .map(|peak| peak.intensity)
+        .sum();
+
+    if total_intensity <= 0.0 {
+        return 0.0
+    }
+
+    let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge.max(1) as f64;
+    let target_intensity: f32 = (0..max_isotopes)
+        .filter_map(|isotope_idx| {
+            select_most_intense_peak(ms1_peaks, mono_mz + isotope_idx as f64 * isotope_spacing, mz_tolerance, None)
+        })
+        .filter(|peak| peak.mz >= lo && peak.mz <= hi)
+        .map(|peak| peak.intensity)
+        .sum();
+
+    (target_intensity / total_intensity).min(1.0)
+}
+
+fn _observed_envelope(ms1_peaks: &[Peak], mono_mz: f64, charge: i8, n_isotopes: usize, mz_tolerance: MassTolWindow) -> Vec<f64> {
+    let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+    (0..n_isotopes).map(|isotope_idx| {
+        select_most_intense_peak(ms1_peaks, mono_mz + isotope_idx as f64 * isotope_spacing, mz_tolerance, None)
+            .map(|peak| peak.intensity as f64)
+            .unwrap_or(0.0)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monoisotopic_peak_correction() {
+        let distrib = IsotopeDistribution::averagine(2400.0, 5).unwrap();
+        let mut ms1_peaks: Vec<Peak> = distrib.mz_values(3).into_iter().zip(distrib.abundances.iter())
+            .map(|(mz, ab)| Peak { mz, intensity: 1000.0 * *ab as f32 })
+            .collect();
+        let mono_mz = ms1_peaks[0].mz;
+
+        // Co-isolated interference
+        ms1_peaks.push(Peak { mz: mono_mz + 0.8, intensity: 100.0 });
+        ms1_peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+
+        // The instrument reported the M+1 peak with a wrong charge state
+        let precursor = Precursor { mz: distrib.mz_values(3)[1], charge: Some(2), intensity: None };
+        let isolation_window = (precursor.mz - 1.0, precursor.mz + 1.0);
+
+        let refined = refine_precursor(&ms1_peaks, &precursor, Some(isolation_window), &PrecursorRefinementConfig::default()).unwrap();
+
+        assert_eq!(refined.charge, 3);
+        assert_eq!(refined.isotope_offset, 1);
+        assert!((refined.mono_mass() - 2400.0).abs() < 0.001);
+
+        let purity = refined.isolation_purity.unwrap();
+        assert!(purity > 0.8 && purity < 1.0, "unexpected isolation purity {}", purity);
+    }
+}
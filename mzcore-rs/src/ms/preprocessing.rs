@@ -0,0 +1,311 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::constants::{H2O_MONO_MASS, NH3_MONO_MASS};
+use crate::ms::spectrum::{Peak, Precursor, SpectrumData};
+use crate::ms::utils::{mass_to_mz, mz_to_mass, MassTolWindow};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum IntensityTransform {
+    Sqrt,
+    /// Natural logarithm of (1 + intensity)
+    Log,
+    /// Intensities are replaced by their rank (the least intense peak has a rank of 1)
+    Rank,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum IntensityNormalization {
+    /// Intensities are divided by the total ion current
+    Tic,
+    /// Intensities are divided by the base peak intensity
+    BasePeak,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NoiseEstimation {
+    /// Median intensity of the whole spectrum
+    Median,
+    /// Median intensity computed in consecutive m/z windows of a given width
+    DynamicMedian { window_width: f64 },
+}
+
+/// A single spectrum processing step.
+/// All processors expect peaks sorted by m/z and preserve this ordering.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SpectrumProcessor {
+    /// Remove the peaks matching the precursor m/z (and the precursor water/ammonia losses)
+    /// at the precursor charge state and, optionally, at all reduced charge states
+    RemovePrecursorPeaks { mz_tolerance: MassTolWindow, include_charge_reduced: bool, include_neutral_losses: bool },
+    /// Keep the peaks having an m/z inside [min_mz, max_mz]
+    ClipMzRange { min_mz: f64, max_mz: f64 },
+    /// Remove the peaks having an m/z inside [min_mz, max_mz] (e.g. low-mass or reporter ion region)
+    RemoveMzRegion { min_mz: f64, max_mz: f64 },
+    /// Keep the N most intense peaks
+    TopN { n: usize },
+    /// Keep the N most intense peaks of consecutive m/z windows
+    TopNPerWindow { n: usize, window_width: f64 },
+    /// Keep the peaks having a signal-to-noise ratio greater than or equal to `min_signal_to_noise`
+    NoiseFilter { estimation: NoiseEstimation, min_signal_to_noise: f32 },
+    TransformIntensities(IntensityTransform),
+    NormalizeIntensities(IntensityNormalization),
+}
+
+impl SpectrumProcessor {
+
+    /// Remove the low-mass region (including isobaric reporter ions)
+    pub fn low_mass_removal(max_mz: f64) -> SpectrumProcessor {
+        SpectrumProcessor::RemoveMzRegion { min_mz: 0.0, max_mz }
+    }
+
+    pub fn process(&self, peaks: Vec<Peak>, precursor: Option<&Precursor>) -> Vec<Peak> {
+        use SpectrumProcessor::*;
+
+        match *self {
+            RemovePrecursorPeaks { mz_tolerance, include_charge_reduced, include_neutral_losses } => {
+                match precursor {
+                    Some(prec) => remove_precursor_peaks(peaks, prec, mz_tolerance, include_charge_reduced, include_neutral_losses),
+                    None => peaks,
+                }
+            }
+            ClipMzRange { min_mz, max_mz } => {
+                peaks.into_iter().filter(|peak| peak.mz >= min_mz && peak.mz <= max_mz).collect()
+            }
+            RemoveMzRegion { min_mz, max_mz } => {
+                peaks.into_iter().filter(|peak| peak.mz < min_mz || peak.mz > max_mz).collect()
+            }
+            TopN { n } => keep_top_n_peaks(peaks, n),
+            TopNPerWindow { n, window_width } => keep_top_n_peaks_per_window(peaks, n, window_width),
+            NoiseFilter { estimation, min_signal_to_noise } => {
+                let noise_levels = estimate_noise_levels(&peaks, estimation);
+                peaks.into_iter().zip(noise_levels)
+                    .filter(|(peak, noise)| *noise <= 0.0 || peak.intensity / noise >= min_signal_to_noise)
+                    .map(|(peak, _)| peak)
+                    .collect()
+            }
+            TransformIntensities(transform) => transform_intensities(peaks, transform),
+            NormalizeIntensities(normalization) => normalize_intensities(peaks, normalization),
+        }
+    }
+}
+
+/// An ordered list of processors, serializable to record the exact settings used to process the spectra of an experiment
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpectrumProcessingPipeline {
+    pub processors: Vec<SpectrumProcessor>,
+}
+
+impl SpectrumProcessingPipeline {
+    pub fn new(processors: Vec<SpectrumProcessor>) -> SpectrumProcessingPipeline {
+        SpectrumProcessingPipeline { processors }
+    }
+
+    #[must_use]
+    pub fn then(mut self, processor: SpectrumProcessor) -> SpectrumProcessingPipeline {
+        self.processors.push(processor);
+        self
+    }
+
+    pub fn process_peaks(&self, peaks: &[Peak], precursor: Option<&Precursor>) -> Vec<Peak> {
+        self.processors.iter().fold(peaks.to_vec(), |processed_peaks, processor| {
+            processor.process(processed_peaks, precursor)
+        })
+    }
+
+    pub fn process_spectrum(&self, spectrum: &SpectrumData, precursor: Option<&Precursor>) -> SpectrumData {
+        SpectrumData::from_peaks(&self.process_peaks(&spectrum.to_peaks(), precursor))
+    }
+}
+
+pub fn remove_precursor_peaks(
+    peaks: Vec<Peak>,
+    precursor: &Precursor,
+    mz_tolerance: MassTolWindow,
+    include_charge_reduced: bool,
+    include_neutral_losses: bool
+) -> Vec<Peak> {
+    let precursor_charge = precursor.charge.unwrap_or(1).max(1) as i32;
+    let precursor_mass = mz_to_mass(precursor.mz, precursor_charge);
+
+    let charges: Vec<i32> = if include_charge_reduced {
+        (1..=precursor_charge).collect()
+    } else {
+        vec![precursor_charge]
+    };
+
+    let mut masses = vec![precursor_mass];
+    if include_neutral_losses {
+        masses.push(precursor_mass - H2O_MONO_MASS);
+        masses.push(precursor_mass - NH3_MONO_MASS);
+    }
+
+    let precursor_mz_values: Vec<f64> = charges.iter()
+        .flat_map(|&charge| masses.iter().map(move |&mass| mass_to_mz(mass, charge)))
+        .collect();
+
+    peaks.into_iter().filter(|peak| {
+        !precursor_mz_values.iter().any(|&prec_mz| mz_tolerance.contains(prec_mz, peak.mz))
+    }).collect()
+}
+
+pub fn keep_top_n_peaks(peaks: Vec<Peak>, n: usize) -> Vec<Peak> {
+    if peaks.len() <= n {
+        return peaks
+    }
+
+    let mut intensity_order: Vec<usize> = (0..peaks.len()).collect();
+    intensity_order.sort_by(|&a, &b| peaks[b].intensity.total_cmp(&peaks[a].intensity));
+
+    let mut is_kept = vec![false; peaks.len()];
+    intensity_order.into_iter().take(n).for_each(|idx| is_kept[idx] = true);
+
+    peaks.into_iter().zip(is_kept).filter(|(_, kept)| *kept).map(|(peak, _)| peak).collect()
+}
+
+pub fn keep_top_n_peaks_per_window(peaks: Vec<Peak>, n: usize, window_width: f64) -> Vec<Peak> {
+    if peaks.is_empty() || window_width <= 0.0 {
+        return peaks
+    }
+
+    let mut kept_peaks = Vec::with_capacity(peaks.len());
+    for window_peaks in _split_in_mz_windows(&peaks, window_width) {
+        kept_peaks.extend(keep_top_n_peaks(window_peaks.to_vec(), n));
+    }
+
+    kept_peaks
+}
+
+/// Estimate the noise level of each peak
+pub fn estimate_noise_levels(peaks: &[Peak], estimation: NoiseEstimation) -> Vec<f32> {
+    match estimation {
+        NoiseEstimation::Median => {
+            let noise = median_intensity(peaks);
+            vec![noise; peaks.len()]
+        }
+        NoiseEstimation::DynamicMedian { window_width } => {
+            if window_width <= 0.0 {
+                return estimate_noise_levels(peaks, NoiseEstimation::Median)
+            }
+            _split_in_mz_windows(peaks, window_width).into_iter().flat_map(|window_peaks| {
+                let noise = median_intensity(window_peaks);
+                std::iter::repeat_n(noise, window_peaks.len())
+            }).collect()
+        }
+    }
+}
+
+pub fn median_intensity(peaks: &[Peak]) -> f32 {
+    if peaks.is_empty() {
+        return 0.0
+    }
+
+    let mut intensities: Vec<f32> = peaks.iter().map(|peak| peak.intensity).collect();
+    intensities.sort_by(|a, b| a.total_cmp(b));
+
+    let mid = intensities.len() / 2;
+    if intensities.len().is_multiple_of(2) {
+        (intensities[mid - 1] + intensities[mid]) / 2.0
+    } else {
+        intensities[mid]
+    }
+}
+
+pub fn transform_intensities(mut peaks: Vec<Peak>, transform: IntensityTransform) -> Vec<Peak> {
+    match transform {
+        IntensityTransform::Sqrt => peaks.iter_mut().for_each(|peak| peak.intensity = peak.intensity.max(0.0).sqrt()),
+        IntensityTransform::Log => peaks.iter_mut().for_each(|peak| peak.intensity = peak.intensity.max(0.0).ln_1p()),
+        IntensityTransform::Rank => {
+            let mut intensity_order: Vec<usize> = (0..peaks.len()).collect();
+            intensity_order.sort_by(|&a, &b| peaks[a].intensity.total_cmp(&peaks[b].intensity));
+            for (rank, peak_idx) in intensity_order.into_iter().enumerate() {
+                peaks[peak_idx].intensity = (rank + 1) as f32;
+            }
+        }
+    }
+
+    peaks
+}
+
+pub fn normalize_intensities(mut peaks: Vec<Peak>, normalization: IntensityNormalization) -> Vec<Peak> {
+    let divisor = match normalization {
+        IntensityNormalization::Tic => peaks.iter().map(|peak| peak.intensity).sum::<f32>(),
+        IntensityNormalization::BasePeak => peaks.iter().map(|peak| peak.intensity).fold(0.0, f32::max),
+    };
+
+    if divisor > 0.0 {
+        peaks.iter_mut().for_each(|peak| peak.intensity /= divisor);
+    }
+
+    peaks
+}
+
+// Split peaks (sorted by m/z) in consecutive windows of a given width, starting at the first peak m/z
+fn _split_in_mz_windows(peaks: &[Peak], window_width: f64) -> Vec<&[Peak]> {
+    let mut windows = Vec::new();
+    if peaks.is_empty() {
+        return windows
+    }
+
+    let first_mz = peaks[0].mz;
+    let mut window_start_idx = 0;
+    let mut cur_window_idx = 0;
+    for (peak_idx, peak) in peaks.iter().enumerate() {
+        let window_idx = ((peak.mz - first_mz) / window_width) as usize;
+        if window_idx != cur_window_idx {
+            windows.push(&peaks[window_start_idx..peak_idx]);
+            window_start_idx = peak_idx;
+            cur_window_idx = window_idx;
+        }
+    }
+    windows.push(&peaks[window_start_idx..]);
+
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _test_peaks() -> Vec<Peak> {
+        vec![
+            Peak { mz: 126.13, intensity: 500.0 },
+            Peak { mz: 200.0, intensity: 10.0 },
+            Peak { mz: 250.0, intensity: 30.0 },
+            Peak { mz: 401.0, intensity: 20.0 },
+            Peak { mz: 450.0, intensity: 40.0 },
+            Peak { mz: 501.0, intensity: 1000.0 },
+            Peak { mz: 650.0, intensity: 5.0 },
+        ]
+    }
+
+    #[test]
+    fn processing_pipeline() {
+        let precursor = Precursor { mz: 501.0, charge: Some(2), intensity: None };
+
+        let pipeline = SpectrumProcessingPipeline::default()
+            .then(SpectrumProcessor::low_mass_removal(150.0))
+            .then(SpectrumProcessor::RemovePrecursorPeaks { mz_tolerance: MassTolWindow::Da(-0.02, 0.02), include_charge_reduced: false, include_neutral_losses: false })
+            .then(SpectrumProcessor::TopNPerWindow { n: 1, window_width: 200.0 })
+            .then(SpectrumProcessor::NormalizeIntensities(IntensityNormalization::BasePeak));
+
+        let processed_peaks = pipeline.process_peaks(&_test_peaks(), Some(&precursor));
+        let mz_values: Vec<f64> = processed_peaks.iter().map(|peak| peak.mz).collect();
+
+        assert_eq!(mz_values, vec![250.0, 450.0, 650.0]);
+        assert_eq!(processed_peaks[1].intensity, 1.0);
+    }
+
+    #[test]
+    fn rank_transform_and_noise() {
+        let ranked_peaks = transform_intensities(_test_peaks(), IntensityTransform::Rank);
+        assert_eq!(ranked_peaks[5].intensity, 7.0);
+        assert_eq!(ranked_peaks[6].intensity, 1.0);
+
+        assert_eq!(median_intensity(&_test_peaks()), 30.0);
+
+        let filtered_peaks = SpectrumProcessor::NoiseFilter {
+            estimation: NoiseEstimation::Median,
+            min_signal_to_noise: 3.0
+        }.process(_test_peaks(), None);
+        assert_eq!(filtered_peaks.len(), 2);
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::regression::{RegressionMethod, RegressionModel};
+use crate::common::stats::*;
+use crate::ms::spectrum::SpectrumData;
+use crate::ms::utils::MassTolWindow;
+use crate::msms::annotator::MatchedPeak;
+
+/// Mass error of a confident match (precursor or fragment ion)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MassErrorObservation {
+    /// Observed m/z value
+    pub mz: f64,
+    /// (observed - theoretical) / theoretical, in ppm
+    pub ppm_error: f64,
+    pub rt: Option<f32>,
+    pub intensity: Option<f32>,
+}
+
+impl MassErrorObservation {
+    pub fn new(observed_mz: f64, theoretical_mz: f64, rt: Option<f32>, intensity: Option<f32>) -> MassErrorObservation {
+        MassErrorObservation {
+            mz: observed_mz,
+            ppm_error: (observed_mz - theoretical_mz) / theoretical_mz * 1e6,
+            rt,
+            intensity,
+        }
+    }
+
+    pub fn from_matched_peak(matched_peak: &MatchedPeak, rt: Option<f32>) -> MassErrorObservation {
+        MassErrorObservation {
+            mz: matched_peak.peak_mz,
+            ppm_error: matched_peak.mz_error as f64 / matched_peak.theo_mz * 1e6,
+            rt,
+            intensity: Some(matched_peak.peak_intensity as f32),
+        }
+    }
+}
+
+/// Summary statistics of a ppm error distribution
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MassErrorStatistics {
+    pub n_observations: usize,
+    pub mean_ppm: f64,
+    pub median_ppm: f64,
+    pub std_dev_ppm: f64,
+    /// Standard deviation estimated from the median absolute deviation (robust to outliers)
+    pub robust_std_dev_ppm: f64,
+    pub q025_ppm: f64,
+    pub q975_ppm: f64,
+}
+
+impl MassErrorStatistics {
+    /// Returns None if less than two errors are provided
+    pub fn compute(ppm_errors: &[f64]) -> Option<MassErrorStatistics> {
+        Some(MassErrorStatistics {
+            n_observations: ppm_errors.len(),
+            mean_ppm: mean(ppm_errors)?,
+            median_ppm: median(ppm_errors)?,
+            std_dev_ppm: standard_deviation(ppm_errors)?,
+            robust_std_dev_ppm: robust_standard_deviation(ppm_errors)?,
+            q025_ppm: quantile(ppm_errors, 0.025)?,
+            q975_ppm: quantile(ppm_errors, 0.975)?,
+        })
+    }
+
+    /// Data-driven tolerance window centered on the median error and spanning +/- `n_std_devs` robust standard deviations.
+    /// Precursor and fragment tolerances should be obtained from their own error distributions, ideally after recalibration.
+    pub fn suggest_tolerance(&self, n_std_devs: f64) -> MassTolWindow {
+        let half_width = n_std_devs * self.robust_std_dev_ppm;
+        MassTolWindow::ppm(self.median_ppm - half_width, self.median_ppm + half_width)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecalibrationConfig {
+    pub method: RegressionMethod,
+    /// Model the error as a function of m/z
+    pub use_mz: bool,
+    /// Model the error as a function of retention time (ignored if some observations have no RT)
+    pub use_rt: bool,
+    /// Model the error as a function of log10 intensity (ignored if some observations have no intensity)
+    pub use_intensity: bool,
+    /// Observations having a larger absolute error are discarded before fitting
+    pub max_abs_ppm_error: f64,
+    pub min_observations: usize,
+}
+
+impl Default for RecalibrationConfig {
+    fn default() -> Self {
+        RecalibrationConfig {
+            method: RegressionMethod::Lowess { span: 0.3, robustness_iterations: 3 },
+            use_mz: true,
+            use_rt: true,
+            use_intensity: false,
+            max_abs_ppm_error: 50.0,
+            min_observations: 20,
+        }
+    }
+}
+
+/// Additive model of the ppm error: a global offset plus one regression model per explanatory variable
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MassRecalibrationModel {
+    pub offset_ppm: f64,
+    pub mz_model: Option<RegressionModel>,
+    pub rt_model: Option<RegressionModel>,
+    pub intensity_model: Option<RegressionModel>,
+}
+
+impl MassRecalibrationModel {
+
+    /// Fit the model by successively regressing the residual errors against m/z, RT and intensity
+    pub fn fit(observations: &[MassErrorObservation], config: &RecalibrationConfig) -> Result<MassRecalibrationModel> {
+        let observations: Vec<&MassErrorObservation> = observations.iter()
+            .filter(|obs| obs.ppm_error.is_finite() && obs.ppm_error.abs() <= config.max_abs_ppm_error)
+            .collect();
+
+        if observations.len() < config.min_observations.max(2) {
+            bail!("not enough mass error observations to fit a recalibration model ({} < {})", observations.len(), config.min_observations.max(2))
+        }
+
+        let mut residuals: Vec<f64> = observations.iter().map(|obs| obs.ppm_error).collect();
+        let offset_ppm = median(&residuals).unwrap(); // safe because there are at least two observations
+        residuals.iter_mut().for_each(|r| *r -= offset_ppm);
+
+        let mz_values: Option<Vec<f64>> = if config.use_mz {
+            Some(observations.iter().map(|obs| obs.mz).collect())
+        } else { None };
+        let rt_values: Option<Vec<f64>> = if config.use_rt {
+            observations.iter().map(|obs| obs.rt.map(|rt| rt as f64)).collect()
+        } else { None };
+        let intensity_values: Option<Vec<f64>> = if config.use_intensity {
+            observations.iter().map(|obs| obs.intensity.filter(|ab| *ab > 0.0).map(|ab| (ab as f64).log10())).collect()
+        } else { None };
+
+        let mz_model = _fit_residuals(mz_values.as_deref(), &mut residuals, config.method)?;
+        let rt_model = _fit_residuals(rt_values.as_deref(), &mut residuals, config.method)?;
+        let intensity_model = _fit_residuals(intensity_values.as_deref(), &mut residuals, config.method)?;
+
+        Ok(MassRecalibrationModel {
+            offset_ppm,
+            mz_model,
+            rt_model,
+            intensity_model,
+        })
+    }
+
+    /// Predicted ppm error (variables which are not provided are not taken into account)
+    pub fn predict_ppm_error(&self, mz: f64, rt: Option<f32>, intensity: Option<f32>) -> f64 {
+        let mut ppm_error = self.offset_ppm;
+        if let Some(model) = &self.mz_model {
+            ppm_error += model.predict(mz);
+        }
+        if let (Some(model), Some(rt)) = (&self.rt_model, rt) {
+            ppm_error += model.predict(rt as f64);
+        }
+        if let (Some(model), Some(intensity)) = (&self.intensity_model, intensity.filter(|ab| *ab > 0.0)) {
+            ppm_error += model.predict((intensity as f64).log10());
+        }
+        ppm_error
+    }
+
+    pub fn recalibrate_mz(&self, mz: f64, rt: Option<f32>, intensity: Option<f32>) -> f64 {
+        mz / (1.0 + self.predict_ppm_error(mz, rt, intensity) * 1e-6)
+    }
+
+    /// Ppm errors remaining after recalibration
+    pub fn recalibrated_ppm_errors(&self, observations: &[MassErrorObservation]) -> Vec<f64> {
+        observations.iter().map(|obs| {
+            obs.ppm_error - self.predict_ppm_error(obs.mz, obs.rt, obs.intensity)
+        }).collect()
+    }
+
+    /// Apply the correction to all the peaks of a spectrum acquired at the provided RT
+    pub fn recalibrate_spectrum(&self, spectrum: &SpectrumData, rt: Option<f32>) -> SpectrumData {
+        let mz_list = spectrum.mz_list.iter().zip(&spectrum.intensity_list)
+            .map(|(&mz, &intensity)| self.recalibrate_mz(mz, rt, Some(intensity)))
+            .collect();
+
+        SpectrumData {
+            mz_list,
+            intensity_list: spectrum.intensity_list.clone(),
+        }
+    }
+}
+
+fn _fit_residuals(values: Option<&[f64]>, residuals: &mut [f64], method: RegressionMethod) -> Result<Option<RegressionModel>> {
+    let Some(values) = values else { return Ok(None) };
+
+    // A variable without any spread can't explain the errors
+    let (min, max) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)));
+    if max <= min {
+        return Ok(None)
+    }
+
+    let model = RegressionModel::fit(values, residuals, method)?;
+    for (residual, &value) in residuals.iter_mut().zip(values) {
+        *residual -= model.predict(value);
+    }
+
+    Ok(Some(model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mz_and_rt_dependent_recalibration() -> Result<()> {
+        let observations: Vec<MassErrorObservation> = (0..500).map(|i| {
+            let theo_mz = 300.0 + (i * 37 % 1500) as f64;
+            let rt = (i * 13 % 120) as f32;
+            // Systematic error: 3 ppm offset, drifting with m/z and RT, plus some noise
+            let ppm_error = 3.0 + 0.002 * (theo_mz - 1000.0) + 0.05 * rt as f64 + ((i * 7919 % 100) as f64 / 100.0 - 0.5);
+            MassErrorObservation::new(theo_mz * (1.0 + ppm_error * 1e-6), theo_mz, Some(rt), None)
+        }).collect();
+
+        let stats_before = MassErrorStatistics::compute(&observations.iter().map(|obs| obs.ppm_error).collect::<Vec<f64>>()).unwrap();
+        assert!(stats_before.median_ppm > 5.0);
+
+        let model = MassRecalibrationModel::fit(&observations, &RecalibrationConfig::default())?;
+        let stats_after = MassErrorStatistics::compute(&model.recalibrated_ppm_errors(&observations)).unwrap();
+        assert!(stats_after.median_ppm.abs() < 0.2);
+        assert!(stats_after.robust_std_dev_ppm < stats_before.robust_std_dev_ppm / 2.0);
+
+        let MassTolWindow::ppm(lo, hi) = stats_after.suggest_tolerance(3.0) else { panic!("ppm window expected") };
+        assert!(lo < 0.0 && hi > 0.0 && hi - lo < 4.0);
+
+        let spectrum = SpectrumData { mz_list: vec![500.0, 1000.0], intensity_list: vec![1.0, 1.0] };
+        let recalibrated_spectrum = model.recalibrate_spectrum(&spectrum, Some(60.0));
+        assert!(recalibrated_spectrum.mz_list[1] < 1000.0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use crate::ms::spectrum::Peak;
+use crate::ms::utils::{binary_search_slice, MassTolWindow};
+
+/// Align the peaks of two spectra (sorted by m/z) by pairing peaks located within the m/z tolerance.
+/// Each peak is used at most once: candidate pairs are assigned by decreasing intensity product.
+/// Returns the (lhs index, rhs index) pairs sorted by lhs index.
+pub fn align_peaks(lhs: &[Peak], rhs: &[Peak], tolerance: MassTolWindow) -> Vec<(usize, usize)> {
+    let mut candidate_pairs = Vec::new();
+    for (lhs_idx, lhs_peak) in lhs.iter().enumerate() {
+        let (lo, hi) = tolerance.bounds(lhs_peak.mz);
+        let (i, j) = binary_search_slice(rhs, |peak, query| peak.mz.total_cmp(query), lo, hi);
+
+        for (rhs_idx, rhs_peak) in rhs.iter().enumerate().take(j).skip(i) {
+            if rhs_peak.mz >= lo && rhs_peak.mz <= hi {
+                candidate_pairs.push((lhs_idx, rhs_idx, lhs_peak.intensity as f64 * rhs_peak.intensity as f64));
+            }
+        }
+    }
+
+    candidate_pairs.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    let mut is_lhs_matched = vec![false; lhs.len()];
+    let mut is_rhs_matched = vec![false; rhs.len()];
+    let mut matched_pairs = Vec::with_capacity(lhs.len().min(rhs.len()));
+    for (lhs_idx, rhs_idx, _) in candidate_pairs {
+        if !is_lhs_matched[lhs_idx] && !is_rhs_matched[rhs_idx] {
+            is_lhs_matched[lhs_idx] = true;
+            is_rhs_matched[rhs_idx] = true;
+            matched_pairs.push((lhs_idx, rhs_idx));
+        }
+    }
+
+    matched_pairs.sort_unstable();
+    matched_pairs
+}
+
+/// Normalized dot product (cosine similarity) of two spectra, unmatched peaks contributing to the vector norms
+pub fn cosine_similarity(lhs: &[Peak], rhs: &[Peak], tolerance: MassTolWindow) -> f64 {
+    let matched_pairs = align_peaks(lhs, rhs, tolerance);
+
+    let dot: f64 = matched_pairs.iter().map(|&(i, j)| lhs[i].intensity as f64 * rhs[j].intensity as f64).sum();
+    let norm_lhs = _l2_norm(lhs);
+    let norm_rhs = _l2_norm(rhs);
+
+    if norm_lhs == 0.0 || norm_rhs == 0.0 {
+        0.0
+    } else {
+        dot / (norm_lhs * norm_rhs)
+    }
+}
+
+/// Cosine similarity of two vectors of the same length (e.g. binned spectra or isotopic envelopes)
+pub fn vector_cosine_similarity(lhs: &[f64], rhs: &[f64]) -> f64 {
+    let dot: f64 = lhs.iter().zip(rhs).map(|(a, b)| a * b).sum();
+    let norms = lhs.iter().map(|a| a * a).sum::<f64>().sqrt() * rhs.iter().map(|b| b * b).sum::<f64>().sqrt();
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// Spectral contrast angle expressed as a similarity in [0, 1] (1 - 2θ/π)
+pub fn spectral_contrast_angle(lhs: &[Peak], rhs: &[Peak], tolerance: MassTolWindow) -> f64 {
+    let cosine = cosine_similarity(lhs, rhs, tolerance).clamp(-1.0, 1.0);
+    1.0 - 2.0 * cosine.acos() / std::f64::consts::PI
+}
+
+/// Pearson correlation coefficient of two vectors of the same length (e.g. binned spectra)
+pub fn pearson_correlation(lhs: &[f64], rhs: &[f64]) -> f64 {
+    let n = lhs.len().min(rhs.len());
+    if n == 0 {
+        return 0.0
+    }
+
+    let mean_lhs = lhs[..n].iter().sum::<f64>() / n as f64;
+    let mean_rhs = rhs[..n].iter().sum::<f64>() / n as f64;
+
+    let mut cov = 0.0;
+    let mut var_lhs = 0.0;
+    let mut var_rhs = 0.0;
+    for (a, b) in lhs[..n].iter().zip(&rhs[..n]) {
+        let (da, db) = (a - mean_lhs, b - mean_rhs);
+        cov += da * db;
+        var_lhs += da * da;
+        var_rhs += db * db;
+    }
+
+    if var_lhs == 0.0 || var_rhs == 0.0 {
+        0.0
+    } else {
+        cov / (var_lhs * var_rhs).sqrt()
+    }
+}
+
+/// Spearman rank correlation coefficient of two vectors of the same length (ties receive their average rank)
+pub fn spearman_correlation(lhs: &[f64], rhs: &[f64]) -> f64 {
+    let n = lhs.len().min(rhs.len());
+    pearson_correlation(&_average_ranks(&lhs[..n]), &_average_ranks(&rhs[..n]))
+}
+
+/// Shannon entropy of a spectrum whose intensities are normalized to a sum of 1
+pub fn spectral_entropy(peaks: &[Peak]) -> f64 {
+    _entropy(&_normalized_intensities(peaks))
+}
+
+/// Unweighted spectral entropy similarity (Li et al., Nature Methods, 2021)
+pub fn entropy_similarity(lhs: &[Peak], rhs: &[Peak], tolerance: MassTolWindow) -> f64 {
+    _entropy_similarity(lhs, rhs, tolerance, false)
+}
+
+/// Weighted spectral entropy similarity: intensities of low-entropy spectra are raised to a power
+/// depending on their entropy before computing the similarity (Li et al., Nature Methods, 2021)
+pub fn weighted_entropy_similarity(lhs: &[Peak], rhs: &[Peak], tolerance: MassTolWindow) -> f64 {
+    _entropy_similarity(lhs, rhs, tolerance, true)
+}
+
+/// Fraction of the peaks of `lhs` which are matched in `rhs`, returned as (peak count fraction, intensity fraction)
+pub fn matched_peak_fractions(lhs: &[Peak], rhs: &[Peak], tolerance: MassTolWindow) -> (f64, f64) {
+    if lhs.is_empty() {
+        return (0.0, 0.0)
+    }
+
+    let matched_pairs = align_peaks(lhs, rhs, tolerance);
+    let total_intensity: f64 = lhs.iter().map(|peak| peak.intensity as f64).sum();
+    let matched_intensity: f64 = matched_pairs.iter().map(|&(i, _)| lhs[i].intensity as f64).sum();
+
+    let count_fraction = matched_pairs.len() as f64 / lhs.len() as f64;
+    let intensity_fraction = if total_intensity > 0.0 { matched_intensity / total_intensity } else { 0.0 };
+
+    (count_fraction, intensity_fraction)
+}
+
+fn _entropy_similarity(lhs: &[Peak], rhs: &[Peak], tolerance: MassTolWindow, weighted: bool) -> f64 {
+    let (lhs_intensities, rhs_intensities) = if weighted {
+        (_entropy_weighted_intensities(lhs), _entropy_weighted_intensities(rhs))
+    } else {
+        (_normalized_intensities(lhs), _normalized_intensities(rhs))
+    };
+
+    if lhs_intensities.is_empty() || rhs_intensities.is_empty() {
+        return 0.0
+    }
+
+    let matched_pairs = align_peaks(lhs, rhs, tolerance);
+
+    // Merged spectrum (A + B) / 2: matched peaks are summed, unmatched peaks are kept as is
+    let mut merged_intensities = Vec::with_capacity(lhs.len() + rhs.len());
+    let mut is_lhs_matched = vec![false; lhs.len()];
+    let mut is_rhs_matched = vec![false; rhs.len()];
+    for &(i, j) in matched_pairs.iter() {
+        is_lhs_matched[i] = true;
+        is_rhs_matched[j] = true;
+        merged_intensities.push((lhs_intensities[i] + rhs_intensities[j]) / 2.0);
+    }
+    merged_intensities.extend(lhs_intensities.iter().zip(is_lhs_matched).filter(|(_, m)| !m).map(|(ab, _)| ab / 2.0));
+    merged_intensities.extend(rhs_intensities.iter().zip(is_rhs_matched).filter(|(_, m)| !m).map(|(ab, _)| ab / 2.0));
+
+    let merged_entropy = _entropy(&merged_intensities);
+    let similarity = 1.0 - (2.0 * merged_entropy - _entropy(&lhs_intensities) - _entropy(&rhs_intensities)) / 4f64.ln();
+
+    similarity.clamp(0.0, 1.0)
+}
+
+fn _entropy_weighted_intensities(peaks: &[Peak]) -> Vec<f64> {
+    let intensities = _normalized_intensities(peaks);
+    let entropy = _entropy(&intensities);
+    if entropy >= 3.0 {
+        return intensities
+    }
+
+    let weight = 0.25 + 0.25 * entropy;
+    let weighted: Vec<f64> = intensities.iter().map(|ab| ab.powf(weight)).collect();
+    let total: f64 = weighted.iter().sum();
+    weighted.into_iter().map(|ab| ab / total).collect()
+}
+
+fn _normalized_intensities(peaks: &[Peak]) -> Vec<f64> {
+    let total: f64 = peaks.iter().map(|peak| peak.intensity.max(0.0) as f64).sum();
+    if total == 0.0 {
+        return Vec::new()
+    }
+    peaks.iter().map(|peak| peak.intensity.max(0.0) as f64 / total).collect()
+}
+
+fn _entropy(abundances: &[f64]) -> f64 {
+    -abundances.iter().filter(|ab| **ab > 0.0).map(|ab| ab * ab.ln()).sum::<f64>()
+}
+
+fn _l2_norm(peaks: &[Peak]) -> f64 {
+    peaks.iter().map(|peak| (peak.intensity as f64).powi(2)).sum::<f64>().sqrt()
+}
+
+fn _average_ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        let avg_rank = (i + j) as f64 / 2.0 + 1.0;
+        for &idx in &order[i..=j] {
+            ranks[idx] = avg_rank;
+        }
+        i = j + 1;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOL: MassTolWindow = MassTolWindow::Da(-0.02, 0.02);
+
+    fn _spectrum_a() -> Vec<Peak> {
+        vec![
+            Peak { mz: 100.0, intensity: 10.0 },
+            Peak { mz: 200.0, intensity: 50.0 },
+            Peak { mz: 300.0, intensity: 100.0 },
+        ]
+    }
+
+    #[test]
+    fn identical_and_disjoint_spectra() {
+        let spectrum_a = _spectrum_a();
+        let spectrum_b: Vec<Peak> = spectrum_a.iter().map(|peak| Peak { mz: peak.mz + 0.01, intensity: peak.intensity * 2.0 }).collect();
+        let spectrum_c: Vec<Peak> = spectrum_a.iter().map(|peak| Peak { mz: peak.mz + 50.0, intensity: peak.intensity }).collect();
+
+        assert!((cosine_similarity(&spectrum_a, &spectrum_b, TOL) - 1.0).abs() < 1e-9);
+        assert!((spectral_contrast_angle(&spectrum_a, &spectrum_b, TOL) - 1.0).abs() < 1e-6);
+        assert!((entropy_similarity(&spectrum_a, &spectrum_b, TOL) - 1.0).abs() < 1e-9);
+        assert!((weighted_entropy_similarity(&spectrum_a, &spectrum_b, TOL) - 1.0).abs() < 1e-9);
+        assert_eq!(matched_peak_fractions(&spectrum_a, &spectrum_b, TOL), (1.0, 1.0));
+
+        assert_eq!(cosine_similarity(&spectrum_a, &spectrum_c, TOL), 0.0);
+        assert!(entropy_similarity(&spectrum_a, &spectrum_c, TOL).abs() < 1e-9);
+    }
+
+    #[test]
+    fn vector_correlations() {
+        let lhs = [1.0, 2.0, 3.0, 4.0];
+        let rhs = [10.0, 20.0, 30.0, 400.0];
+
+        assert!((spearman_correlation(&lhs, &rhs) - 1.0).abs() < 1e-9);
+        assert!(pearson_correlation(&lhs, &rhs) < 1.0);
+    }
+}
@@ -27,9 +27,15 @@ impl HasSpectrumData for SpectrumData {
     }
 }
 
-#[allow(dead_code)]
 impl SpectrumData {
-    fn to_peaks(&self) -> Vec<Peak> {
+    pub fn from_peaks(peaks: &[Peak]) -> SpectrumData {
+        SpectrumData {
+            mz_list: peaks.iter().map(|peak| peak.mz).collect(),
+            intensity_list: peaks.iter().map(|peak| peak.intensity).collect(),
+        }
+    }
+
+    pub fn to_peaks(&self) -> Vec<Peak> {
         let peaks = self.mz_list
             .iter().copied()
             .zip(self.intensity_list.iter())
@@ -42,6 +48,14 @@ impl SpectrumData {
     }
 }
 
+/// Precursor ion of a MS/MS spectrum
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Precursor {
+    pub mz: f64,
+    pub charge: Option<i8>,
+    pub intensity: Option<f32>,
+}
+
 // --- Similar to sage definitions --- //
 
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
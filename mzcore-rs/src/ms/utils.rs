@@ -7,12 +7,12 @@
 ///
 
 use std::cmp::Ordering;
-use std::ops::Mul;
+use std::ops::{Mul, RangeInclusive};
 
 use anyhow::*;
 use serde::{Serialize, Deserialize};
 
-use crate::chemistry::constants::PROTON_MASS;
+use crate::chemistry::constants::{AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF, PROTON_MASS};
 
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]//#[serde(rename_all = "lowercase")]
@@ -45,6 +45,27 @@ impl MassTolWindow {
     pub fn ppm_to_delta_mass(center: f64, ppm: f64) -> f64 {
         ppm * center / 1_000_000.0
     }
+
+    /// Check if `rhs` matches `center` when allowing for precursor isotope errors
+    /// (i.e. the instrument picked the M+i peak instead of the monoisotopic one).
+    /// Returns the smallest (in absolute value) matching isotope error.
+    pub fn contains_with_isotope_errors(&self, center: f64, rhs: f64, isotope_errors: RangeInclusive<i8>) -> Option<i8> {
+        let mut isotope_errors: Vec<i8> = isotope_errors.collect();
+        isotope_errors.sort_by_key(|isotope_error| isotope_error.abs());
+
+        isotope_errors.into_iter().find(|&isotope_error| {
+            self.contains(center, rhs - isotope_error as f64 * AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF)
+        })
+    }
+
+    /// Compute the (`lower`, `upper`) windows (in Da) of each isotope error, to be used for a precursor mass search
+    pub fn bounds_with_isotope_errors(&self, center: f64, isotope_errors: RangeInclusive<i8>) -> Vec<(f64, f64)> {
+        isotope_errors.map(|isotope_error| {
+            let (lo, hi) = self.bounds(center);
+            let isotope_shift = isotope_error as f64 * AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF;
+            (lo + isotope_shift, hi + isotope_shift)
+        }).collect()
+    }
 }
 
 impl Mul<f64> for MassTolWindow {
@@ -170,4 +191,12 @@ mod tests {
             (999.95, 1000.05)
         );
     }
+
+    #[test]
+    fn isotope_errors() {
+        let tol = MassTolWindow::ppm(-10.0, 10.0);
+        assert_eq!(tol.contains_with_isotope_errors(1000.0, 1000.0, -1..=3), Some(0));
+        assert_eq!(tol.contains_with_isotope_errors(1000.0, 1002.0054, -1..=3), Some(2));
+        assert_eq!(tol.contains_with_isotope_errors(1000.0, 1002.5, -1..=3), None);
+    }
 }
\ No newline at end of file
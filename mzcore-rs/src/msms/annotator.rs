@@ -1,10 +1,15 @@
 
 //use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::*;
 use serde::{Deserialize, Serialize};
 
+use crate::chemistry::constants::AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF;
+use crate::chemistry::table::AminoAcidTable;
 //use crate::ms::spectrum::SpectrumData;
 use crate::msms::fragmentation::*;
-use crate::msms::model::FragmentIonSeries;
+use crate::msms::model::{FragmentIonSeries, FragmentType, NeutralLossCombination};
 
 /// Struct that contains the required info about a match between the exp. and theo. data.
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
@@ -16,8 +21,13 @@ pub struct MatchedPeak {
     pub mz_error: f32,
     pub charge: i8,
     pub ion_type: FragmentIonSeries,
+    pub fragment_type: FragmentType,
+    pub neutral_losses: NeutralLossCombination,
     pub frag_index: u16,  // index of m/z value in the fragmentation table (starts at 0)
     pub aa_position: u16, // AA position in amino acid sequence (starts at 1)
+    pub start_position: u16, // position of the first residue of the fragment (starts at 1)
+    pub end_position: u16, // position of the last residue of the fragment (starts at 1)
+    pub isotope_index: u8, // index of the matched isotopic peak (0 for the monoisotopic peak)
 }
 
 pub fn annotate_spectrum(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationTable, mz_error_tol: f64) -> Vec<MatchedPeak> {
@@ -39,9 +49,17 @@ pub fn annotate_spectrum(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationT
             all_theo_frags.push((
                 mz_value,
                 frag_table_col_idx,
-                frag_table_row_idx
+                frag_table_row_idx,
+                0
             ));
 
+            // Add the isotopic peaks of the fragment when its envelope has been computed
+            let isotope_count = frag_series_fragments.isotope_abundances.get(frag_table_row_idx).map_or(0, |abundances| abundances.len());
+            for isotope_idx in 1..isotope_count {
+                let isotope_mz = mz_value + isotope_idx as f64 * AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / frag_series_fragments.charge.abs() as f64;
+                all_theo_frags.push((isotope_mz, frag_table_col_idx, frag_table_row_idx, isotope_idx as u8));
+            }
+
             frag_table_row_idx += 1
         }
 
@@ -57,7 +75,7 @@ pub fn annotate_spectrum(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationT
 
     let mut prev_frag_mz = 0.0;
     for theo_frag in all_theo_frags.iter() {
-        let (frag_mz, _, _) = *theo_frag;
+        let (frag_mz, _, _, _) = *theo_frag;
 
         if !theo_frags_group_buffer.is_empty() && frag_mz - prev_frag_mz > mz_error_tol {
             grouped_theo_frags.push(theo_frags_group_buffer.clone());
@@ -107,7 +125,7 @@ pub fn annotate_spectrum(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationT
         //println!("new last_theo_mz={}",last_theo_mz);
 
         for cur_frag in cur_frag_group {
-            let (theo_mz, frag_table_col_idx, frag_table_row_idx) = **cur_frag;
+            let (theo_mz, frag_table_col_idx, frag_table_row_idx, isotope_idx) = **cur_frag;
             //println!("theo_mz={}",theo_mz);
 
             let mz_error = peak_mz - theo_mz;
@@ -116,10 +134,12 @@ pub fn annotate_spectrum(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationT
                 //println!("matching fragment with m/z={}", peak_mz);
 
                 let frag_table_col: &TheoreticalFragmentIons = frag_table.get(frag_table_col_idx).unwrap();
-                let aa_position = if frag_table_col.ion_type.is_n_terminal().unwrap() {
-                    (1 + frag_table_row_idx ) as u16
+                let (start_position, end_position) = frag_table_col.residue_ranges[frag_table_row_idx];
+                // Position of the residue preceding the cleavage site for N-terminal ions, following it otherwise
+                let aa_position = if frag_table_col.ion_type.is_n_terminal() == Some(true) {
+                    end_position
                 } else {
-                    (1 + frag_table_n_rows - frag_table_row_idx) as u16
+                    start_position
                 };
 
                 matched_peaks.push(MatchedPeak {
@@ -130,8 +150,13 @@ pub fn annotate_spectrum(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationT
                     mz_error: mz_error as f32,
                     charge: frag_table_col.charge,
                     ion_type: frag_table_col.ion_type,
+                    fragment_type: frag_table_col.fragment_type,
+                    neutral_losses: frag_table_col.neutral_losses,
                     frag_index: frag_table_row_idx as u16,
                     aa_position: aa_position,
+                    start_position,
+                    end_position,
+                    isotope_index: isotope_idx,
                 });
             }
         }
@@ -142,6 +167,119 @@ pub fn annotate_spectrum(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationT
     matched_peaks
 }
 
+type FragmentKey = (FragmentIonSeries, FragmentType, i8, NeutralLossCombination, u16, u16);
+
+/// Mean cosine similarity between the observed and the predicted isotope envelopes of the matched fragments.
+/// Only the fragments having a predicted envelope of at least two peaks are considered (None if there is no such fragment).
+pub fn isotope_envelope_consistency(matched_peaks: &[MatchedPeak], frag_table: &FragmentationTable) -> Option<f64> {
+
+    let mut matched_peaks_by_fragment: HashMap<FragmentKey, Vec<&MatchedPeak>> = HashMap::new();
+    for matched_peak in matched_peaks {
+        let fragment_key = (
+            matched_peak.ion_type,
+            matched_peak.fragment_type,
+            matched_peak.charge,
+            matched_peak.neutral_losses,
+            matched_peak.start_position,
+            matched_peak.end_position,
+        );
+        matched_peaks_by_fragment.entry(fragment_key).or_default().push(matched_peak);
+    }
+
+    let mut similarities = Vec::new();
+    for fragment_matched_peaks in matched_peaks_by_fragment.values() {
+        let first_peak = fragment_matched_peaks[0];
+        let frag_index = first_peak.frag_index as usize;
+        let frag_series_opt = frag_table.iter().find(|frag_series| {
+            frag_series.ion_type == first_peak.ion_type
+                && frag_series.fragment_type == first_peak.fragment_type
+                && frag_series.charge == first_peak.charge
+                && frag_series.neutral_losses == first_peak.neutral_losses
+                && frag_series.residue_ranges.get(frag_index) == Some(&(first_peak.start_position, first_peak.end_position))
+        });
+        let Some(predicted_abundances) = frag_series_opt.and_then(|frag_series| frag_series.isotope_abundances.get(frag_index)) else { continue };
+        if predicted_abundances.len() < 2 {
+            continue;
+        }
+
+        // Keep the most intense peak matching each isotope
+        let mut observed_intensities = vec![0.0; predicted_abundances.len()];
+        for matched_peak in fragment_matched_peaks {
+            let isotope_idx = matched_peak.isotope_index as usize;
+            if isotope_idx < observed_intensities.len() {
+                observed_intensities[isotope_idx] = f64::max(observed_intensities[isotope_idx], matched_peak.peak_intensity);
+            }
+        }
+
+        let dot_product: f64 = observed_intensities.iter().zip(predicted_abundances).map(|(obs, pred)| obs * *pred as f64).sum();
+        let observed_norm = observed_intensities.iter().map(|obs| obs * obs).sum::<f64>().sqrt();
+        let predicted_norm = predicted_abundances.iter().map(|pred| (*pred as f64).powi(2)).sum::<f64>().sqrt();
+        if observed_norm > 0.0 && predicted_norm > 0.0 {
+            similarities.push(dot_product / (observed_norm * predicted_norm));
+        }
+    }
+
+    if similarities.is_empty() {
+        None
+    } else {
+        Some(similarities.iter().sum::<f64>() / similarities.len() as f64)
+    }
+}
+
+/// Annotate the spectrum of a modified peptide using the fragment ions defined by a fragmentation config
+pub fn annotate_peptide_spectrum(
+    spectrum_peaks: &[[f64;2]],
+    pep_seq: &[u8],
+    located_mass_increments: &[LocatedMassIncrement],
+    aa_table: &AminoAcidTable,
+    frag_config: &SimpleFragmentationConfig,
+    precursor_charge: i8,
+    mz_error_tol: f64,
+) -> Result<Vec<MatchedPeak>> {
+    let frag_table = aa_table.compute_frag_table(pep_seq, located_mass_increments, frag_config, precursor_charge)?;
+    if frag_table.is_empty() {
+        bail!("the fragmentation config doesn't define any fragment ion series");
+    }
+
+    let matched_peaks = annotate_spectrum(spectrum_peaks, &frag_table, mz_error_tol);
+
+    let remove_precursor_peaks = frag_config.precursor_ions.as_ref().is_some_and(|precursor_config| precursor_config.remove_from_spectrum);
+    if !remove_precursor_peaks {
+        return Ok(matched_peaks);
+    }
+
+    // Peaks explained by precursor-derived ions are only annotated as such
+    let precursor_peak_indices: HashSet<usize> = matched_peaks.iter()
+        .filter(|matched_peak| matched_peak.fragment_type == FragmentType::Precursor)
+        .map(|matched_peak| matched_peak.peak_index)
+        .collect();
+
+    Ok(matched_peaks.into_iter().filter(|matched_peak| {
+        matched_peak.fragment_type == FragmentType::Precursor || !precursor_peak_indices.contains(&matched_peak.peak_index)
+    }).collect())
+}
+
+/// Remove the peaks matching precursor-derived ions (unreacted and charge-reduced precursors, and their neutral losses)
+pub fn remove_precursor_peaks(spectrum_peaks: &[[f64;2]], frag_table: &FragmentationTable, mz_error_tol: f64) -> Vec<[f64;2]> {
+    let precursor_frag_table: FragmentationTable = frag_table.iter()
+        .filter(|frag_series| frag_series.fragment_type == FragmentType::Precursor)
+        .cloned()
+        .collect();
+    if precursor_frag_table.is_empty() {
+        return spectrum_peaks.to_vec();
+    }
+
+    let precursor_peak_indices: HashSet<usize> = annotate_spectrum(spectrum_peaks, &precursor_frag_table, mz_error_tol)
+        .iter()
+        .map(|matched_peak| matched_peak.peak_index)
+        .collect();
+
+    spectrum_peaks.iter().enumerate()
+        .filter(|(peak_idx, _)| !precursor_peak_indices.contains(peak_idx))
+        .map(|(_, peak)| *peak)
+        .collect()
+}
+
 // --- R*Tree based annotator by david-bouyssie (should be implemented in a dedicated feature)
 
 /*
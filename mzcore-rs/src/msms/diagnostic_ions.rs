@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+use crate::quant::isobaric::IsobaricLabel;
+
+/// Glycopeptide oxonium ions (singly charged m/z values)
+pub const OXONIUM_IONS: [(&str, f64); 10] = [
+    ("HexNAc-C2H6O3", 126.05495),
+    ("HexNAc-CH6O3", 138.05495),
+    ("HexNAc-C2H4O2", 144.06552),
+    ("Hex", 163.06009),
+    ("HexNAc-2H2O", 168.06552),
+    ("HexNAc-H2O", 186.07608),
+    ("HexNAc", 204.08665),
+    ("NeuAc-H2O", 274.09213),
+    ("NeuAc", 292.10269),
+    ("HexHexNAc", 366.13947),
+];
+
+/// Signature ions of cross-linked peptides: immonium ions of Lys carrying the linker (DSS/BS3)
+/// or the remnants left by the MS-cleavable linkers (DSSO: alkene, thiol and sulfenic acid, DSBU: Bu and BuUr)
+const DSS_SIGNATURE_IONS: [(&str, f64); 2] = [
+    ("DSS-K-NH3", 222.14885),
+    ("DSS-K", 239.17540),
+];
+const DSSO_SIGNATURE_IONS: [(&str, f64); 3] = [
+    ("DSSO-A-K", 155.11789),
+    ("DSSO-T-K", 187.08996),
+    ("DSSO-S-K", 205.10052),
+];
+const DSBU_SIGNATURE_IONS: [(&str, f64); 2] = [
+    ("DSBU-Bu-K", 186.16009),
+    ("DSBU-BuUr-K", 212.13935),
+];
+
+/// Lys-reactive cross-linking reagents (BS3 produces the same ions as DSS)
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum CrossLinker {
+    DSS,
+    DSSO,
+    DSBU,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum DiagnosticIonCategory {
+    CrossLinker,
+    Oxonium,
+    Reporter,
+    Other,
+}
+
+/// Singly charged fragment ion whose presence is characteristic of a modification, a label or a cross-linker
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiagnosticIon {
+    pub name: String,
+    pub category: DiagnosticIonCategory,
+    pub mz: f64,
+}
+
+impl DiagnosticIon {
+    pub fn new(name: &str, category: DiagnosticIonCategory, mz: f64) -> Self {
+        DiagnosticIon { name: name.to_string(), category, mz }
+    }
+}
+
+/// Diagnostic ions annotated alongside the fragments of the peptide
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiagnosticIonLibrary {
+    pub ions: Vec<DiagnosticIon>,
+}
+
+impl DiagnosticIonLibrary {
+    pub fn oxonium_ions() -> Self {
+        let ions = OXONIUM_IONS.iter()
+            .map(|(name, mz)| DiagnosticIon::new(name, DiagnosticIonCategory::Oxonium, *mz))
+            .collect();
+
+        DiagnosticIonLibrary { ions }
+    }
+
+    /// Reporter ions named as in the PSI mzPAF reference ion list (e.g. TMT127N, iTRAQ114)
+    pub fn reporter_ions(label: IsobaricLabel) -> Self {
+        // The TMT 6-plex channels are named after the TMT 11-plex channel sharing their reporter ion
+        let tmt11_reporter_ions = IsobaricLabel::Tmt11.reporter_ions();
+
+        let ions = label.reporter_ions().iter()
+            .map(|reporter_ion| {
+                let name = match label {
+                    IsobaricLabel::Itraq4 | IsobaricLabel::Itraq8 => format!("iTRAQ{}", reporter_ion.channel),
+                    _ => {
+                        let channel = tmt11_reporter_ions.iter()
+                            .find(|tmt11_ion| (tmt11_ion.mz - reporter_ion.mz).abs() < 1e-4)
+                            .map_or(&reporter_ion.channel, |tmt11_ion| &tmt11_ion.channel);
+                        format!("TMT{}", channel)
+                    }
+                };
+                DiagnosticIon::new(&name, DiagnosticIonCategory::Reporter, reporter_ion.mz)
+            })
+            .collect();
+
+        DiagnosticIonLibrary { ions }
+    }
+
+    pub fn cross_linker_ions(cross_linker: CrossLinker) -> Self {
+        let signature_ions: &[(&str, f64)] = match cross_linker {
+            CrossLinker::DSS => &DSS_SIGNATURE_IONS,
+            CrossLinker::DSSO => &DSSO_SIGNATURE_IONS,
+            CrossLinker::DSBU => &DSBU_SIGNATURE_IONS,
+        };
+
+        let ions = signature_ions.iter()
+            .map(|(name, mz)| DiagnosticIon::new(name, DiagnosticIonCategory::CrossLinker, *mz))
+            .collect();
+
+        DiagnosticIonLibrary { ions }
+    }
+
+    pub fn add(&mut self, ion: DiagnosticIon) {
+        self.ions.push(ion);
+    }
+
+    /// Append the ions of another library
+    pub fn merge(&mut self, other: &DiagnosticIonLibrary) {
+        self.ions.extend(other.ions.iter().cloned());
+    }
+
+    pub fn ions_of_category(&self, category: DiagnosticIonCategory) -> impl Iterator<Item = &DiagnosticIon> {
+        self.ions.iter().filter(move |ion| ion.category == category)
+    }
+}
@@ -1,106 +1,401 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 
 use anyhow::*;
 use serde::{Deserialize, Serialize};
 
 use crate::chemistry::amino_acid::AminoAcidDefinition;
 use crate::chemistry::api::*;
-use crate::chemistry::constants::WATER_MONO_MASS;
-use crate::chemistry::table::AminoAcidTable;
+use crate::chemistry::composition::ElementalComposition;
+use crate::chemistry::constants::{ELECTRON_MASS, OXIDATION_MONO_MASS, PHOSPHO_MONO_MASS, PROTON_MASS};
+use crate::chemistry::element::Element;
+use crate::chemistry::isotope_distribution::IsotopeDistribution;
+use crate::chemistry::peptide::LinearPeptide;
+use crate::chemistry::table::{biomolecule_atom_table, AminoAcidTable};
+use crate::ms::MassType;
 use crate::ms::utils::*;
+use crate::msms::diagnostic_ions::DiagnosticIonLibrary;
 use crate::msms::model::*;
 
-/*
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
-pub struct SimpleFragmentationRule {
-    pub fragment_ion_type: FragmentIonType,
-    pub fragment_ion_charge: i8, // = 1
+/// Fragment ions expected for a given activation type and MSn analyzer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimpleFragmentationConfig {
+    pub activation_type: ActivationType,
+    pub msn_analyzer: MsAnalyzer,
+    pub ion_series: Vec<FragmentIonSeries>,
+    /// Maximum fragment charge state (fragment charges are also limited to the precursor charge minus one)
+    pub max_fragment_charge: i8,
+    /// Neutral losses of the sequence ions (None to disable them)
+    pub neutral_losses: Option<NeutralLossConfig>,
+    /// Internal fragments (None to disable them)
+    pub internal_fragments: Option<InternalFragmentConfig>,
+    /// Immonium ions of the residues (None to disable them)
+    pub immonium_ions: Option<ImmoniumIonConfig>,
+    /// Precursor-derived ions (None to disable them)
+    pub precursor_ions: Option<PrecursorIonConfig>,
+    /// Diagnostic ions annotated alongside the fragments of the peptide
+    pub diagnostic_ions: DiagnosticIonLibrary,
+    /// Isotope envelopes of the fragments (None to consider monoisotopic peaks only)
+    pub isotope_envelopes: Option<IsotopeEnvelopeConfig>,
+    pub mass_type: MassType,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
-pub struct SimpleFragmentationConfig {
-    pub frag_rules: Vec<SimpleFragmentationRule>,
-    pub frag_rule_by_ion_series: HashMap<FragmentIonSeries,SimpleFragmentationRule>
+impl Default for SimpleFragmentationConfig {
+    fn default() -> Self {
+        Self::new(ActivationType::HCD, MsAnalyzer::FTMS)
+    }
 }
 
 impl SimpleFragmentationConfig {
 
-    /*pub fn create_with_defaults() -> Result<SimpleFragmentationConfig> {
-        create(1 as i8, ActivationType::HCD, MsAnalyzer::FTMS)
-    }*/
-
-    /*pub fn create(
-        fragment_ion_charge: i8,
-        activation_type: ActivationType,
-        msn_analyzer: MsAnalyzer
-    ) -> Result<SimpleFragmentationConfig> {
+    /// Preset configuration of an activation type
+    pub fn new(activation_type: ActivationType, msn_analyzer: MsAnalyzer) -> SimpleFragmentationConfig {
 
         use ActivationType::*;
         use FragmentIonSeries::*;
         use MsAnalyzer::*;
 
-        let ion_series_list = match activation_type  {
-            CID => vec!(b,b_NH3,b_H2O,y,y_NH3,y_H2O),
-            ECD => vec!(c,y,z_p1,z_p2),
-            ETD => if msn_analyzer == FTMS {vec!(c,y,z,z_p1,z_p2)} else {vec!(c,y,z_p1,z_p2)},
-            HCD => vec!(a,a_NH3,a_H2O,b,b_NH3,b_H2O,y,y_NH3,y_H2O,ya,yb),
-            PSD => vec!(a,a_NH3,a_H2O,b,b_NH3,b_H2O,y),
+        let ion_series = match activation_type {
+            CID => vec![b, y],
+            ECD => vec![c, y, z_p1, z_p2],
+            ETD => if msn_analyzer == FTMS { vec![c, y, z, z_p1, z_p2] } else { vec![c, y, z_p1, z_p2] },
+            EThcD => vec![b, c, y, z_p1, w],
+            HCD => vec![a, b, y],
+            PSD => vec![a, b, y],
+            UVPD => vec![a, b, c, x, y, z, z_p1],
         };
 
-        /*logger.debug(
-            s"Fragmentation config for activationType=$activationType and msnAnalyzer=$msnAnalyzer will contain this list of fragment series: " + ionSeriesList.mkString(",")
-        )*/
+        // Labile modifications are retained by electron-based activation
+        let neutral_losses = match activation_type {
+            ECD | ETD => None,
+            _ => Some(NeutralLossConfig::default()),
+        };
 
-        // Map fragmentation rules by series
-        let mut frag_rule_by_ion_series:  HashMap<FragmentIonSeries,SimpleFragmentationRule> = HashMap::new();
+        SimpleFragmentationConfig {
+            activation_type,
+            msn_analyzer,
+            ion_series,
+            max_fragment_charge: if msn_analyzer == FTMS { 3 } else { 2 },
+            neutral_losses,
+            internal_fragments: if matches!(activation_type, HCD | UVPD) { Some(InternalFragmentConfig::default()) } else { None },
+            immonium_ions: if matches!(activation_type, HCD | PSD | UVPD) { Some(ImmoniumIonConfig::default()) } else { None },
+            precursor_ions: if matches!(activation_type, ECD | ETD | EThcD) { Some(PrecursorIonConfig::default()) } else { None },
+            diagnostic_ions: DiagnosticIonLibrary::default(),
+            isotope_envelopes: None,
+            mass_type: MassType::Monoisotopic,
+        }
+    }
 
-        let simple_frag_rules = ion_series_list.iter().map( |ion_series| {
+    pub fn contains_ion_series(&self, ion_series: FragmentIonSeries) -> bool {
+        self.ion_series.contains(&ion_series)
+    }
 
-            let frag_rule = SimpleFragmentationRule {
-                fragment_ion_type: FragmentIonType { ion_series: (*ion_series).to_owned(), neutral_loss: None },
-                fragment_ion_charge: fragment_ion_charge
-            };
+    /// Fragment charge states considered for a given precursor charge state (at least 1)
+    pub fn fragment_charges(&self, precursor_charge: i8) -> Vec<i8> {
+        let max_charge = self.max_fragment_charge.min(precursor_charge - 1).max(1);
+        (1..=max_charge).collect()
+    }
+}
 
-            let frag_ion_series = frag_rule.fragment_ion_type.ion_series;
-            if frag_rule_by_ion_series.contains_key(&frag_ion_series) {
-                bail!("Duplicated SimpleFragmentationRule found, the same ionSeries {} has been already defined",frag_ion_series)
-            }
+/// Internal fragments result from a double backbone cleavage, they contain neither the N-terminal nor the C-terminal residue
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InternalFragmentConfig {
+    /// Internal fragments series (yb for b-type and ya for a-type fragments)
+    pub ion_series: Vec<FragmentIonSeries>,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub max_charge: i8,
+}
 
-            frag_rule_by_ion_series.insert(frag_ion_series, &*frag_rule);
+impl Default for InternalFragmentConfig {
+    fn default() -> Self {
+        InternalFragmentConfig {
+            ion_series: vec![FragmentIonSeries::yb, FragmentIonSeries::ya],
+            min_length: 2,
+            max_length: 10,
+            max_charge: 1,
+        }
+    }
+}
 
-            frag_rule
-        }).collect();
+/// Ions derived from the precursor: unreacted precursor, charge-reduced precursors resulting from electron transfer
+/// without dissociation (e.g. [M+2H]+• for a doubly charged precursor) and their neutral losses
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PrecursorIonConfig {
+    pub include_charge_reduced: bool,
+    pub neutral_losses: Vec<NeutralLoss>,
+    /// Peaks matching precursor-derived ions are not annotated as fragments
+    pub remove_from_spectrum: bool,
+}
 
-        Ok(SimpleFragmentationConfig {
-            frag_rules: simple_frag_rules,
-            frag_rule_by_ion_series: frag_rule_by_ion_series,
-        })
-    }*/
+impl Default for PrecursorIonConfig {
+    fn default() -> Self {
+        PrecursorIonConfig {
+            include_charge_reduced: true,
+            neutral_losses: vec![NeutralLoss::H2O, NeutralLoss::NH3],
+            remove_from_spectrum: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IsotopeEnvelopeConfig {
+    pub max_isotopes: usize,
+    /// Isotopic peaks less abundant than this fraction of the most abundant one are discarded
+    pub min_relative_abundance: f32,
+}
 
-    /*pub fn  containsIonSeries(&self, ionSeries: FragmentIonSeries.Value) -> bool {
-        frag_rule_by_ion_series.contains(ionSeries)
+impl Default for IsotopeEnvelopeConfig {
+    fn default() -> Self {
+        IsotopeEnvelopeConfig {
+            max_isotopes: 3,
+            min_relative_abundance: 0.1,
+        }
     }
-    pub fn  getFragmentationRule(&self, ionSeries: FragmentIonSeries.Value) -> Option[SimpleFragmentationRule] {
-    fragRuleByIonSeries.get(ionSeries)
+}
+
+/// Immonium ions are generated for each residue (including its modifications),
+/// the related ions correspond to the neutral losses of the immonium ions (e.g. Lys-NH3)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImmoniumIonConfig {
+    pub related_ions: Vec<NeutralLossRule>,
+}
+
+impl Default for ImmoniumIonConfig {
+    fn default() -> Self {
+        ImmoniumIonConfig {
+            related_ions: vec![NeutralLossRule::new(NeutralLoss::NH3, "KRQN", None)],
+        }
     }
-    pub fn  getRequiredFragmentIonCharge(&self, ionSeries: FragmentIonSeries.Value): Option[Int] = {
-    fragRuleByIonSeries.get(ionSeries).map(_.fragmentIonCharge)
-    }*/
-}*/
+}
+
+/// Mass tolerance used to recognize the modification required by a neutral loss rule
+const NEUTRAL_LOSS_MOD_MASS_TOLERANCE: f64 = 0.01;
+
+/// Defines the residues (optionally carrying a given modification) able to undergo a neutral loss
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NeutralLossRule {
+    pub neutral_loss: NeutralLoss,
+    /// One letter codes of the compatible residues
+    pub residues: String,
+    /// Mass of the modification that must be located on the residue
+    pub modification_mass: Option<f64>,
+}
+
+impl NeutralLossRule {
+    pub fn new(neutral_loss: NeutralLoss, residues: &str, modification_mass: Option<f64>) -> Self {
+        NeutralLossRule { neutral_loss, residues: residues.to_string(), modification_mass }
+    }
+
+    /// H2O from S/T/E/D, NH3 from R/K/N/Q, H3PO4 from phospho-S/T, HPO3 from phospho-Y and CH4OS from oxidized M
+    pub fn default_rules() -> Vec<NeutralLossRule> {
+        vec![
+            Self::new(NeutralLoss::H2O, "STED", None),
+            Self::new(NeutralLoss::NH3, "RKNQ", None),
+            Self::new(NeutralLoss::H3PO4, "ST", Some(PHOSPHO_MONO_MASS)),
+            Self::new(NeutralLoss::HPO3, "Y", Some(PHOSPHO_MONO_MASS)),
+            Self::new(NeutralLoss::CH4OS, "M", Some(OXIDATION_MONO_MASS)),
+        ]
+    }
+
+    pub fn applies_to(&self, residue: u8, residue_mod_masses: &[f64]) -> bool {
+        self.residues.as_bytes().contains(&residue) && self.modification_mass.is_none_or(|rule_mod_mass| {
+            residue_mod_masses.iter().any(|mod_mass| (mod_mass - rule_mod_mass).abs() <= NEUTRAL_LOSS_MOD_MASS_TOLERANCE)
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NeutralLossConfig {
+    pub rules: Vec<NeutralLossRule>,
+    /// Maximum number of losses undergone by a single fragment (also limited by the number of loss sites of the peptide)
+    pub max_losses_per_fragment: usize,
+    /// Neutral losses of the modifications of a LinearPeptide, indexed by modification id
+    pub modification_losses: HashMap<i64, Vec<NeutralLoss>>,
+}
+
+impl Default for NeutralLossConfig {
+    fn default() -> Self {
+        NeutralLossConfig {
+            rules: NeutralLossRule::default_rules(),
+            max_losses_per_fragment: 1,
+            modification_losses: HashMap::new(),
+        }
+    }
+}
 
 pub type FragmentationTable = Vec<TheoreticalFragmentIons>;
 
-impl FragmentationTableFactory<AminoAcidDefinition> for AminoAcidTable {}
+/// (aa_pos, mass_increment): residue positions start at 1, 0 corresponds to the N-terminus and sequence length + 1 to the C-terminus
+pub type LocatedMassIncrement = (usize, f64);
+
+/// Validate the positions of the mass increments and group them by residue (terminal mass increments are skipped)
+fn _residue_mass_increments(pep_seq_len: usize, located_mass_increments: &[LocatedMassIncrement]) -> Result<Vec<Vec<f64>>> {
+    let mut mass_increments_by_residue = vec![Vec::new(); pep_seq_len];
+    for &(aa_pos, mass_increment) in located_mass_increments {
+        if aa_pos > pep_seq_len + 1 {
+            bail!("invalid amino acid position ({}) for peptide of length {} (mod mass = {})", aa_pos, pep_seq_len, mass_increment);
+        }
+        if aa_pos >= 1 && aa_pos <= pep_seq_len {
+            mass_increments_by_residue[aa_pos - 1].push(mass_increment);
+        }
+    }
+
+    Ok(mass_increments_by_residue)
+}
+
+/// Substituents of the beta carbon lost by the side chain cleavage leading to d and w ions.
+/// Ile and Thr have two possible substituents ('a' and 'b' variants), Gly, Ala and Pro don't produce satellite ions.
+fn _side_chain_gamma_substituents(residue: u8) -> &'static [&'static [(Element, i16)]] {
+    use Element::{C, H, N, O, S, Se};
+
+    match residue {
+        b'C' => &[&[(S, 1), (H, 1)]],
+        b'D' => &[&[(C, 1), (O, 2), (H, 1)]],
+        b'E' => &[&[(C, 2), (H, 3), (O, 2)]],
+        b'F' => &[&[(C, 6), (H, 5)]],
+        b'H' => &[&[(C, 3), (H, 3), (N, 2)]],
+        b'I' => &[&[(C, 2), (H, 5)], &[(C, 1), (H, 3)]],
+        b'K' => &[&[(C, 3), (H, 8), (N, 1)]],
+        b'L' => &[&[(C, 3), (H, 7)]],
+        b'M' => &[&[(C, 2), (H, 5), (S, 1)]],
+        b'N' => &[&[(C, 1), (H, 2), (N, 1), (O, 1)]],
+        b'Q' => &[&[(C, 2), (H, 4), (N, 1), (O, 1)]],
+        b'R' => &[&[(C, 3), (H, 8), (N, 3)]],
+        b'S' => &[&[(O, 1), (H, 1)]],
+        b'T' => &[&[(O, 1), (H, 1)], &[(C, 1), (H, 3)]],
+        b'U' => &[&[(Se, 1), (H, 1)]],
+        b'V' => &[&[(C, 1), (H, 3)]],
+        b'W' => &[&[(C, 8), (H, 6), (N, 1)]],
+        b'Y' => &[&[(C, 6), (H, 5), (O, 1)]],
+        _ => &[],
+    }
+}
+
+/// Compute the neutral loss columns corresponding to the loss-free columns of a fragmentation table.
+/// A combination of losses is only allowed when the fragment contains enough compatible residues,
+/// the other fragments are omitted from the column (columns without any allowed fragment are skipped).
+pub fn compute_neutral_loss_frag_table(
+    pep_seq: &[u8],
+    located_mass_increments: &[LocatedMassIncrement],
+    frag_table: &FragmentationTable,
+    neutral_loss_config: &NeutralLossConfig,
+    mass_type: MassType,
+) -> Result<FragmentationTable> {
+
+    let pep_seq_len = pep_seq.len();
+
+    let mod_masses_by_position = _residue_mass_increments(pep_seq_len, located_mass_increments)?;
+
+    // Cumulated number of sites able to undergo each loss, from the N-terminus
+    let mut cumulated_site_counts = vec![[0usize; NeutralLoss::ALL.len()]; pep_seq_len + 1];
+    for (idx, residue) in pep_seq.iter().enumerate() {
+        let mut site_counts = cumulated_site_counts[idx];
+        for neutral_loss in NeutralLoss::ALL {
+            let is_site = neutral_loss_config.rules.iter().any(|rule| {
+                rule.neutral_loss == neutral_loss && rule.applies_to(*residue, &mod_masses_by_position[idx])
+            });
+            if is_site {
+                site_counts[neutral_loss as usize] += 1;
+            }
+        }
+        cumulated_site_counts[idx + 1] = site_counts;
+    }
+
+    let mut rule_losses: Vec<NeutralLoss> = neutral_loss_config.rules.iter().map(|rule| rule.neutral_loss).collect();
+    rule_losses.sort();
+    rule_losses.dedup();
+    // A fragment can't undergo more losses than the number of sites of the peptide
+    let site_count: usize = cumulated_site_counts[pep_seq_len].iter().sum();
+    let loss_combinations = _enumerate_neutral_loss_combinations(&rule_losses, neutral_loss_config.max_losses_per_fragment.min(site_count))?;
+
+    let mut neutral_loss_frag_table = Vec::new();
+    for frag_series in frag_table.iter().filter(|frag_series| {
+        frag_series.fragment_type == FragmentType::Sequence && frag_series.neutral_losses.is_empty()
+    }) {
+        if frag_series.ion_type.is_n_terminal().is_none() {
+            continue;
+        }
+
+        for loss_combination in loss_combinations.iter() {
+            let loss_mz = loss_combination.mass(mass_type)? / frag_series.charge as f64;
+
+            // Keep the fragments covering enough sites to undergo the losses
+            let (mz_values, residue_ranges): (Vec<f64>, Vec<(u16, u16)>) = frag_series.mz_values.iter()
+                .zip(&frag_series.residue_ranges)
+                .filter(|(_, (start_pos, end_pos))| {
+                    loss_combination.iter().all(|(neutral_loss, count)| {
+                        let loss_idx = neutral_loss as usize;
+                        cumulated_site_counts[*end_pos as usize][loss_idx] - cumulated_site_counts[*start_pos as usize - 1][loss_idx] >= count as usize
+                    })
+                })
+                .map(|(frag_mz, residue_range)| (frag_mz - loss_mz, *residue_range))
+                .unzip();
+
+            if !mz_values.is_empty() {
+                neutral_loss_frag_table.push(TheoreticalFragmentIons {
+                    neutral_losses: *loss_combination,
+                    mz_values,
+                    residue_ranges,
+                    ..frag_series.clone()
+                });
+            }
+        }
+    }
+
+    Ok(neutral_loss_frag_table)
+}
+
+fn _enumerate_neutral_loss_combinations(neutral_losses: &[NeutralLoss], max_losses: usize) -> Result<Vec<NeutralLossCombination>> {
+    let mut combinations = vec![NeutralLossCombination::default()];
+
+    for neutral_loss in neutral_losses {
+        let mut extended_combinations = Vec::new();
+        for combination in combinations.iter() {
+            let mut extended_combination = *combination;
+            while extended_combination.total_count() < max_losses {
+                extended_combination.add(*neutral_loss)?;
+                extended_combinations.push(extended_combination);
+            }
+        }
+        combinations.extend(extended_combinations);
+    }
+
+    combinations.retain(|combination| !combination.is_empty());
+    combinations.sort_by_key(|combination| combination.total_count());
+
+    Ok(combinations)
+}
+
+impl FragmentationTableFactory<AminoAcidDefinition> for AminoAcidTable {
+    fn residue_composition(&self, aa: &AminoAcidDefinition) -> Result<ElementalComposition> {
+        aa.composition()
+    }
+}
 
 pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
 
+    /// Elemental composition of a residue, required to compute the isotope envelopes of the fragments
+    fn residue_composition(&self, aa: &T) -> Result<ElementalComposition> {
+        bail!("the elemental composition of residue '{}' is not available", aa.single_letter_code() as char)
+    }
+
     // --- Compute m/z value of fragment ion series (it contains all ion_types into one vector)  --- //
-    fn compute_frag_series_mz_values(&self, pep_seq: &Cow<[u8]>, ion_type: FragmentIonSeries, charge: i8) -> Result<Vec<f64>> {
+    fn compute_frag_series_mz_values(&self, pep_seq: &Cow<[u8]>, ion_type: FragmentIonSeries, charge: i8, mass_type: MassType) -> Result<Vec<f64>> {
 
         let seq_len = pep_seq.len();
+        if seq_len == 0 {
+            bail!("can't compute the fragment ions of an empty peptide sequence");
+        }
         let mut frag_series_mz_values = Vec::with_capacity(seq_len);
 
-        let frag_series_mass_shift = WATER_MONO_MASS + ion_type.get_ion_mono_mass_shift();
+        let frag_series_mass_shift = ion_type.mass_shift(mass_type)?;
+        let residue_mass = |aa: &T| -> Result<f64> {
+            match mass_type {
+                MassType::Monoisotopic => Ok(aa.mono_mass()),
+                MassType::Average => aa.average_mass().ok_or_else(|| anyhow!("undefined average mass")),
+            }
+        };
 
         let pep_seq_as_bytes = pep_seq.iter();
 
@@ -115,7 +410,7 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
                 for aa_as_byte in pep_seq_as_bytes.take(seq_len - 1) {
                     let aa = self.aa_from_byte(aa_as_byte)?;
 
-                    seq_mass += aa.mono_mass();
+                    seq_mass += residue_mass(aa)?;
 
                     // Convert mass to m/z value
                     let ion_mz = mass_to_mz(seq_mass, charge as i32);
@@ -132,7 +427,7 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
                 for aa_as_byte in pep_seq_as_bytes.rev().take(seq_len - 1) {
                     let aa = self.aa_from_byte(aa_as_byte)?;
 
-                    seq_mass += aa.mono_mass();
+                    seq_mass += residue_mass(aa)?;
 
                     // Convert mass to m/z value
                     let ion_mz = mass_to_mz(seq_mass, charge as i32);
@@ -149,7 +444,7 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
 
     }
 
-    fn compute_frag_table_without_mods(&self, pep_seq: &Cow<[u8]>, ion_types: &[FragmentIonSeries], frag_ion_charges: &Vec<i8>) -> Result<FragmentationTable> {
+    fn compute_frag_table_without_mods(&self, pep_seq: &Cow<[u8]>, ion_types: &[FragmentIonSeries], frag_ion_charges: &Vec<i8>, mass_type: MassType) -> Result<FragmentationTable> {
 
         let mut frag_table: FragmentationTable = Vec::with_capacity(ion_types.len());
 
@@ -159,14 +454,23 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
 
             for charge in frag_ion_charges {
 
-                let mz_values_res = self.compute_frag_series_mz_values(&pep_seq, *ion_type, *charge);
+                let mz_values_res = self.compute_frag_series_mz_values(&pep_seq, *ion_type, *charge, mass_type);
                 let mz_values = mz_values_res?;
 
+                let seq_len = pep_seq.len() as u16;
+                let residue_ranges = (0..mz_values.len() as u16).map(|frag_idx| {
+                    if ion_type.is_n_terminal() == Some(true) { (1, frag_idx + 1) } else { (seq_len - frag_idx, seq_len) }
+                }).collect();
+
                 // add to fragmentation table a new column containing different mz values for considered ion type and charge state
                 frag_table.push(TheoreticalFragmentIons {
                     ion_type: *ion_type,
+                    fragment_type: FragmentType::Sequence,
                     charge: *charge,
-                    mz_values: mz_values
+                    neutral_losses: NeutralLossCombination::default(),
+                    mz_values: mz_values,
+                    residue_ranges,
+                    isotope_abundances: Vec::new(),
                 });
             }
         }
@@ -178,7 +482,7 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
     fn _compute_frag_table_with_mods(
         pep_seq: &Cow<[u8]>,
         frag_table_without_mods: &FragmentationTable,
-        located_mass_increments: &Vec<(usize,f64)> // (aa_pos, mass_increment) // TODO: add custom Type
+        located_mass_increments: &[LocatedMassIncrement]
     ) -> Result<Vec<TheoreticalFragmentIons>> {
 
         let pep_seq_len = pep_seq.len();
@@ -189,12 +493,14 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
 
         // Inject mass increments in created vectors
         for &(aa_pos,mass_increment) in located_mass_increments {
-            if aa_pos < 1 || aa_pos > pep_seq_len {
+            if aa_pos > pep_seq_len + 1 {
                 let pep_seq_str = std::str::from_utf8(pep_seq)?;
                 bail!("invalid amino acid position ({}) for peptide {} of length {} (mod mass = {})", aa_pos, pep_seq_str, pep_seq_len, mass_increment);
             }
-            forward_mass_increments[aa_pos - 1] += mass_increment;
-            reverse_mass_increments[pep_seq_len - aa_pos] += mass_increment;
+            // Terminal mass increments are carried by all the ions containing the terminal residue
+            let residue_pos = aa_pos.clamp(1, pep_seq_len);
+            forward_mass_increments[residue_pos - 1] += mass_increment;
+            reverse_mass_increments[pep_seq_len - residue_pos] += mass_increment;
         }
 
         let mut cur_mass_inc = 0.0;
@@ -241,69 +547,566 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
         Ok(updated_frag_table)
     }
 
-    fn compute_frag_table_from_mod_string(
+    /// Compute the internal fragments of a modified peptide, with one column per ion series, charge state and fragment length.
+    /// The rows of a column correspond to the successive start positions of the fragments.
+    fn compute_internal_frag_table(
         &self,
-        pep_seq: &Cow<[u8]>,
-        pep_mods_str_opt: Option<&str>,
+        pep_seq: &[u8],
+        located_mass_increments: &[LocatedMassIncrement],
+        internal_config: &InternalFragmentConfig,
+        frag_ion_charges: &[i8],
+        mass_type: MassType,
+    ) -> Result<FragmentationTable> {
+
+        let pep_seq_len = pep_seq.len();
+
+        // Cumulated residue and modification masses
+        let mut cumulated_masses = vec![0.0; pep_seq_len + 1];
+        for (idx, aa_as_byte) in pep_seq.iter().enumerate() {
+            let aa = self.aa_from_byte(aa_as_byte)?;
+            let residue_mass = match mass_type {
+                MassType::Monoisotopic => aa.mono_mass(),
+                MassType::Average => aa.average_mass().ok_or_else(|| anyhow!("undefined average mass"))?,
+            };
+            cumulated_masses[idx + 1] = residue_mass;
+        }
+        // Terminal modifications are not included in internal fragments
+        let mod_masses_by_position = _residue_mass_increments(pep_seq_len, located_mass_increments)?;
+        for (idx, mod_masses) in mod_masses_by_position.iter().enumerate() {
+            cumulated_masses[idx + 1] += mod_masses.iter().sum::<f64>();
+        }
+        for idx in 1..=pep_seq_len {
+            cumulated_masses[idx] += cumulated_masses[idx - 1];
+        }
+
+        let max_length = internal_config.max_length.min(pep_seq_len.saturating_sub(2));
+        let mut internal_frag_table = Vec::new();
+
+        for ion_type in internal_config.ion_series.iter() {
+            let mass_shift = ion_type.mass_shift(mass_type)?;
+
+            for charge in frag_ion_charges.iter().filter(|charge| **charge <= internal_config.max_charge) {
+                for frag_length in internal_config.min_length.max(1)..=max_length {
+                    // The first residue of an internal fragment is located between the second and the penultimate positions
+                    let start_positions = 2..=(pep_seq_len - frag_length);
+
+                    let mz_values = start_positions.clone().map(|start_pos| {
+                        let frag_mass = cumulated_masses[start_pos + frag_length - 1] - cumulated_masses[start_pos - 1] + mass_shift;
+                        mass_to_mz(frag_mass, *charge as i32)
+                    }).collect();
+
+                    internal_frag_table.push(TheoreticalFragmentIons {
+                        ion_type: *ion_type,
+                        fragment_type: FragmentType::Internal,
+                        charge: *charge,
+                        neutral_losses: NeutralLossCombination::default(),
+                        mz_values,
+                        residue_ranges: start_positions.map(|start_pos| (start_pos as u16, (start_pos + frag_length - 1) as u16)).collect(),
+                        isotope_abundances: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        Ok(internal_frag_table)
+    }
+
+    /// Compute the satellite ions of a modified peptide, resulting from the side chain cleavage of the residue adjacent to the backbone cleavage:
+    /// - d ions: a ions having lost the beta carbon substituent of their C-terminal residue as an alkene (high-energy CID)
+    /// - v ions: y ions having lost the side chain of their N-terminal residue as an alkene (high-energy CID)
+    /// - w ions: z· ions having lost the beta carbon substituent of their N-terminal residue as a radical (ETD/EThcD),
+    ///   they differentiate Leu (w) from Ile (wa and wb)
+    ///
+    /// The satellite ions of modified residues are omitted since the composition of their side chain is unknown.
+    fn compute_satellite_frag_table(
+        &self,
+        pep_seq: &[u8],
+        located_mass_increments: &[LocatedMassIncrement],
         ion_types: &[FragmentIonSeries],
         frag_ion_charges: &Vec<i8>,
+        mass_type: MassType,
     ) -> Result<FragmentationTable> {
 
-        let frag_table_without_mods = self.compute_frag_table_without_mods(
-            pep_seq,
-            ion_types,
-            frag_ion_charges,
-        )?;
+        use FragmentIonSeries::*;
 
-        let frag_table = if pep_mods_str_opt.is_none() {
-            frag_table_without_mods
-        } else {
-            let pep_mods_str = pep_mods_str_opt.unwrap();
-            let pep_mods = pep_mods_str.split(",");
-
-            let mut located_mass_incs = Vec::new();
-            for pep_mod in pep_mods {
-                let pep_mod_parts: Vec<_> = pep_mod.splitn(2, "@").collect();
-                let mod_mass: f64 = pep_mod_parts.first().unwrap().parse().unwrap();
-                let mut mod_pos: i64 = pep_mod_parts.last().unwrap().parse().unwrap();
-                if mod_pos == 0 {
-                    mod_pos += 1;
-                } else if mod_pos == -1 {
-                    mod_pos = pep_seq.len() as i64;
+        let pep_seq = Cow::from(pep_seq);
+        let atom_table = biomolecule_atom_table();
+        let calc_mass = |composition: &ElementalComposition| match mass_type {
+            MassType::Monoisotopic => atom_table.calc_mono_mass(composition),
+            MassType::Average => atom_table.calc_average_mass(composition),
+        };
+        let hydrogen = ElementalComposition::from_monoisotope_tuples(&[(Element::H, 1)]);
+        let glycine_residue_mass = match mass_type {
+            MassType::Monoisotopic => self.aa_from_byte(&b'G')?.mono_mass(),
+            MassType::Average => self.aa_from_byte(&b'G')?.average_mass().ok_or_else(|| anyhow!("undefined average mass"))?,
+        };
+
+        let mut satellite_series: Vec<FragmentIonSeries> = ion_types.iter().flat_map(|ion_type| ion_type.satellite_variants()).copied().collect();
+        satellite_series.sort();
+        satellite_series.dedup();
+
+        let mut satellite_frag_table = Vec::new();
+        for satellite_type in satellite_series {
+            let base_type = match satellite_type {
+                d | da | db => a,
+                v => y,
+                _ => z·,
+            };
+            let variant_idx = match satellite_type {
+                da | wa => Some(0),
+                db | wb => Some(1),
+                _ => None,
+            };
+
+            let base_frag_table = self.compute_frag_table_without_mods(&pep_seq, &[base_type], frag_ion_charges, mass_type)?;
+            let base_frag_table = Self::_compute_frag_table_with_mods(&pep_seq, &base_frag_table, located_mass_increments)?;
+
+            for base_series in base_frag_table {
+                let mut mz_values = Vec::with_capacity(base_series.mz_values.len());
+                let mut residue_ranges = Vec::with_capacity(base_series.mz_values.len());
+                for (base_mz, (start_pos, end_pos)) in base_series.mz_values.iter().zip(&base_series.residue_ranges) {
+                    // Position of the residue adjacent to the backbone cleavage site
+                    let aa_pos = if satellite_type.is_n_terminal() == Some(true) { *end_pos as usize } else { *start_pos as usize };
+                    let residue = pep_seq[aa_pos - 1];
+                    let is_modified = located_mass_increments.iter().any(|(mod_pos, _)| *mod_pos == aa_pos);
+
+                    let loss_mass = if is_modified {
+                        None
+                    } else if satellite_type == v {
+                        let side_chain_loss = self.aa_from_byte(&residue).map(|aa| match mass_type {
+                            MassType::Monoisotopic => Some(aa.mono_mass()),
+                            MassType::Average => aa.average_mass(),
+                        })?.map(|residue_mass| residue_mass - glycine_residue_mass);
+                        side_chain_loss.filter(|_| residue != b'G' && residue != b'P')
+                    } else {
+                        let substituents = _side_chain_gamma_substituents(residue);
+                        let substituent_opt = match variant_idx {
+                            None if substituents.len() == 1 => Some(substituents[0]),
+                            Some(idx) if substituents.len() == 2 => Some(substituents[idx]),
+                            _ => None,
+                        };
+
+                        match substituent_opt {
+                            None => None,
+                            Some(substituent) => {
+                                let mut loss = ElementalComposition::from_monoisotope_tuples(substituent);
+                                // The d ions are formed by the loss of an alkene
+                                if satellite_type.is_n_terminal() == Some(true) {
+                                    loss = loss - &hydrogen;
+                                }
+                                Some(calc_mass(&loss)?)
+                            }
+                        }
+                    };
+
+                    // Skip the residues unable to undergo the side chain cleavage
+                    if let Some(loss_mass) = loss_mass {
+                        mz_values.push(base_mz - loss_mass / base_series.charge as f64);
+                        residue_ranges.push((*start_pos, *end_pos));
+                    }
                 }
 
-                located_mass_incs.push((mod_pos as usize, mod_mass));
+                if !mz_values.is_empty() {
+                    satellite_frag_table.push(TheoreticalFragmentIons {
+                        ion_type: satellite_type,
+                        fragment_type: FragmentType::Satellite,
+                        mz_values,
+                        residue_ranges,
+                        ..base_series
+                    });
+                }
+            }
+        }
+
+        Ok(satellite_frag_table)
+    }
+
+    /// Compute the precursor-derived ions of a modified peptide, with one single-row column per charge state and neutral loss.
+    /// Charge-reduced precursors keep the protons of the precursor and carry the transferred electrons.
+    fn compute_precursor_frag_table(
+        &self,
+        pep_seq: &[u8],
+        located_mass_increments: &[LocatedMassIncrement],
+        precursor_config: &PrecursorIonConfig,
+        precursor_charge: i8,
+        mass_type: MassType,
+    ) -> Result<FragmentationTable> {
+
+        if precursor_charge < 1 {
+            bail!("invalid precursor charge state ({})", precursor_charge);
+        }
+
+        let pep_seq_len = pep_seq.len();
+        if located_mass_increments.iter().any(|(aa_pos, _)| *aa_pos > pep_seq_len + 1) {
+            bail!("invalid amino acid position for peptide of length {}", pep_seq_len);
+        }
+
+        let mut precursor_mass = FragmentIonSeries::precursor.mass_shift(mass_type)?;
+        for aa_as_byte in pep_seq {
+            let aa = self.aa_from_byte(aa_as_byte)?;
+            precursor_mass += match mass_type {
+                MassType::Monoisotopic => aa.mono_mass(),
+                MassType::Average => aa.average_mass().ok_or_else(|| anyhow!("undefined average mass"))?,
+            };
+        }
+        precursor_mass += located_mass_increments.iter().map(|(_, mass_increment)| mass_increment).sum::<f64>();
+
+        let min_charge = if precursor_config.include_charge_reduced { 1 } else { precursor_charge };
+        let mut loss_combinations = vec![NeutralLossCombination::default()];
+        loss_combinations.extend(precursor_config.neutral_losses.iter().map(|neutral_loss| NeutralLossCombination::single(*neutral_loss)));
+
+        let mut precursor_frag_table = Vec::new();
+        for charge in (min_charge..=precursor_charge).rev() {
+            let transferred_electrons = (precursor_charge - charge) as f64;
+            for loss_combination in loss_combinations.iter() {
+                let ion_mass = precursor_mass - loss_combination.mass(mass_type)?
+                    + precursor_charge as f64 * PROTON_MASS + transferred_electrons * ELECTRON_MASS;
+
+                precursor_frag_table.push(TheoreticalFragmentIons {
+                    ion_type: FragmentIonSeries::precursor,
+                    fragment_type: FragmentType::Precursor,
+                    charge,
+                    neutral_losses: *loss_combination,
+                    mz_values: vec![ion_mass / charge as f64],
+                    residue_ranges: vec![(1, pep_seq_len as u16)],
+                    isotope_abundances: Vec::new(),
+                });
             }
+        }
+
+        Ok(precursor_frag_table)
+    }
+
+    /// Compute the immonium ions of a modified peptide (one row per residue), followed by the columns of the related ions
+    /// (restricted to the residues undergoing the loss)
+    fn compute_immonium_frag_table(
+        &self,
+        pep_seq: &[u8],
+        located_mass_increments: &[LocatedMassIncrement],
+        immonium_config: &ImmoniumIonConfig,
+        mass_type: MassType,
+    ) -> Result<FragmentationTable> {
+
+        let pep_seq_len = pep_seq.len();
+        let mass_shift = FragmentIonSeries::immonium.mass_shift(mass_type)?;
+
+        let mod_masses_by_position = _residue_mass_increments(pep_seq_len, located_mass_increments)?;
 
-            Self::_compute_frag_table_with_mods(
-                pep_seq,
-                &frag_table_without_mods,
-                &located_mass_incs,
-            )?
+        let mut mz_values = Vec::with_capacity(pep_seq_len);
+        for (aa_as_byte, mod_masses) in pep_seq.iter().zip(&mod_masses_by_position) {
+            let aa = self.aa_from_byte(aa_as_byte)?;
+            let residue_mass = match mass_type {
+                MassType::Monoisotopic => aa.mono_mass(),
+                MassType::Average => aa.average_mass().ok_or_else(|| anyhow!("undefined average mass"))?,
+            };
+            mz_values.push(mass_to_mz(residue_mass + mod_masses.iter().sum::<f64>() + mass_shift, 1));
+        }
+
+        let immonium_ions = TheoreticalFragmentIons {
+            ion_type: FragmentIonSeries::immonium,
+            fragment_type: FragmentType::Immonium,
+            charge: 1,
+            neutral_losses: NeutralLossCombination::default(),
+            mz_values,
+            residue_ranges: (1..=pep_seq_len as u16).map(|aa_pos| (aa_pos, aa_pos)).collect(),
+            isotope_abundances: Vec::new(),
         };
 
-        Ok(frag_table)
+        let mut immonium_frag_table = Vec::with_capacity(1 + immonium_config.related_ions.len());
+        for rule in immonium_config.related_ions.iter() {
+            let loss_mass = rule.neutral_loss.mass(mass_type)?;
+            let (related_mz_values, related_residue_ranges): (Vec<f64>, Vec<(u16, u16)>) = immonium_ions.mz_values.iter()
+                .zip(&immonium_ions.residue_ranges)
+                .enumerate()
+                .filter(|(idx, _)| rule.applies_to(pep_seq[*idx], &mod_masses_by_position[*idx]))
+                .map(|(_, (immonium_mz, residue_range))| (immonium_mz - loss_mass, *residue_range))
+                .unzip();
+
+            if !related_mz_values.is_empty() {
+                immonium_frag_table.push(TheoreticalFragmentIons {
+                    neutral_losses: NeutralLossCombination::single(rule.neutral_loss),
+                    mz_values: related_mz_values,
+                    residue_ranges: related_residue_ranges,
+                    ..immonium_ions.clone()
+                });
+            }
+        }
+        immonium_frag_table.insert(0, immonium_ions);
+
+        Ok(immonium_frag_table)
+    }
+
+    fn compute_frag_table_from_mod_string(
+        &self,
+        pep_seq: &[u8],
+        pep_mods_str_opt: Option<&str>,
+        frag_config: &SimpleFragmentationConfig,
+        precursor_charge: i8,
+    ) -> Result<FragmentationTable> {
+
+        let mut located_mass_incs = Vec::new();
+        if let Some(pep_mods_str) = pep_mods_str_opt {
+            for pep_mod in pep_mods_str.split(",") {
+                let (mod_mass_str, mod_pos_str) = pep_mod.split_once("@")
+                    .ok_or_else(|| anyhow!("invalid modification '{}', expected mass@position", pep_mod))?;
+                let mod_mass: f64 = mod_mass_str.trim().parse()
+                    .with_context(|| format!("invalid mass in modification '{}'", pep_mod))?;
+                let mod_pos: i64 = mod_pos_str.trim().parse()
+                    .with_context(|| format!("invalid position in modification '{}'", pep_mod))?;
+
+                // 0 corresponds to the N-terminus and -1 to the C-terminus
+                let aa_pos = match mod_pos {
+                    -1 => pep_seq.len() + 1,
+                    pos if pos >= 0 && pos as usize <= pep_seq.len() => pos as usize,
+                    _ => bail!("invalid position in modification '{}' for peptide of length {}", pep_mod, pep_seq.len()),
+                };
+
+                located_mass_incs.push((aa_pos, mod_mass));
+            }
+        }
+
+        self.compute_frag_table(pep_seq, &located_mass_incs, frag_config, precursor_charge)
+    }
+
+    /// Compute the isotope envelopes of the fragments of a table (monoisotopic masses are expected).
+    /// The fragment compositions are obtained from the compositions of the residues, the mass of the modifications being
+    /// added as an extra mass, while the envelopes of the satellite and diagnostic ions are estimated using the averagine model.
+    fn compute_isotope_envelopes(
+        &self,
+        pep_seq: &[u8],
+        frag_table: &mut FragmentationTable,
+        envelope_config: &IsotopeEnvelopeConfig,
+    ) -> Result<()> {
+
+        // Residues without a defined formula (e.g. B, X or Z) have no composition
+        let residue_compositions = pep_seq.iter()
+            .map(|aa_as_byte| Ok(self.residue_composition(self.aa_from_byte(aa_as_byte)?).ok()))
+            .collect::<Result<Vec<Option<ElementalComposition>>>>()?;
+        let atom_table = biomolecule_atom_table();
+
+        for frag_series in frag_table.iter_mut() {
+            let composition_delta_opt = frag_series.ion_type.composition_delta()
+                .filter(|_| frag_series.fragment_type != FragmentType::Satellite)
+                .map(|composition_delta| composition_delta - frag_series.neutral_losses.composition());
+
+            let mut isotope_abundances = Vec::with_capacity(frag_series.mz_values.len());
+            for (frag_mz, (start_pos, end_pos)) in frag_series.mz_values.iter().zip(&frag_series.residue_ranges) {
+                let frag_mass = mz_to_mass(*frag_mz, frag_series.charge as i32);
+                let composition_opt = composition_delta_opt.as_ref()
+                    .filter(|_| *start_pos > 0)
+                    .and_then(|composition_delta| {
+                        residue_compositions[*start_pos as usize - 1..*end_pos as usize].iter()
+                            .try_fold(composition_delta.clone(), |composition, residue_composition_opt| {
+                                residue_composition_opt.as_ref().map(|residue_composition| composition + residue_composition)
+                            })
+                    })
+                    // Modifications are accounted as an extra mass, so their neutral losses (e.g. H3PO4)
+                    // may exceed the atoms of the unmodified residues
+                    .filter(|composition| composition.element_counts.iter().all(|element_count| element_count.count.round() >= 0.0));
+
+                // Fall back to averagine for the fragments without a valid composition
+                let distribution = match composition_opt {
+                    Some(mut composition) => {
+                        composition.additional_mass += frag_mass - atom_table.calc_mono_mass(&composition)?;
+                        IsotopeDistribution::from_composition(&composition, envelope_config.max_isotopes)?
+                    }
+                    None => IsotopeDistribution::averagine(frag_mass, envelope_config.max_isotopes)?,
+                };
+
+                let min_abundance = envelope_config.min_relative_abundance as f64;
+                let mut relative_abundances: Vec<f32> = distribution.relative_abundances().iter().map(|ab| *ab as f32).collect();
+                while relative_abundances.len() > 1 && (*relative_abundances.last().unwrap() as f64) < min_abundance {
+                    relative_abundances.pop();
+                }
+                isotope_abundances.push(relative_abundances);
+            }
+
+            frag_series.isotope_abundances = isotope_abundances;
+        }
+
+        Ok(())
     }
 
+    /// Compute the fragmentation table of a LinearPeptide according to a fragmentation config.
+    /// The neutral losses of its modifications are defined by the `modification_losses` of the neutral loss config.
+    fn compute_linear_peptide_frag_table(
+        &self,
+        peptide: &LinearPeptide,
+        frag_config: &SimpleFragmentationConfig,
+        precursor_charge: i8,
+    ) -> Result<FragmentationTable> {
+
+        let pep_seq = &peptide.sequence;
+        let pep_seq_len = pep_seq.len();
+
+        let mut located_mass_increments = Vec::with_capacity(peptide.mods.len());
+        let mut modification_loss_rules = Vec::new();
+        for pep_mod in peptide.mods.iter() {
+            let mod_pos = pep_mod.position.ok_or_else(|| anyhow!("modification {} is not localized", pep_mod.id))?;
+
+            // SimpleModification positions are residue indices starting at 0, -1 means N-term and the sequence length means C-term
+            let aa_pos = match mod_pos {
+                -1 => 0,
+                pos if pos >= 0 && pos as usize <= pep_seq_len => pos as usize + 1,
+                _ => bail!("invalid position ({}) of modification {} for peptide of length {}", mod_pos, pep_mod.id, pep_seq_len),
+            };
+            located_mass_increments.push((aa_pos, pep_mod.mono_mass));
+
+            let mod_losses = frag_config.neutral_losses.as_ref().and_then(|nl_config| nl_config.modification_losses.get(&pep_mod.id));
+            if let Some(mod_losses) = mod_losses.filter(|_| aa_pos >= 1 && aa_pos <= pep_seq_len) {
+                let residue = (pep_seq[aa_pos - 1] as char).to_string();
+                for neutral_loss in mod_losses {
+                    modification_loss_rules.push(NeutralLossRule::new(*neutral_loss, &residue, Some(pep_mod.mono_mass)));
+                }
+            }
+        }
+
+        if modification_loss_rules.is_empty() {
+            self.compute_frag_table(pep_seq, &located_mass_increments, frag_config, precursor_charge)
+        } else {
+            let mut peptide_frag_config = frag_config.clone();
+            if let Some(nl_config) = peptide_frag_config.neutral_losses.as_mut() {
+                nl_config.rules.extend(modification_loss_rules);
+            }
+            self.compute_frag_table(pep_seq, &located_mass_increments, &peptide_frag_config, precursor_charge)
+        }
+    }
+
+    /// Compute the fragmentation table of a modified peptide according to a fragmentation config
     fn compute_frag_table(
         &self,
-        pep_seq: &Cow<[u8]>,
-        located_mass_increments: &Vec<(usize,f64)>,
+        pep_seq: &[u8],
+        located_mass_increments: &[LocatedMassIncrement],
+        frag_config: &SimpleFragmentationConfig,
+        precursor_charge: i8,
+    ) -> Result<FragmentationTable> {
+
+        if pep_seq.is_empty() {
+            bail!("can't compute the fragmentation table of an empty peptide sequence");
+        }
+
+        let pep_seq = Cow::from(pep_seq);
+
+        let frag_ion_charges = frag_config.fragment_charges(precursor_charge);
+        let (satellite_ion_types, sequence_ion_types): (Vec<FragmentIonSeries>, Vec<FragmentIonSeries>) = frag_config.ion_series
+            .iter()
+            .partition(|ion_type| ion_type.is_satellite());
+
+        let frag_table_without_mods = self.compute_frag_table_without_mods(
+            &pep_seq,
+            &sequence_ion_types,
+            &frag_ion_charges,
+            frag_config.mass_type,
+        )?;
+
+        let mut frag_table = Self::_compute_frag_table_with_mods(
+            &pep_seq,
+            &frag_table_without_mods,
+            located_mass_increments,
+        )?;
+
+        if let Some(neutral_loss_config) = frag_config.neutral_losses.as_ref() {
+            let neutral_loss_frag_table = compute_neutral_loss_frag_table(
+                &pep_seq,
+                located_mass_increments,
+                &frag_table,
+                neutral_loss_config,
+                frag_config.mass_type,
+            )?;
+            frag_table.extend(neutral_loss_frag_table);
+        }
+
+        if !satellite_ion_types.is_empty() {
+            let satellite_frag_table = self.compute_satellite_frag_table(
+                &pep_seq,
+                located_mass_increments,
+                &satellite_ion_types,
+                &frag_ion_charges,
+                frag_config.mass_type,
+            )?;
+            frag_table.extend(satellite_frag_table);
+        }
+
+        if let Some(internal_config) = frag_config.internal_fragments.as_ref() {
+            let internal_frag_table = self.compute_internal_frag_table(
+                &pep_seq,
+                located_mass_increments,
+                internal_config,
+                &frag_ion_charges,
+                frag_config.mass_type,
+            )?;
+            frag_table.extend(internal_frag_table);
+        }
+
+        if let Some(immonium_config) = frag_config.immonium_ions.as_ref() {
+            let immonium_frag_table = self.compute_immonium_frag_table(
+                &pep_seq,
+                located_mass_increments,
+                immonium_config,
+                frag_config.mass_type,
+            )?;
+            frag_table.extend(immonium_frag_table);
+        }
+
+        if let Some(precursor_config) = frag_config.precursor_ions.as_ref() {
+            let precursor_frag_table = self.compute_precursor_frag_table(
+                &pep_seq,
+                located_mass_increments,
+                precursor_config,
+                precursor_charge,
+                frag_config.mass_type,
+            )?;
+            frag_table.extend(precursor_frag_table);
+        }
+
+        if !frag_config.diagnostic_ions.ions.is_empty() {
+            frag_table.push(TheoreticalFragmentIons::from_diagnostic_ions(&frag_config.diagnostic_ions));
+        }
+
+        if let Some(envelope_config) = frag_config.isotope_envelopes.as_ref() {
+            if frag_config.mass_type != MassType::Monoisotopic {
+                bail!("isotope envelopes can only be computed for monoisotopic masses");
+            }
+            self.compute_isotope_envelopes(&pep_seq, &mut frag_table, envelope_config)?;
+        }
+
+        Ok(frag_table)
+    }
+
+    /// Compute the fragmentation table of a modified peptide, completed by the columns of its neutral loss ions
+    fn compute_frag_table_with_neutral_losses(
+        &self,
+        pep_seq: &[u8],
+        located_mass_increments: &[LocatedMassIncrement],
         ion_types: &[FragmentIonSeries],
         frag_ion_charges: &Vec<i8>,
+        neutral_loss_config: &NeutralLossConfig,
+        mass_type: MassType,
     ) -> Result<FragmentationTable> {
 
+        let pep_seq = Cow::from(pep_seq);
+
         let frag_table_without_mods = self.compute_frag_table_without_mods(
-            pep_seq,
+            &pep_seq,
             ion_types,
             frag_ion_charges,
+            mass_type,
         )?;
 
-        let frag_table = Self::_compute_frag_table_with_mods(
-            pep_seq,
+        let mut frag_table = Self::_compute_frag_table_with_mods(
+            &pep_seq,
             &frag_table_without_mods,
-            &located_mass_increments,
+            located_mass_increments,
+        )?;
+
+        let neutral_loss_frag_table = compute_neutral_loss_frag_table(
+            &pep_seq,
+            located_mass_increments,
+            &frag_table,
+            neutral_loss_config,
+            mass_type,
         )?;
+        frag_table.extend(neutral_loss_frag_table);
 
         Ok(frag_table)
     }
@@ -312,11 +1115,32 @@ pub trait FragmentationTableFactory<T: IsAminoAcid>: AminoAcidFactory<T>  {
 #[derive(Clone, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct TheoreticalFragmentIons {
     pub ion_type: FragmentIonSeries,
+    pub fragment_type: FragmentType,
     pub charge: i8,
-    pub mz_values: Vec<f64>
+    /// Neutral losses undergone by the fragments of the series
+    pub neutral_losses: NeutralLossCombination,
+    /// m/z values of the fragments able to form (e.g. to undergo the neutral losses), in the order of the sequence
+    pub mz_values: Vec<f64>,
+    /// First and last residue positions (starting at 1) of each fragment
+    pub residue_ranges: Vec<(u16, u16)>,
+    /// Relative abundances of the isotopic peaks of each fragment, starting with the monoisotopic one (empty if not computed)
+    pub isotope_abundances: Vec<Vec<f32>>,
 }
 
 impl TheoreticalFragmentIons {
+    /// Diagnostic ions are not located on the peptide sequence (their residue ranges are set to (0, 0))
+    pub fn from_diagnostic_ions(diagnostic_ions: &DiagnosticIonLibrary) -> TheoreticalFragmentIons {
+        TheoreticalFragmentIons {
+            ion_type: FragmentIonSeries::diagnostic,
+            fragment_type: FragmentType::Diagnostic,
+            charge: 1,
+            neutral_losses: NeutralLossCombination::default(),
+            mz_values: diagnostic_ions.ions.iter().map(|ion| ion.mz).collect(),
+            residue_ranges: vec![(0, 0); diagnostic_ions.ions.len()],
+            isotope_abundances: Vec::new(),
+        }
+    }
+
     pub fn change_frag_series_charge_state(&self, new_charge: i8) -> TheoreticalFragmentIons {
         let mut new_mz_values = Vec::with_capacity(self.mz_values.len());
 
@@ -326,9 +1150,9 @@ impl TheoreticalFragmentIons {
         }
 
         TheoreticalFragmentIons {
-            ion_type: self.ion_type,
             charge: new_charge,
-            mz_values: new_mz_values
+            mz_values: new_mz_values,
+            ..self.clone()
         }
     }
 }
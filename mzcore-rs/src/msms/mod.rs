@@ -1,14 +1,31 @@
 pub mod annotator;
+pub mod diagnostic_ions;
 pub mod fragmentation;
 pub mod model;
+pub mod mzpaf;
 
 #[cfg(test)]
 mod tests {
     use std::borrow::Cow;
+    use std::collections::HashMap;
+    use std::sync::Arc;
     use anyhow::*;
+    use crate::chemistry::peptide::{LinearPeptide, SimpleModification};
     use crate::chemistry::table::*;
-    use crate::msms::fragmentation::FragmentationTableFactory;
-    use crate::msms::model::FragmentIonSeries::{b,y};
+    use crate::ms::MassType;
+    use crate::ms::utils::MassTolUnit;
+    use crate::quant::isobaric::IsobaricLabel;
+    use crate::msms::annotator::{annotate_peptide_spectrum, annotate_spectrum, isotope_envelope_consistency, remove_precursor_peaks};
+    use crate::msms::diagnostic_ions::{CrossLinker, DiagnosticIon, DiagnosticIonCategory, DiagnosticIonLibrary};
+    use crate::msms::fragmentation::{FragmentationTableFactory, ImmoniumIonConfig, InternalFragmentConfig, IsotopeEnvelopeConfig, NeutralLossConfig, SimpleFragmentationConfig, TheoreticalFragmentIons};
+    use crate::msms::mzpaf::{matched_peaks_to_mzpaf, parse_mzpaf, MzPafAnnotation, MzPafIon, MzPafMassDelta};
+    use crate::msms::model::{ActivationType, FragmentType, MsAnalyzer, NeutralLoss, NeutralLossCombination};
+    use crate::msms::model::FragmentIonSeries::{self, a, b, c, d, v, w, x, y, z_p1, z·};
+
+    /// m/z value of the fragment covering a given range of residues
+    fn _fragment_mz(frag_series: &TheoreticalFragmentIons, residue_range: (u16, u16)) -> Option<f64> {
+        frag_series.residue_ranges.iter().position(|range| *range == residue_range).map(|idx| frag_series.mz_values[idx])
+    }
 
     #[test]
     fn pep_seq_to_frag_table() -> Result<()> {
@@ -20,6 +37,7 @@ mod tests {
             &Cow::from(pep_seq.as_bytes()),
             &[b,y],
             &vec![1],
+            MassType::Monoisotopic,
         )?;
 
         let expected_frag_table_len = 2;
@@ -28,5 +46,425 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn frag_ion_masses_from_compositions() -> Result<()> {
+        let pep_seq = Cow::from("INTERSTELLAR".as_bytes());
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        let frag_table = default_aa_table.compute_frag_table_without_mods(&pep_seq, &[b, c, x, y, z_p1], &vec![1], MassType::Monoisotopic)?;
+        let first_mz = |col: usize| frag_table[col].mz_values[0];
+
+        assert!((first_mz(0) - 114.09134).abs() < 1e-4, "b1 = {}", first_mz(0));
+        assert!((first_mz(1) - 131.11789).abs() < 1e-4, "c1 = {}", first_mz(1));
+        assert!((first_mz(3) - 175.11895).abs() < 1e-4, "y1 = {}", first_mz(3));
+        // x = y + CO - H2
+        assert!((first_mz(2) - first_mz(3) - 25.97926).abs() < 1e-4, "x1 = {}", first_mz(2));
+        // z+1 = y - NH2
+        assert!((first_mz(4) - first_mz(3) + 16.01872).abs() < 1e-4, "z+1 = {}", first_mz(4));
+
+        let average_table = default_aa_table.compute_frag_table_without_mods(&pep_seq, &[b], &vec![1], MassType::Average)?;
+        assert!((average_table[0].mz_values[0] - 114.16).abs() < 0.01);
+
+        assert!(FragmentIonSeries::w.composition_delta().is_none());
+        assert!(default_aa_table.compute_frag_table_without_mods(&pep_seq, &[FragmentIonSeries::d], &vec![1], MassType::Monoisotopic).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn neutral_loss_frag_table() -> Result<()> {
+        // Phosphorylated serine at position 3
+        let pep_seq = Cow::from("GASPK".as_bytes());
+        let located_mass_increments = vec![(3, 79.96633)];
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        let frag_table = default_aa_table.compute_frag_table_with_neutral_losses(
+            &pep_seq, &located_mass_increments, &[b, y], &vec![1], &NeutralLossConfig::default(), MassType::Monoisotopic
+        )?;
+        let find_column = |ion_type: FragmentIonSeries, neutral_loss: NeutralLoss| {
+            frag_table.iter().find(|col| col.ion_type == ion_type && col.neutral_losses == NeutralLossCombination::single(neutral_loss))
+        };
+
+        // No M and no phospho-Y in the peptide
+        assert!(find_column(b, NeutralLoss::CH4OS).is_none());
+        assert!(find_column(y, NeutralLoss::HPO3).is_none());
+
+        let b_h3po4 = find_column(b, NeutralLoss::H3PO4).unwrap();
+        // Fragments unable to undergo a loss are omitted from its column
+        assert!(frag_table.iter().all(|col| col.mz_values.len() == col.residue_ranges.len() && col.mz_values.iter().all(|mz| mz.is_finite())));
+        assert!(_fragment_mz(b_h3po4, (1, 1)).is_none() && _fragment_mz(b_h3po4, (1, 2)).is_none());
+        assert!((frag_table[0].mz_values[2] - _fragment_mz(b_h3po4, (1, 3)).unwrap() - 97.97690).abs() < 1e-4);
+
+        let y_h2o = find_column(y, NeutralLoss::H2O).unwrap();
+        assert!(_fragment_mz(y_h2o, (4, 5)).is_none());
+        assert!((frag_table[1].mz_values[2] - _fragment_mz(y_h2o, (3, 5)).unwrap() - 18.01056).abs() < 1e-4);
+
+        let y_nh3 = find_column(y, NeutralLoss::NH3).unwrap();
+        assert!((frag_table[1].mz_values[0] - _fragment_mz(y_nh3, (5, 5)).unwrap() - 17.02655).abs() < 1e-4);
+
+        // Two losses require two compatible sites
+        let config = NeutralLossConfig { max_losses_per_fragment: 2, ..Default::default() };
+        let frag_table = default_aa_table.compute_frag_table_with_neutral_losses(
+            &pep_seq, &located_mass_increments, &[b], &vec![1], &config, MassType::Monoisotopic
+        )?;
+        let mut h2o_h3po4 = NeutralLossCombination::single(NeutralLoss::H2O);
+        h2o_h3po4.add(NeutralLoss::H3PO4)?;
+        assert_eq!(h2o_h3po4.to_string(), "-H2O-H3PO4");
+        assert!(frag_table.iter().any(|col| col.neutral_losses == h2o_h3po4));
+        assert!(frag_table.iter().all(|col| col.neutral_losses.count(NeutralLoss::H2O) < 2));
+
+        // The number of losses is limited by the number of sites of the peptide (2 sites in the b fragments)
+        let config = NeutralLossConfig { max_losses_per_fragment: 1000, ..Default::default() };
+        let unbounded_frag_table = default_aa_table.compute_frag_table_with_neutral_losses(
+            &pep_seq, &located_mass_increments, &[b], &vec![1], &config, MassType::Monoisotopic
+        )?;
+        assert_eq!(unbounded_frag_table, frag_table);
+        let mut repeated_losses = NeutralLossCombination::default();
+        assert!((0..255).try_for_each(|_| repeated_losses.add(NeutralLoss::H2O)).is_ok());
+        assert!(repeated_losses.add(NeutralLoss::H2O).is_err());
+
+        let peak_mz = frag_table[0].mz_values[2] - 97.97690;
+        let matched_peaks = annotate_spectrum(&[[peak_mz, 100.0]], &frag_table, 0.01);
+        assert_eq!(matched_peaks.len(), 1);
+        assert_eq!(matched_peaks[0].neutral_losses, NeutralLossCombination::single(NeutralLoss::H3PO4));
+        assert_eq!(matched_peaks[0].aa_position, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn activation_fragmentation_configs() -> Result<()> {
+        let hcd_config = SimpleFragmentationConfig::default();
+        assert_eq!(hcd_config.activation_type, ActivationType::HCD);
+        assert!(hcd_config.contains_ion_series(b) && hcd_config.neutral_losses.is_some());
+        assert_eq!(hcd_config.fragment_charges(1), vec![1]);
+        assert_eq!(hcd_config.fragment_charges(3), vec![1, 2]);
+        assert_eq!(hcd_config.fragment_charges(5), vec![1, 2, 3]);
+
+        let etd_config = SimpleFragmentationConfig::new(ActivationType::ETD, MsAnalyzer::TRAP);
+        assert!(etd_config.contains_ion_series(c) && !etd_config.contains_ion_series(b));
+        assert!(etd_config.neutral_losses.is_none());
+        assert_eq!(etd_config.fragment_charges(5), vec![1, 2]);
+
+        let ethcd_config = SimpleFragmentationConfig::new(ActivationType::EThcD, MsAnalyzer::FTMS);
+        let frag_table = proteinogenic_amino_acid_table().compute_frag_table(b"INTERSTELLAR", &[], &ethcd_config, 3)?;
+        let loss_free_columns = frag_table.iter().filter(|col| col.fragment_type == FragmentType::Sequence && col.neutral_losses.is_empty()).count();
+        assert_eq!(loss_free_columns, (ethcd_config.ion_series.len() - 1) * 2);
+        assert!(frag_table.iter().any(|col| col.fragment_type == FragmentType::Satellite));
+        assert!(frag_table.len() > loss_free_columns);
+
+        // Phosphorylated serine at position 3
+        let pep_seq = b"GASPK";
+        let located_mass_increments = vec![(3, 79.96633)];
+        let cid_config = SimpleFragmentationConfig::new(ActivationType::CID, MsAnalyzer::TRAP);
+        let frag_table = proteinogenic_amino_acid_table().compute_frag_table(pep_seq, &located_mass_increments, &cid_config, 2)?;
+        let neutral_loss_config = cid_config.neutral_losses.as_ref().unwrap();
+        let expected_frag_table = proteinogenic_amino_acid_table().compute_frag_table_with_neutral_losses(
+            pep_seq, &located_mass_increments, &cid_config.ion_series, &vec![1], neutral_loss_config, MassType::Monoisotopic
+        )?;
+        assert_eq!(frag_table.len(), expected_frag_table.len());
+
+        let peak_mz = frag_table[0].mz_values[2] - 97.97690;
+        let matched_peaks = annotate_peptide_spectrum(&[[peak_mz, 100.0]], pep_seq, &located_mass_increments, proteinogenic_amino_acid_table(), &cid_config, 2, 0.01)?;
+        assert_eq!(matched_peaks.len(), 1);
+        assert_eq!(matched_peaks[0].neutral_losses, NeutralLossCombination::single(NeutralLoss::H3PO4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn internal_fragments() -> Result<()> {
+        // Phosphorylated threonine at position 4
+        let pep_seq = b"PEPTIDEK";
+        let located_mass_increments = vec![(4, 79.96633)];
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        let internal_config = InternalFragmentConfig { min_length: 2, max_length: 3, ..Default::default() };
+        let internal_table = default_aa_table.compute_internal_frag_table(pep_seq, &located_mass_increments, &internal_config, &[1, 2], MassType::Monoisotopic)?;
+        // yb and ya series of charge 1, with lengths 2 and 3
+        assert_eq!(internal_table.len(), 4);
+        assert!(internal_table.iter().all(|col| col.fragment_type == FragmentType::Internal));
+        assert!(internal_table.iter().flat_map(|col| col.residue_ranges.iter()).all(|(start, end)| *start >= 2 && *end <= 7));
+
+        // Internal b-type fragment PT(phospho) and its a-type counterpart
+        let pt_mz = 97.05276 + 101.04768 + 79.96633 + 1.007276;
+        let config = SimpleFragmentationConfig { internal_fragments: Some(internal_config), ..Default::default() };
+        let matched_peaks = annotate_peptide_spectrum(&[[pt_mz - 27.99491, 50.0], [pt_mz, 100.0]], pep_seq, &located_mass_increments, default_aa_table, &config, 2, 0.005)?;
+        let internal_peaks: Vec<_> = matched_peaks.iter().filter(|peak| peak.fragment_type == FragmentType::Internal).collect();
+        assert_eq!(internal_peaks.len(), 2);
+        assert_eq!(internal_peaks[0].ion_type, FragmentIonSeries::ya);
+        assert_eq!(internal_peaks[1].ion_type, FragmentIonSeries::yb);
+        assert!(internal_peaks.iter().all(|peak| peak.start_position == 3 && peak.end_position == 4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn immonium_and_diagnostic_ions() -> Result<()> {
+        // Acetylated lysine at position 3 and phosphorylated tyrosine at position 5
+        let pep_seq = b"AGKPYR";
+        let located_mass_increments = vec![(3, 42.010565), (5, 79.96633)];
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        let immonium_table = default_aa_table.compute_immonium_frag_table(pep_seq, &located_mass_increments, &ImmoniumIonConfig::default(), MassType::Monoisotopic)?;
+        // Immonium ions and their NH3 losses (K and R only)
+        assert_eq!(immonium_table.len(), 2);
+        assert!((immonium_table[0].mz_values[4] - 216.0420).abs() < 1e-3);
+        assert!((immonium_table[0].mz_values[5] - 129.1135).abs() < 1e-3);
+        assert!((_fragment_mz(&immonium_table[1], (3, 3)).unwrap() - 126.0913).abs() < 1e-3);
+        assert!(_fragment_mz(&immonium_table[1], (5, 5)).is_none());
+
+        let mut diagnostic_ions = DiagnosticIonLibrary::oxonium_ions();
+        diagnostic_ions.add(DiagnosticIon::new("DSSO-alkene", DiagnosticIonCategory::CrossLinker, 54.0106));
+        diagnostic_ions.merge(&DiagnosticIonLibrary::cross_linker_ions(CrossLinker::DSS));
+        assert_eq!(diagnostic_ions.ions[0].name, "HexNAc-C2H6O3");
+        assert_eq!(diagnostic_ions.ions_of_category(DiagnosticIonCategory::CrossLinker).count(), 3);
+        let config = SimpleFragmentationConfig { diagnostic_ions, ..Default::default() };
+        let peaks = [[126.0913, 10.0], [204.0867, 20.0], [216.0420, 30.0], [239.1754, 40.0]];
+        let matched_peaks = annotate_peptide_spectrum(&peaks, pep_seq, &located_mass_increments, default_aa_table, &config, 2, 0.005)?;
+
+        // The HexNAc fragment ion at m/z 126.055 is too far from the related ion of acetyl-Lys
+        assert_eq!(matched_peaks.len(), 4);
+        assert_eq!(matched_peaks[0].fragment_type, FragmentType::Immonium);
+        assert_eq!(matched_peaks[0].aa_position, 3);
+        assert_eq!(matched_peaks[1].fragment_type, FragmentType::Diagnostic);
+        assert_eq!(config.diagnostic_ions.ions[matched_peaks[1].frag_index as usize].name, "HexNAc");
+        assert_eq!((matched_peaks[2].ion_type, matched_peaks[2].start_position), (FragmentIonSeries::immonium, 5));
+        assert_eq!(config.diagnostic_ions.ions[matched_peaks[3].frag_index as usize].name, "DSS-K");
+
+        // Lys-remnant ions of the MS-cleavable cross-linkers
+        let dsso_ions = DiagnosticIonLibrary::cross_linker_ions(CrossLinker::DSSO);
+        assert!((dsso_ions.ions[2].mz - dsso_ions.ions[0].mz - 49.98264).abs() < 1e-4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn satellite_ions() -> Result<()> {
+        let pep_seq = b"ALIK";
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        let base_table = default_aa_table.compute_frag_table_without_mods(&Cow::from(&pep_seq[..]), &[a, y, z·], &vec![1], MassType::Monoisotopic)?;
+        let satellite_table = default_aa_table.compute_satellite_frag_table(pep_seq, &[], &[d, v, w], &vec![1], MassType::Monoisotopic)?;
+        let find_column = |ion_type: FragmentIonSeries| satellite_table.iter().find(|col| col.ion_type == ion_type).unwrap();
+        let (a_mz, y_mz, z_mz) = (&base_table[0].mz_values, &base_table[1].mz_values, &base_table[2].mz_values);
+
+        // Leu is distinguished from Ile by the loss of an isopropyl radical instead of an ethyl or a methyl radical
+        assert!((z_mz[2] - _fragment_mz(find_column(w), (2, 4)).unwrap() - 43.05478).abs() < 1e-4);
+        assert!(_fragment_mz(find_column(w), (3, 4)).is_none());
+        assert!((z_mz[1] - _fragment_mz(find_column(FragmentIonSeries::wa), (3, 4)).unwrap() - 29.03913).abs() < 1e-4);
+        assert!((z_mz[1] - _fragment_mz(find_column(FragmentIonSeries::wb), (3, 4)).unwrap() - 15.02348).abs() < 1e-4);
+        assert!(_fragment_mz(find_column(FragmentIonSeries::wa), (2, 4)).is_none());
+
+        // d ions lose an alkene (propene for Leu), v ions lose the whole side chain
+        assert!((a_mz[1] - _fragment_mz(find_column(d), (1, 2)).unwrap() - 42.04695).abs() < 1e-4);
+        assert!((y_mz[1] - _fragment_mz(find_column(v), (3, 4)).unwrap() - 56.06260).abs() < 1e-4);
+        assert_eq!(find_column(v).fragment_type, FragmentType::Satellite);
+
+        // No satellite ion for a modified residue
+        let mod_table = default_aa_table.compute_satellite_frag_table(pep_seq, &[(2, 15.99491)], &[w], &vec![1], MassType::Monoisotopic)?;
+        assert!(mod_table.iter().all(|col| _fragment_mz(col, (2, 4)).is_none()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn linear_peptide_frag_table() -> Result<()> {
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        // N-terminal acetylation, phosphorylated Ser and C-terminal amidation
+        let mods = vec![
+            SimpleModification { id: 1, mono_mass: 42.010565, position: Some(-1) },
+            SimpleModification { id: 21, mono_mass: 79.966331, position: Some(0) },
+            SimpleModification { id: 2, mono_mass: -0.984016, position: Some(7) },
+        ];
+        let peptide = LinearPeptide::new(Arc::from(b"SAMPLEK".as_slice()), mods.clone(), 900.0, None)?;
+
+        let neutral_losses = NeutralLossConfig {
+            rules: Vec::new(),
+            modification_losses: HashMap::from([(21, vec![NeutralLoss::H3PO4])]),
+            ..Default::default()
+        };
+        let config = SimpleFragmentationConfig {
+            ion_series: vec![b, y],
+            neutral_losses: Some(neutral_losses),
+            immonium_ions: Some(ImmoniumIonConfig::default()),
+            ..Default::default()
+        };
+        let frag_table = default_aa_table.compute_linear_peptide_frag_table(&peptide, &config, 2)?;
+
+        assert!((frag_table[0].mz_values[0] - 210.01621).abs() < 1e-4, "b1 = {}", frag_table[0].mz_values[0]);
+        assert!((frag_table[1].mz_values[0] - 146.12879).abs() < 1e-4, "y1 = {}", frag_table[1].mz_values[0]);
+
+        let b_h3po4 = frag_table.iter().find(|col| col.ion_type == b && !col.neutral_losses.is_empty()).unwrap();
+        assert!((frag_table[0].mz_values[0] - b_h3po4.mz_values[0] - 97.97690).abs() < 1e-4);
+        // The Ser immonium ion carries the phosphorylation but not the N-terminal acetylation
+        let immonium_ions = frag_table.iter().find(|col| col.fragment_type == FragmentType::Immonium).unwrap();
+        assert!((immonium_ions.mz_values[0] - 140.01073).abs() < 1e-4);
+
+        // Invalid or missing positions are reported as errors
+        let mut invalid_mods = mods.clone();
+        invalid_mods[1].position = Some(8);
+        let invalid_peptide = LinearPeptide::new(Arc::from(b"SAMPLEK".as_slice()), invalid_mods, 900.0, None)?;
+        assert!(default_aa_table.compute_linear_peptide_frag_table(&invalid_peptide, &config, 2).is_err());
+        invalid_mods = mods;
+        invalid_mods[1].position = None;
+        let invalid_peptide = LinearPeptide::new(Arc::from(b"SAMPLEK".as_slice()), invalid_mods, 900.0, None)?;
+        assert!(default_aa_table.compute_linear_peptide_frag_table(&invalid_peptide, &config, 2).is_err());
+
+        let mod_string_table = default_aa_table.compute_frag_table_from_mod_string(b"SAMPLEK", Some("42.010565@0,79.966331@1"), &config, 2)?;
+        assert!((mod_string_table[0].mz_values[0] - 210.01621).abs() < 1e-4);
+        assert!(default_aa_table.compute_frag_table_from_mod_string(b"SAMPLEK", Some("79.97"), &config, 2).is_err());
+        assert!(default_aa_table.compute_frag_table_from_mod_string(b"SAMPLEK", Some("79.97@9"), &config, 2).is_err());
+        assert!(default_aa_table.compute_frag_table_from_mod_string(b"", None, &config, 2).is_err());
+        assert!(default_aa_table.compute_frag_table(b"", &[], &config, 2).is_err());
+        assert!(default_aa_table.compute_frag_table_without_mods(&Cow::from(&b""[..]), &[b], &vec![1], MassType::Monoisotopic).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn fragment_isotope_envelopes() -> Result<()> {
+        let pep_seq = b"PEPTIDEK";
+        let default_aa_table = proteinogenic_amino_acid_table();
+
+        let config = SimpleFragmentationConfig {
+            ion_series: vec![b, y],
+            neutral_losses: None,
+            internal_fragments: None,
+            immonium_ions: None,
+            isotope_envelopes: Some(IsotopeEnvelopeConfig::default()),
+            ..Default::default()
+        };
+        let frag_table = default_aa_table.compute_frag_table(pep_seq, &[(4, 79.96633)], &config, 2)?;
+
+        // y3 (DEK, C15H26N4O7) has a M+1 peak of about 18% of the monoisotopic one
+        let y3_abundances = &frag_table[1].isotope_abundances[2];
+        assert!(y3_abundances.len() >= 2 && y3_abundances[0] == 1.0);
+        assert!(y3_abundances[1] > 0.16 && y3_abundances[1] < 0.2, "M+1 = {}", y3_abundances[1]);
+
+        let y3_mz = frag_table[1].mz_values[2];
+        let peaks = [[y3_mz, 100.0], [y3_mz + 1.00335, 18.0]];
+        let matched_peaks = annotate_peptide_spectrum(&peaks, pep_seq, &[(4, 79.96633)], default_aa_table, &config, 2, 0.005)?;
+        assert_eq!(matched_peaks.len(), 2);
+        assert_eq!((matched_peaks[0].isotope_index, matched_peaks[1].isotope_index), (0, 1));
+        assert_eq!((matched_peaks[1].start_position, matched_peaks[1].end_position), (6, 8));
+        assert!(isotope_envelope_consistency(&matched_peaks, &frag_table).unwrap() > 0.99);
+
+        let inconsistent_peaks = [[y3_mz, 20.0], [y3_mz + 1.00335, 100.0]];
+        let matched_peaks = annotate_peptide_spectrum(&inconsistent_peaks, pep_seq, &[(4, 79.96633)], default_aa_table, &config, 2, 0.005)?;
+        assert!(isotope_envelope_consistency(&matched_peaks, &frag_table).unwrap() < 0.5);
+
+        // Phosphopeptide with the default neutral losses (the H3PO4 loss exceeds the P atoms of the unmodified residues)
+        let hcd_config = SimpleFragmentationConfig { isotope_envelopes: Some(IsotopeEnvelopeConfig::default()), ..Default::default() };
+        let frag_table = default_aa_table.compute_frag_table(b"PEPTSDEK", &[(5, 79.96633)], &hcd_config, 2)?;
+        let y_h3po4 = frag_table.iter()
+            .find(|col| col.ion_type == y && col.charge == 1 && col.neutral_losses == NeutralLossCombination::single(NeutralLoss::H3PO4))
+            .unwrap();
+        assert!(y_h3po4.isotope_abundances.iter().all(|abundances| !abundances.is_empty() && abundances[0] == 1.0));
+        assert_eq!(y_h3po4.isotope_abundances.len(), y_h3po4.mz_values.len());
+
+        // Residues without a defined composition only affect the envelopes of the fragments containing them
+        let frag_table = default_aa_table.compute_frag_table(b"PEPXIDEK", &[], &config, 2)?;
+        assert_eq!(frag_table[1].isotope_abundances.len(), frag_table[1].mz_values.len());
+        assert_eq!(&frag_table[1].isotope_abundances[2], y3_abundances);
+
+        let average_config = SimpleFragmentationConfig { mass_type: MassType::Average, ..config };
+        assert!(default_aa_table.compute_frag_table(pep_seq, &[], &average_config, 2).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn precursor_derived_ions() -> Result<()> {
+        let pep_seq = b"PEPTIDEK";
+        let default_aa_table = proteinogenic_amino_acid_table();
+        let etd_config = SimpleFragmentationConfig::new(ActivationType::ETD, MsAnalyzer::FTMS);
+
+        let frag_table = default_aa_table.compute_frag_table(pep_seq, &[], &etd_config, 3)?;
+        let precursor_columns: Vec<_> = frag_table.iter().filter(|col| col.fragment_type == FragmentType::Precursor).collect();
+        // Charge states 3, 2 and 1, without loss or with the loss of H2O or NH3
+        assert_eq!(precursor_columns.len(), 9);
+        assert!((precursor_columns[0].mz_values[0] - 310.15891).abs() < 1e-4, "[M+3H]3+ = {}", precursor_columns[0].mz_values[0]);
+        // [M+3H]2+• carries an additional electron
+        let charge_reduced_mz = precursor_columns[3].mz_values[0];
+        assert_eq!((precursor_columns[3].charge, precursor_columns[3].neutral_losses.is_empty()), (2, true));
+        assert!((charge_reduced_mz - 465.23864).abs() < 1e-4, "[M+3H]2+. = {}", charge_reduced_mz);
+
+        let c2_mz = frag_table[0].mz_values[1];
+        let peaks = [[c2_mz, 50.0], [charge_reduced_mz - 9.00528, 20.0], [charge_reduced_mz, 100.0]];
+        let matched_peaks = annotate_peptide_spectrum(&peaks, pep_seq, &[], default_aa_table, &etd_config, 3, 0.005)?;
+        assert_eq!(matched_peaks.len(), 3);
+        assert!(matched_peaks[1..].iter().all(|peak| peak.fragment_type == FragmentType::Precursor));
+        assert_eq!(matched_peaks[1].neutral_losses, NeutralLossCombination::single(NeutralLoss::H2O));
+
+        let filtered_peaks = remove_precursor_peaks(&peaks, &frag_table, 0.005);
+        assert_eq!(filtered_peaks, vec![[c2_mz, 50.0]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mzpaf_annotations() -> Result<()> {
+        let annotation_strings = [
+            "b2-H2O^2/1.3ppm", "IY[Phospho]", "m3:5-CO", "y4+2i", "2@p-H2O[M+3H]^2/-0.0012",
+            "r[TMT127N]", "?17", "f{C13H9}/0.0020*0.75", "z5+H-2H3PO4^2", "w3+[Oxidation]",
+        ];
+        for annotation_string in annotation_strings {
+            let annotation: MzPafAnnotation = annotation_string.parse()?;
+            assert_eq!(annotation.to_string(), annotation_string);
+        }
+
+        let annotations = parse_mzpaf("b2-H2O^2/1.3ppm,IY[Phospho]")?;
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].ion, MzPafIon::Sequence { series: b, position: 2 });
+        assert_eq!(annotations[0].mass_deltas, vec![MzPafMassDelta::new(-1, "H2O")]);
+        assert_eq!((annotations[0].charge, annotations[0].mass_error), (2, Some((1.3, MassTolUnit::ppm))));
+        assert_eq!(annotations[0].neutral_loss_combination(), Some(NeutralLossCombination::single(NeutralLoss::H2O)));
+        assert_eq!(annotations[1].ion, MzPafIon::Immonium { residue: 'Y', modification: Some("Phospho".to_string()) });
+
+        let isotope_annotation: MzPafAnnotation = "y4+2i".parse()?;
+        assert_eq!((isotope_annotation.isotope, isotope_annotation.charge), (2, 1));
+        assert_eq!(parse_mzpaf("2@p-H2O[M+3H]^2")?[0].analyte_index, Some(2));
+        assert!("b2-".parse::<MzPafAnnotation>().is_err());
+        assert!("k2".parse::<MzPafAnnotation>().is_err());
+        // Out of range numbers are reported as errors
+        assert!("y4+100i+100i".parse::<MzPafAnnotation>().is_err());
+        assert!("b2^300".parse::<MzPafAnnotation>().is_err());
+        assert!("b70000".parse::<MzPafAnnotation>().is_err());
+        assert!("b2-40000H2O".parse::<MzPafAnnotation>().is_err());
+        assert!("99999999999@b2".parse::<MzPafAnnotation>().is_err());
+
+        // Write the annotations of matched peaks
+        let pep_seq = b"PEPTIDEK";
+        let default_aa_table = proteinogenic_amino_acid_table();
+        let etd_config = SimpleFragmentationConfig::new(ActivationType::ETD, MsAnalyzer::FTMS);
+        let frag_table = default_aa_table.compute_frag_table(pep_seq, &[], &etd_config, 3)?;
+
+        let c2_mz = frag_table[0].mz_values[1];
+        let charge_reduced_mz = frag_table.iter()
+            .find(|col| col.fragment_type == FragmentType::Precursor && col.charge == 2 && col.neutral_losses.is_empty())
+            .map(|col| col.mz_values[0])
+            .unwrap();
+        let peaks = [[c2_mz + 0.001, 50.0], [charge_reduced_mz, 100.0]];
+        let matched_peaks = annotate_peptide_spectrum(&peaks, pep_seq, &[], default_aa_table, &etd_config, 3, 0.005)?;
+
+        let peak_annotations = matched_peaks_to_mzpaf(&matched_peaks, pep_seq, &[], &etd_config.diagnostic_ions, 3, MassTolUnit::Da)?;
+        assert_eq!(peak_annotations, vec![(0, "c2/0.0010".to_string()), (1, "p[M+3H]^2/0.0000".to_string())]);
+
+        // Matched reporter ions are written with their mzPAF reference names
+        let tmt_config = SimpleFragmentationConfig { diagnostic_ions: DiagnosticIonLibrary::reporter_ions(IsobaricLabel::Tmt10), ..etd_config.clone() };
+        let reporter_peaks = [[127.124761, 100.0], [128.134436, 80.0]];
+        let matched_reporters = annotate_peptide_spectrum(&reporter_peaks, pep_seq, &[], default_aa_table, &tmt_config, 3, 0.002)?;
+        let reporter_annotations = matched_peaks_to_mzpaf(&matched_reporters, pep_seq, &[], &tmt_config.diagnostic_ions, 3, MassTolUnit::Da)?;
+        assert_eq!(reporter_annotations, vec![(0, "r[TMT127N]/0.0000".to_string()), (1, "r[TMT128C]/0.0000".to_string())]);
+        assert_eq!(parse_mzpaf(&reporter_annotations[0].1)?[0].ion, MzPafIon::Reference("TMT127N".to_string()));
+        assert_eq!(DiagnosticIonLibrary::reporter_ions(IsobaricLabel::Tmt6).ions[1].name, "TMT127N");
+        assert_eq!(DiagnosticIonLibrary::reporter_ions(IsobaricLabel::Itraq4).ions[0].name, "iTRAQ114");
 
+        let ppm_annotations = matched_peaks_to_mzpaf(&matched_peaks[..1], pep_seq, &[], &etd_config.diagnostic_ions, 3, MassTolUnit::ppm)?;
+        assert_eq!(ppm_annotations[0].1, format!("c2/{:.1}ppm", 0.001 * 1e6 / c2_mz));
+
+        Ok(())
+    }
 }
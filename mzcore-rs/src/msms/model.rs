@@ -1,18 +1,22 @@
 
+use anyhow::*;
 use serde::{Deserialize, Serialize};
 
-use crate::chemistry::constants::*;
+use crate::chemistry::composition::ElementalComposition;
 use crate::chemistry::element::Element;
 use crate::chemistry::peptide::LinearPeptide;
 use crate::chemistry::table::biomolecule_atom_table;
+use crate::ms::MassType;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum ActivationType {
     CID,
     ECD,
     ETD,
+    EThcD,
     HCD,
     PSD,
+    UVPD,
 }
 
 impl std::fmt::Display for ActivationType {
@@ -65,8 +69,12 @@ pub enum FragmentIonSeries {
     c_H2O,
     c_NH3,
     d,
+    da,
+    db,
     v,
     w,
+    wa,
+    wb,
     x,
     x_H2O,
     x_NH3,
@@ -85,54 +93,81 @@ pub enum FragmentIonSeries {
     z_p2,
     z_p3,
     immonium,
+    precursor,
+    diagnostic,
 }
 
 impl FragmentIonSeries {
 
-    pub fn get_ion_mono_mass_shift(&self) -> f64 {
+    /// Elemental composition added to the sum of the residue compositions to obtain the neutral fragment.
+    /// Returns None for the satellite series (d, v, w) whose composition depends on the side chain of the cleaved residue,
+    /// and for the diagnostic ions which are not derived from the peptide sequence.
+    pub fn composition_delta(&self) -> Option<ElementalComposition> {
+        use Element::{C, H, N, O};
 
-        // TODO: define as constants?
-        let atom_table = biomolecule_atom_table();
-        let h_mono_mass = atom_table.atom_by_element.get(&Element::H).unwrap().monoisotopic_mass();
-        let n_mono_mass = atom_table.atom_by_element.get(&Element::N).unwrap().monoisotopic_mass();
-        let o_mono_mass = atom_table.atom_by_element.get(&Element::O).unwrap().monoisotopic_mass();
+        let element_counts: &[(Element, i16)] = match self {
+            Self::a => &[(C, -1), (O, -1)],
+            Self::a_H2O => &[(C, -1), (O, -2), (H, -2)],
+            Self::a_NH3 => &[(C, -1), (O, -1), (N, -1), (H, -3)],
+            Self::b => &[],
+            Self::b_H2O => &[(H, -2), (O, -1)],
+            Self::b_NH3 => &[(N, -1), (H, -3)],
+            Self::c => &[(N, 1), (H, 3)],
+            // The radical c ion results from the loss of a hydrogen atom (same composition as c-1)
+            Self::c· => &[(N, 1), (H, 2)],
+            Self::c_m1 => &[(N, 1), (H, 2)],
+            Self::c_p1 => &[(N, 1), (H, 4)],
+            Self::c_p2 => &[(N, 1), (H, 5)],
+            Self::c_H2O => &[(N, 1), (H, 1), (O, -1)],
+            Self::c_NH3 => &[],
+            Self::d | Self::da | Self::db | Self::v | Self::w | Self::wa | Self::wb | Self::diagnostic => return None,
+            Self::x => &[(C, 1), (O, 2)],
+            Self::x_H2O => &[(C, 1), (O, 1), (H, -2)],
+            Self::x_NH3 => &[(C, 1), (O, 2), (N, -1), (H, -3)],
+            Self::y => &[(H, 2), (O, 1)],
+            Self::y_H2O => &[],
+            Self::y_NH3 => &[(H, -1), (O, 1), (N, -1)],
+            // Internal fragments of a and b types
+            Self::ya => &[(C, -1), (O, -1)],
+            Self::yb => &[],
+            Self::z => &[(H, -1), (O, 1), (N, -1)],
+            Self::z_H2O => &[(H, -3), (N, -1)],
+            Self::z_NH3 => &[(H, -4), (O, 1), (N, -2)],
+            // The radical z ion results from the gain of a hydrogen atom (same composition as z+1)
+            Self::z· => &[(O, 1), (N, -1)],
+            Self::z_p1 => &[(O, 1), (N, -1)],
+            Self::z_p2 => &[(H, 1), (O, 1), (N, -1)],
+            Self::z_p3 => &[(H, 2), (O, 1), (N, -1)],
+            // Single residue a-type ion
+            Self::immonium => &[(C, -1), (O, -1)],
+            // Intact peptide
+            Self::precursor => &[(H, 2), (O, 1)],
+        };
 
+        Some(ElementalComposition::from_monoisotope_tuples(element_counts))
+    }
+
+    /// Mass added to the sum of the residue masses to obtain the neutral fragment mass
+    pub fn mass_shift(&self, mass_type: MassType) -> Result<f64> {
+        let composition_delta = self.composition_delta()
+            .ok_or_else(|| anyhow!("the mass of {} ions can't be derived from the residue masses", self))?;
+
+        _calc_composition_mass(&composition_delta, mass_type)
+    }
+
+    /// Satellite ions result from the side chain cleavage of the residue adjacent to the backbone cleavage site
+    pub fn is_satellite(&self) -> bool {
+        matches!(self, Self::d | Self::da | Self::db | Self::v | Self::w | Self::wa | Self::wb)
+    }
+
+    /// Variants of a satellite series: the residues with two possible side chain cleavages (Ile and Thr)
+    /// produce the 'a' and 'b' variants, the other residues the unlabeled series
+    pub fn satellite_variants(&self) -> &'static [FragmentIonSeries] {
         match self {
-            //M => 0.0,
-            //M_H2O => - WATER_MONO_MASS,
-            //M_NH3 => - NH3_MONO_MASS,
-            Self::a =>  - (WATER_MONO_MASS + CO_MONO_MASS), //Composition(formula='H-2O-1' + 'C-1O-1'),
-            Self::a_H2O => - (2.0 * WATER_MONO_MASS + CO_MONO_MASS), //Composition(formula='H-2O-1' + 'C-1O-1' + 'H-2O-1'),
-            Self::a_NH3 => - (CO_MONO_MASS + NH3_MONO_MASS + WATER_MONO_MASS), //Composition(formula='H-2O-1' + 'C-1O-1' + 'N-1H-3'),
-            Self::b => - WATER_MONO_MASS, //Composition(formula='H-2O-1'),
-            Self::b_H2O => -2.0 * WATER_MONO_MASS, //Composition(formula='H-2O-1' + 'H-2O-1'),
-            Self::b_NH3 => - (WATER_MONO_MASS + NH3_MONO_MASS), //Composition(formula='H-2O-1' + 'N-1H-3'),
-            Self::c => - WATER_MONO_MASS + NH3_MONO_MASS,
-            Self::c· => - WATER_MONO_MASS + NH3_MONO_MASS + PROTON_MASS,
-            Self::c_m1 => - WATER_MONO_MASS + NH3_MONO_MASS - PROTON_MASS,
-            Self::c_p1 => - WATER_MONO_MASS + NH3_MONO_MASS + PROTON_MASS,
-            Self::c_p2 => - WATER_MONO_MASS + NH3_MONO_MASS + 2.0 * PROTON_MASS,
-            Self::c_H2O => - 2.0 * WATER_MONO_MASS + NH3_MONO_MASS,
-            Self::c_NH3 => - WATER_MONO_MASS,
-            Self::d => 0.0, // FIXME
-            Self::v => 0.0, // FIXME
-            Self::w => 0.0, // FIXME
-            Self::x => - WATER_MONO_MASS + CO2_MONO_MASS,
-            Self::x_H2O => - 2.0 * WATER_MONO_MASS + CO2_MONO_MASS,
-            Self::x_NH3 => - WATER_MONO_MASS - NH3_MONO_MASS + CO2_MONO_MASS,
-            Self::y => 0.0,
-            Self::y_H2O => - WATER_MONO_MASS,
-            Self::y_NH3 => - NH3_MONO_MASS,
-            Self::ya => 0.0, // FIXME
-            Self::yb => 0.0, // FIXME
-            Self::z => - WATER_MONO_MASS + o_mono_mass - n_mono_mass,
-            Self::z_H2O => - 2.0 * WATER_MONO_MASS + o_mono_mass - n_mono_mass - h_mono_mass,
-            Self::z_NH3 => - WATER_MONO_MASS - NH3_MONO_MASS + o_mono_mass - n_mono_mass - h_mono_mass,
-            Self::z· => - WATER_MONO_MASS + o_mono_mass - n_mono_mass, // TODO: check me
-            Self::z_p1 => - WATER_MONO_MASS + o_mono_mass - n_mono_mass + h_mono_mass,
-            Self::z_p2 => - WATER_MONO_MASS + o_mono_mass - n_mono_mass + 2.0 * h_mono_mass,
-            Self::z_p3 => - WATER_MONO_MASS + o_mono_mass - n_mono_mass + 3.0 * h_mono_mass,
-            Self::immonium => 0.0 // FIXME
+            Self::d | Self::da | Self::db => &[Self::d, Self::da, Self::db],
+            Self::v => &[Self::v],
+            Self::w | Self::wa | Self::wb => &[Self::w, Self::wa, Self::wb],
+            _ => &[],
         }
     }
 
@@ -165,17 +200,21 @@ impl FragmentIonSeries {
             Self::c_p2 => SeriesDir::NTerminal,
             Self::c_H2O => SeriesDir::NTerminal,
             Self::c_NH3 => SeriesDir::NTerminal,
-            Self::d => SeriesDir::Unspecified, // FIXME
-            Self::v => SeriesDir::Unspecified, // FIXME
-            Self::w => SeriesDir::Unspecified, // FIXME
+            Self::d => SeriesDir::NTerminal,
+            Self::da => SeriesDir::NTerminal,
+            Self::db => SeriesDir::NTerminal,
+            Self::v => SeriesDir::CTerminal,
+            Self::w => SeriesDir::CTerminal,
+            Self::wa => SeriesDir::CTerminal,
+            Self::wb => SeriesDir::CTerminal,
             Self::x => SeriesDir::CTerminal,
             Self::x_H2O => SeriesDir::CTerminal,
             Self::x_NH3 => SeriesDir::CTerminal,
             Self::y => SeriesDir::CTerminal,
             Self::y_H2O => SeriesDir::CTerminal,
             Self::y_NH3 => SeriesDir::CTerminal,
-            Self::ya => SeriesDir::CTerminal,
-            Self::yb => SeriesDir::CTerminal,
+            Self::ya => SeriesDir::Unspecified, // internal fragment
+            Self::yb => SeriesDir::Unspecified, // internal fragment
             Self::z => SeriesDir::CTerminal,
             Self::z_H2O => SeriesDir::CTerminal,
             Self::z_NH3 => SeriesDir::CTerminal,
@@ -184,6 +223,8 @@ impl FragmentIonSeries {
             Self::z_p2 => SeriesDir::CTerminal,
             Self::z_p3 => SeriesDir::CTerminal,
             Self::immonium => SeriesDir::Unspecified,
+            Self::precursor => SeriesDir::Unspecified,
+            Self::diagnostic => SeriesDir::Unspecified,
         }
     }
 }
@@ -200,17 +241,21 @@ impl std::fmt::Display for FragmentIonSeries {
             a_H2O => write!(f, "a-H2O"),
             b => write!(f, "b"),
             b_NH3 => write!(f, "b-NH3"),
-            b_H2O => write!(f, "a-H2O"),
+            b_H2O => write!(f, "b-H2O"),
             c => write!(f, "c"),
             c· => write!(f, "c·"),
             c_m1 => write!(f, "c-1"),
             c_p1 => write!(f, "c+1"),
-            c_p2 => write!(f, "c+1"),
+            c_p2 => write!(f, "c+2"),
             c_NH3 => write!(f, "c-NH3"),
             c_H2O => write!(f, "c-H2O"),
             d => write!(f, "d"),
+            da => write!(f, "da"),
+            db => write!(f, "db"),
             v => write!(f, "v"),
             w => write!(f, "w"),
+            wa => write!(f, "wa"),
+            wb => write!(f, "wb"),
             x => write!(f, "x"),
             x_NH3 => write!(f, "x-NH3"),
             x_H2O => write!(f, "x-H2O"),
@@ -227,14 +272,18 @@ impl std::fmt::Display for FragmentIonSeries {
             z_p2 => write!(f, "z+2"),
             z_p3 => write!(f, "z+3"),
             immonium => write!(f, "immonium"),
+            precursor => write!(f, "precursor"),
+            diagnostic => write!(f, "diagnostic"),
         }
     }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum FragmentType {
+    Diagnostic,
     Immonium,
     Internal,
+    Precursor,
     Satellite,
     Sequence,
 }
@@ -245,8 +294,10 @@ impl std::fmt::Display for FragmentType {
         use FragmentType::*;
 
         match self {
+            Diagnostic => write!(f, "DIAG"),
             Immonium => write!(f, "IM"),
             Internal => write!(f, "IN"),
+            Precursor => write!(f, "PREC"),
             Satellite => write!(f, "SAT"),
             Sequence => write!(f, "SEQ"),
         }
@@ -279,12 +330,108 @@ pub enum NeutralLoss {
     NH3,
 }
 
+impl NeutralLoss {
+    pub const ALL: [NeutralLoss; 5] = [Self::CH4OS, Self::H2O, Self::H3PO4, Self::HPO3, Self::NH3];
+
+    pub fn composition(&self) -> ElementalComposition {
+        use Element::{C, H, N, O, P, S};
+
+        let element_counts: &[(Element, i16)] = match self {
+            Self::CH4OS => &[(C, 1), (H, 4), (O, 1), (S, 1)],
+            Self::H2O => &[(H, 2), (O, 1)],
+            Self::H3PO4 => &[(H, 3), (P, 1), (O, 4)],
+            Self::HPO3 => &[(H, 1), (P, 1), (O, 3)],
+            Self::NH3 => &[(N, 1), (H, 3)],
+        };
+
+        ElementalComposition::from_monoisotope_tuples(element_counts)
+    }
+
+    pub fn mass(&self, mass_type: MassType) -> Result<f64> {
+        _calc_composition_mass(&self.composition(), mass_type)
+    }
+}
+
 impl std::fmt::Display for NeutralLoss {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+/// Number of neutral losses of each type undergone by a fragment ion (no loss by default)
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct NeutralLossCombination {
+    counts: [u8; NeutralLoss::ALL.len()],
+}
+
+impl NeutralLossCombination {
+    pub fn single(neutral_loss: NeutralLoss) -> Self {
+        let mut combination = Self::default();
+        combination.counts[neutral_loss as usize] = 1;
+        combination
+    }
+
+    /// Add a loss to the combination (a given loss can't be undergone more than 255 times)
+    pub fn add(&mut self, neutral_loss: NeutralLoss) -> Result<()> {
+        let count = &mut self.counts[neutral_loss as usize];
+        *count = count.checked_add(1).ok_or_else(|| anyhow!("too many {} losses in a neutral loss combination", neutral_loss))?;
+        Ok(())
+    }
+
+    pub fn count(&self, neutral_loss: NeutralLoss) -> u8 {
+        self.counts[neutral_loss as usize]
+    }
+
+    pub fn total_count(&self) -> usize {
+        self.counts.iter().map(|count| *count as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_count() == 0
+    }
+
+    /// Iterate over the losses of the combination and their counts
+    pub fn iter(&self) -> impl Iterator<Item = (NeutralLoss, u8)> + '_ {
+        NeutralLoss::ALL.iter()
+            .map(|neutral_loss| (*neutral_loss, self.count(*neutral_loss)))
+            .filter(|(_, count)| *count > 0)
+    }
+
+    pub fn composition(&self) -> ElementalComposition {
+        let mut composition = ElementalComposition::default();
+        for (neutral_loss, count) in self.iter() {
+            composition += neutral_loss.composition() * (count as i16);
+        }
+        composition
+    }
+
+    /// Total mass lost by the fragment
+    pub fn mass(&self, mass_type: MassType) -> Result<f64> {
+        _calc_composition_mass(&self.composition(), mass_type)
+    }
+}
+
+impl std::fmt::Display for NeutralLossCombination {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (neutral_loss, count) in self.iter() {
+            if count > 1 {
+                write!(f, "-{}{}", count, neutral_loss)?;
+            } else {
+                write!(f, "-{}", neutral_loss)?;
+            }
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+
+fn _calc_composition_mass(composition: &ElementalComposition, mass_type: MassType) -> Result<f64> {
+    let atom_table = biomolecule_atom_table();
+    match mass_type {
+        MassType::Monoisotopic => atom_table.calc_mono_mass(composition),
+        MassType::Average => atom_table.calc_average_mass(composition),
+    }
+}
+
 /*
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 struct TheoreticalFragmentIon {
@@ -0,0 +1,506 @@
+//! Reading and writing of peak annotations in the HUPO-PSI mzPAF format (e.g. "b2-H2O^2/1.3ppm")
+
+use std::str::FromStr;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ms::utils::MassTolUnit;
+use crate::msms::annotator::MatchedPeak;
+use crate::msms::diagnostic_ions::DiagnosticIonLibrary;
+use crate::msms::fragmentation::LocatedMassIncrement;
+use crate::msms::model::{FragmentIonSeries, FragmentType, NeutralLoss, NeutralLossCombination};
+
+/// Ion part of an mzPAF annotation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MzPafIon {
+    /// Unexplained peak ("?"), optionally numbered to group related peaks ("?17")
+    Unknown(Option<u16>),
+    /// Backbone or satellite ion ("b2", "y5", "w3"), the series being one of a, b, c, d, v, w, x, y and z
+    Sequence { series: FragmentIonSeries, position: u16 },
+    /// b/y type internal fragment spanning residues start..=end, starting at 1 ("m3:5")
+    Internal { start: u16, end: u16 },
+    /// Immonium ion of a residue, optionally modified ("IY[Phospho]")
+    Immonium { residue: char, modification: Option<String> },
+    /// Intact precursor ion ("p")
+    Precursor,
+    /// Ion of a reference library, such as a reporter or an oxonium ion ("r[TMT127N]")
+    Reference(String),
+    /// Ion given by its elemental formula ("f{C13H9}")
+    Formula(String),
+}
+
+/// Formula or named group gained (positive count) or lost (negative count) by an ion ("-H2O", "-2H3PO4", "+[Phospho]")
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MzPafMassDelta {
+    pub count: i16,
+    /// Elemental formula, or name enclosed in square brackets
+    pub group: String,
+}
+
+impl MzPafMassDelta {
+    pub fn new(count: i16, group: &str) -> Self {
+        MzPafMassDelta { count, group: group.to_string() }
+    }
+}
+
+/// Structured representation of a single mzPAF peak annotation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MzPafAnnotation {
+    /// Index of the analyte (starting at 1) when several analytes are annotated in the same spectrum ("2@y5")
+    pub analyte_index: Option<u16>,
+    pub ion: MzPafIon,
+    pub mass_deltas: Vec<MzPafMassDelta>,
+    /// Number of 13C (or average isotope) mass shifts (0 for the monoisotopic peak)
+    pub isotope: i8,
+    /// Content of the adduct brackets, such as "M+3H" for a charge-reduced precursor
+    pub adduct: Option<String>,
+    pub charge: i8,
+    /// Observed - theoretical m/z value, in Da or in ppm
+    pub mass_error: Option<(f64, MassTolUnit)>,
+    pub confidence: Option<f32>,
+}
+
+impl MzPafAnnotation {
+    pub fn new(ion: MzPafIon, charge: i8) -> Self {
+        MzPafAnnotation {
+            analyte_index: None,
+            ion,
+            mass_deltas: Vec::new(),
+            isotope: 0,
+            adduct: None,
+            charge,
+            mass_error: None,
+            confidence: None,
+        }
+    }
+
+    /// Describe a matched peak using the mzPAF notation.
+    /// The ion series variants are written relative to the base series (e.g. "z+H" for the radical z ion),
+    /// the Ile/Thr variants of the satellite ions being reported as the unlabeled series.
+    pub fn from_matched_peak(
+        matched_peak: &MatchedPeak,
+        pep_seq: &[u8],
+        located_mass_increments: &[LocatedMassIncrement],
+        diagnostic_ions: &DiagnosticIonLibrary,
+        precursor_charge: i8,
+        mass_error_unit: MassTolUnit,
+    ) -> Result<Self> {
+        use FragmentIonSeries::*;
+
+        let pep_seq_len = pep_seq.len();
+        let start_pos = matched_peak.start_position as usize;
+        let end_pos = matched_peak.end_position as usize;
+
+        let (ion, deltas): (MzPafIon, &[(i16, &str)]) = match matched_peak.fragment_type {
+            FragmentType::Diagnostic => {
+                let diagnostic_ion = diagnostic_ions.ions.get(matched_peak.frag_index as usize)
+                    .ok_or_else(|| anyhow!("no diagnostic ion at index {}", matched_peak.frag_index))?;
+                (MzPafIon::Reference(diagnostic_ion.name.clone()), &[])
+            }
+            FragmentType::Precursor => (MzPafIon::Precursor, &[]),
+            FragmentType::Immonium => {
+                if start_pos == 0 || start_pos > pep_seq_len {
+                    bail!("invalid immonium ion position ({}) for peptide of length {}", start_pos, pep_seq_len);
+                }
+
+                let modification_mass: f64 = located_mass_increments.iter()
+                    .filter(|(aa_pos, _)| *aa_pos == start_pos)
+                    .map(|(_, mass_increment)| mass_increment)
+                    .sum();
+                let modification = if modification_mass == 0.0 { None } else { Some(format!("{:+.4}", modification_mass)) };
+
+                (MzPafIon::Immonium { residue: pep_seq[start_pos - 1] as char, modification }, &[])
+            }
+            FragmentType::Internal => {
+                let internal_ion = MzPafIon::Internal { start: matched_peak.start_position, end: matched_peak.end_position };
+                match matched_peak.ion_type {
+                    ya => (internal_ion, &[(-1, "CO")]),
+                    _ => (internal_ion, &[]),
+                }
+            }
+            FragmentType::Satellite | FragmentType::Sequence => {
+                let (series, deltas): (FragmentIonSeries, &[(i16, &str)]) = match matched_peak.ion_type {
+                    a_H2O => (a, &[(-1, "H2O")]),
+                    a_NH3 => (a, &[(-1, "NH3")]),
+                    b_H2O => (b, &[(-1, "H2O")]),
+                    b_NH3 => (b, &[(-1, "NH3")]),
+                    c· | c_m1 => (c, &[(-1, "H")]),
+                    c_p1 => (c, &[(1, "H")]),
+                    c_p2 => (c, &[(2, "H")]),
+                    c_H2O => (c, &[(-1, "H2O")]),
+                    c_NH3 => (c, &[(-1, "NH3")]),
+                    da | db => (d, &[]),
+                    wa | wb => (w, &[]),
+                    x_H2O => (x, &[(-1, "H2O")]),
+                    x_NH3 => (x, &[(-1, "NH3")]),
+                    y_H2O => (y, &[(-1, "H2O")]),
+                    y_NH3 => (y, &[(-1, "NH3")]),
+                    z_H2O => (z, &[(-1, "H2O")]),
+                    z_NH3 => (z, &[(-1, "NH3")]),
+                    z· | z_p1 => (z, &[(1, "H")]),
+                    z_p2 => (z, &[(2, "H")]),
+                    z_p3 => (z, &[(3, "H")]),
+                    other => (other, &[]),
+                };
+
+                let position = match series.is_n_terminal() {
+                    Some(true) => end_pos,
+                    Some(false) => (pep_seq_len + 1).checked_sub(start_pos)
+                        .ok_or_else(|| anyhow!("invalid fragment position ({}) for peptide of length {}", start_pos, pep_seq_len))?,
+                    None => bail!("{} ions can't be written as sequence ions", series),
+                };
+
+                let position = u16::try_from(position).map_err(|_| anyhow!("fragment position out of range ({})", position))?;
+                (MzPafIon::Sequence { series, position }, deltas)
+            }
+        };
+
+        let mut annotation = MzPafAnnotation::new(ion, matched_peak.charge);
+        annotation.mass_deltas = deltas.iter().map(|(count, group)| MzPafMassDelta::new(*count, group)).collect();
+        annotation.mass_deltas.extend(
+            matched_peak.neutral_losses.iter().map(|(neutral_loss, count)| MzPafMassDelta::new(-(count as i16), &neutral_loss.to_string()))
+        );
+        annotation.isotope = i8::try_from(matched_peak.isotope_index)
+            .map_err(|_| anyhow!("isotope index out of range ({})", matched_peak.isotope_index))?;
+
+        // The electrons transferred to a charge-reduced precursor are expressed through its adduct
+        if matched_peak.fragment_type == FragmentType::Precursor && matched_peak.charge != precursor_charge {
+            annotation.adduct = Some(format!("M+{}H", precursor_charge));
+        }
+
+        let mz_error = matched_peak.mz_error as f64;
+        annotation.mass_error = Some(match mass_error_unit {
+            MassTolUnit::ppm => (mz_error * 1e6 / matched_peak.theo_mz, MassTolUnit::ppm),
+            _ => (mz_error, MassTolUnit::Da),
+        });
+
+        Ok(annotation)
+    }
+
+    /// Neutral losses of the annotation, if they are all losses of known neutral molecules
+    pub fn neutral_loss_combination(&self) -> Option<NeutralLossCombination> {
+        let mut combination = NeutralLossCombination::default();
+        for mass_delta in self.mass_deltas.iter() {
+            if mass_delta.count >= 0 {
+                return None;
+            }
+
+            let neutral_loss = NeutralLoss::ALL.iter().find(|neutral_loss| neutral_loss.to_string() == mass_delta.group)?;
+            for _ in 0..-mass_delta.count {
+                combination.add(*neutral_loss).ok()?;
+            }
+        }
+
+        Some(combination)
+    }
+}
+
+/// Parse a comma-separated list of mzPAF annotations (the annotations of a single peak)
+pub fn parse_mzpaf(annotations: &str) -> Result<Vec<MzPafAnnotation>> {
+    let mut parsed_annotations = Vec::new();
+    let mut bracket_depth = 0;
+    let mut annotation_start = 0;
+
+    for (char_idx, c) in annotations.char_indices() {
+        match c {
+            '[' | '{' => bracket_depth += 1,
+            ']' | '}' => bracket_depth -= 1,
+            ',' if bracket_depth == 0 => {
+                parsed_annotations.push(annotations[annotation_start..char_idx].parse()?);
+                annotation_start = char_idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parsed_annotations.push(annotations[annotation_start..].parse()?);
+
+    Ok(parsed_annotations)
+}
+
+/// Write the annotations of a single peak as a comma-separated list
+pub fn format_mzpaf(annotations: &[MzPafAnnotation]) -> String {
+    annotations.iter().map(|annotation| annotation.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// Write the mzPAF annotations of each annotated peak, as (peak index, annotations) tuples
+pub fn matched_peaks_to_mzpaf(
+    matched_peaks: &[MatchedPeak],
+    pep_seq: &[u8],
+    located_mass_increments: &[LocatedMassIncrement],
+    diagnostic_ions: &DiagnosticIonLibrary,
+    precursor_charge: i8,
+    mass_error_unit: MassTolUnit,
+) -> Result<Vec<(usize, String)>> {
+    let mut annotations_by_peak: Vec<(usize, Vec<MzPafAnnotation>)> = Vec::new();
+
+    for matched_peak in matched_peaks {
+        let annotation = MzPafAnnotation::from_matched_peak(
+            matched_peak, pep_seq, located_mass_increments, diagnostic_ions, precursor_charge, mass_error_unit
+        )?;
+
+        match annotations_by_peak.iter_mut().find(|(peak_idx, _)| *peak_idx == matched_peak.peak_index) {
+            Some((_, peak_annotations)) => peak_annotations.push(annotation),
+            None => annotations_by_peak.push((matched_peak.peak_index, vec![annotation])),
+        }
+    }
+
+    Ok(annotations_by_peak.iter().map(|(peak_idx, peak_annotations)| (*peak_idx, format_mzpaf(peak_annotations))).collect())
+}
+
+impl std::fmt::Display for MzPafIon {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MzPafIon::Unknown(None) => write!(f, "?"),
+            MzPafIon::Unknown(Some(number)) => write!(f, "?{}", number),
+            MzPafIon::Sequence { series, position } => write!(f, "{}{}", series, position),
+            MzPafIon::Internal { start, end } => write!(f, "m{}:{}", start, end),
+            MzPafIon::Immonium { residue, modification: None } => write!(f, "I{}", residue),
+            MzPafIon::Immonium { residue, modification: Some(modification) } => write!(f, "I{}[{}]", residue, modification),
+            MzPafIon::Precursor => write!(f, "p"),
+            MzPafIon::Reference(name) => write!(f, "r[{}]", name),
+            MzPafIon::Formula(formula) => write!(f, "f{{{}}}", formula),
+        }
+    }
+}
+
+impl std::fmt::Display for MzPafMassDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sign = if self.count < 0 { '-' } else { '+' };
+        match self.count.abs() {
+            1 => write!(f, "{}{}", sign, self.group),
+            count => write!(f, "{}{}{}", sign, count, self.group),
+        }
+    }
+}
+
+impl std::fmt::Display for MzPafAnnotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(analyte_index) = self.analyte_index {
+            write!(f, "{}@", analyte_index)?;
+        }
+
+        write!(f, "{}", self.ion)?;
+
+        for mass_delta in self.mass_deltas.iter() {
+            write!(f, "{}", mass_delta)?;
+        }
+
+        match self.isotope {
+            0 => {}
+            1 => write!(f, "+i")?,
+            -1 => write!(f, "-i")?,
+            isotope => write!(f, "{:+}i", isotope)?,
+        }
+
+        if let Some(adduct) = self.adduct.as_ref() {
+            write!(f, "[{}]", adduct)?;
+        }
+
+        if self.charge != 1 {
+            write!(f, "^{}", self.charge)?;
+        }
+
+        match self.mass_error {
+            Some((mass_error, MassTolUnit::ppm)) => write!(f, "/{:.1}ppm", mass_error)?,
+            Some((mass_error, MassTolUnit::mmu)) => write!(f, "/{:.4}", mass_error / 1000.0)?,
+            Some((mass_error, MassTolUnit::Da)) => write!(f, "/{:.4}", mass_error)?,
+            None => {}
+        }
+
+        if let Some(confidence) = self.confidence {
+            write!(f, "*{}", confidence)?;
+        }
+
+        std::fmt::Result::Ok(())
+    }
+}
+
+impl FromStr for MzPafAnnotation {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut reader = _MzPafReader { chars: value.trim().chars().collect(), pos: 0 };
+
+        let analyte_index = reader.read_integer::<u16>("analyte index")?;
+        if analyte_index.is_some() {
+            reader.expect('@')?;
+        }
+
+        let ion = reader.read_ion()?;
+        let mut annotation = MzPafAnnotation::new(ion, 1);
+        annotation.analyte_index = analyte_index;
+
+        // Mass deltas and isotope, both introduced by a sign
+        while let Some(sign) = reader.peek().filter(|c| *c == '+' || *c == '-') {
+            reader.pos += 1;
+            let sign: i16 = if sign == '-' { -1 } else { 1 };
+            let count = reader.read_integer::<i16>("count")?.unwrap_or(1);
+
+            match reader.peek() {
+                Some('i') => {
+                    reader.pos += 1;
+                    if reader.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        bail!("isotopes of a specific element are not supported in '{}'", value);
+                    }
+                    annotation.isotope = i8::try_from(sign * count).ok()
+                        .and_then(|isotope| annotation.isotope.checked_add(isotope))
+                        .ok_or_else(|| anyhow!("isotope out of range in '{}'", value))?;
+                }
+                Some('[') => {
+                    let name = reader.read_enclosed('[', ']')?;
+                    annotation.mass_deltas.push(MzPafMassDelta::new(sign * count, &format!("[{}]", name)));
+                }
+                Some(c) if c.is_ascii_uppercase() => {
+                    let formula = reader.read_while(|c| c.is_ascii_alphanumeric());
+                    annotation.mass_deltas.push(MzPafMassDelta::new(sign * count, &formula));
+                }
+                _ => bail!("invalid mass delta at position {} of '{}'", reader.pos, value),
+            }
+        }
+
+        if reader.peek() == Some('[') {
+            annotation.adduct = Some(reader.read_enclosed('[', ']')?);
+        }
+
+        if reader.peek() == Some('^') {
+            reader.pos += 1;
+            annotation.charge = reader.read_integer("charge state")?.ok_or_else(|| anyhow!("missing charge state in '{}'", value))?;
+        }
+
+        if reader.peek() == Some('/') {
+            reader.pos += 1;
+            let mass_error = reader.read_while(|c| c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e');
+            let mass_error: f64 = mass_error.parse().map_err(|_| anyhow!("invalid mass error '{}' in '{}'", mass_error, value))?;
+
+            if reader.read_while(|c| c.is_ascii_alphabetic()) == "ppm" {
+                annotation.mass_error = Some((mass_error, MassTolUnit::ppm));
+            } else {
+                annotation.mass_error = Some((mass_error, MassTolUnit::Da));
+            }
+        }
+
+        if reader.peek() == Some('*') {
+            reader.pos += 1;
+            let confidence = reader.read_while(|c| c.is_ascii_digit() || c == '.');
+            annotation.confidence = Some(confidence.parse().map_err(|_| anyhow!("invalid confidence '{}' in '{}'", confidence, value))?);
+        }
+
+        if reader.pos < reader.chars.len() {
+            bail!("unexpected character '{}' at position {} of '{}'", reader.chars[reader.pos], reader.pos, value);
+        }
+
+        Ok(annotation)
+    }
+}
+
+struct _MzPafReader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl _MzPafReader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => bail!("expected '{}' at position {}", expected, self.pos),
+        }
+    }
+
+    fn read_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&predicate) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Read an unsigned integer, checking that it fits in the target type
+    fn read_integer<N: FromStr>(&mut self, value_name: &str) -> Result<Option<N>> {
+        let digits = self.read_while(|c| c.is_ascii_digit());
+        if digits.is_empty() {
+            return Ok(None);
+        }
+
+        let number = digits.parse().map_err(|_| anyhow!("{} out of range ({})", value_name, digits))?;
+        Ok(Some(number))
+    }
+
+    /// Read the content of a bracketed group, nested brackets being kept in the content
+    fn read_enclosed(&mut self, open: char, close: char) -> Result<String> {
+        self.expect(open)?;
+
+        let start = self.pos;
+        let mut depth = 1;
+        while let Some(c) = self.peek() {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    let content = self.chars[start..self.pos].iter().collect();
+                    self.pos += 1;
+                    return Ok(content);
+                }
+            }
+            self.pos += 1;
+        }
+
+        bail!("missing '{}' after position {}", close, start)
+    }
+
+    fn read_position(&mut self) -> Result<u16> {
+        self.read_integer("ion position")?.ok_or_else(|| anyhow!("missing ion position at position {}", self.pos))
+    }
+
+    fn read_ion(&mut self) -> Result<MzPafIon> {
+        let first_char = self.peek().ok_or_else(|| anyhow!("empty annotation"))?;
+        self.pos += 1;
+
+        let series = match first_char {
+            'a' => Some(FragmentIonSeries::a),
+            'b' => Some(FragmentIonSeries::b),
+            'c' => Some(FragmentIonSeries::c),
+            'd' => Some(FragmentIonSeries::d),
+            'v' => Some(FragmentIonSeries::v),
+            'w' => Some(FragmentIonSeries::w),
+            'x' => Some(FragmentIonSeries::x),
+            'y' => Some(FragmentIonSeries::y),
+            'z' => Some(FragmentIonSeries::z),
+            _ => None,
+        };
+        if let Some(series) = series {
+            return Ok(MzPafIon::Sequence { series, position: self.read_position()? });
+        }
+
+        let ion = match first_char {
+            '?' => MzPafIon::Unknown(self.read_integer("unknown ion number")?),
+            'm' => {
+                let start = self.read_position()?;
+                self.expect(':')?;
+                MzPafIon::Internal { start, end: self.read_position()? }
+            }
+            'I' => {
+                let residue = self.peek().filter(|c| c.is_ascii_uppercase())
+                    .ok_or_else(|| anyhow!("missing immonium ion residue at position {}", self.pos))?;
+                self.pos += 1;
+
+                let modification = if self.peek() == Some('[') { Some(self.read_enclosed('[', ']')?) } else { None };
+                MzPafIon::Immonium { residue, modification }
+            }
+            'p' => MzPafIon::Precursor,
+            'r' => MzPafIon::Reference(self.read_enclosed('[', ']')?),
+            'f' => MzPafIon::Formula(self.read_enclosed('{', '}')?),
+            other => bail!("unsupported ion type '{}'", other),
+        };
+
+        Ok(ion)
+    }
+}
@@ -0,0 +1,408 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ms::preprocessing::{estimate_noise_levels, NoiseEstimation};
+use crate::ms::spectrum::Peak;
+use crate::ms::utils::{binary_search_slice, MassTolWindow};
+
+/// Mass difference between 13C and 12C
+const C13_MASS_DIFF: f64 = 1.0033548378;
+
+/// Maximum m/z difference used to assign an isotopic impurity to a reporter channel
+const IMPURITY_CHANNEL_MZ_TOL: f64 = 0.01;
+
+/// Isobaric labeling reagents (TMT 16-plex and 18-plex correspond to the TMTpro reagents)
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum IsobaricLabel {
+    Tmt6,
+    Tmt10,
+    Tmt11,
+    TmtPro16,
+    TmtPro18,
+    Itraq4,
+    Itraq8,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReporterIon {
+    pub channel: String,
+    pub mz: f64,
+}
+
+impl IsobaricLabel {
+    /// Reporter ions sorted by m/z (singly charged m/z values)
+    pub fn reporter_ions(&self) -> Vec<ReporterIon> {
+        let channels: &[(&str, f64)] = match self {
+            IsobaricLabel::Tmt6 => &TMT6_REPORTER_IONS,
+            IsobaricLabel::Tmt10 => &TMT11_REPORTER_IONS[..10],
+            IsobaricLabel::Tmt11 => &TMT11_REPORTER_IONS,
+            IsobaricLabel::TmtPro16 => &TMTPRO18_REPORTER_IONS[..16],
+            IsobaricLabel::TmtPro18 => &TMTPRO18_REPORTER_IONS,
+            IsobaricLabel::Itraq4 => &ITRAQ8_REPORTER_IONS[1..5],
+            IsobaricLabel::Itraq8 => &ITRAQ8_REPORTER_IONS,
+        };
+
+        channels.iter().map(|(channel, mz)| ReporterIon { channel: channel.to_string(), mz: *mz }).collect()
+    }
+
+    pub fn n_channels(&self) -> usize {
+        match self {
+            IsobaricLabel::Tmt6 => 6,
+            IsobaricLabel::Tmt10 => 10,
+            IsobaricLabel::Tmt11 => 11,
+            IsobaricLabel::TmtPro16 => 16,
+            IsobaricLabel::TmtPro18 => 18,
+            IsobaricLabel::Itraq4 => 4,
+            IsobaricLabel::Itraq8 => 8,
+        }
+    }
+}
+
+const TMT6_REPORTER_IONS: [(&str, f64); 6] = [
+    ("126", 126.127726),
+    ("127", 127.124761),
+    ("128", 128.134436),
+    ("129", 129.131471),
+    ("130", 130.141145),
+    ("131", 131.138180),
+];
+
+const TMT11_REPORTER_IONS: [(&str, f64); 11] = [
+    ("126", 126.127726),
+    ("127N", 127.124761),
+    ("127C", 127.131081),
+    ("128N", 128.128116),
+    ("128C", 128.134436),
+    ("129N", 129.131471),
+    ("129C", 129.137790),
+    ("130N", 130.134825),
+    ("130C", 130.141145),
+    ("131N", 131.138180),
+    ("131C", 131.144500),
+];
+
+const TMTPRO18_REPORTER_IONS: [(&str, f64); 18] = [
+    ("126", 126.127726),
+    ("127N", 127.124761),
+    ("127C", 127.131081),
+    ("128N", 128.128116),
+    ("128C", 128.134436),
+    ("129N", 129.131471),
+    ("129C", 129.137790),
+    ("130N", 130.134825),
+    ("130C", 130.141145),
+    ("131N", 131.138180),
+    ("131C", 131.144500),
+    ("132N", 132.141535),
+    ("132C", 132.147855),
+    ("133N", 133.144890),
+    ("133C", 133.151210),
+    ("134N", 134.148245),
+    ("134C", 134.154565),
+    ("135N", 135.151600),
+];
+
+const ITRAQ8_REPORTER_IONS: [(&str, f64); 8] = [
+    ("113", 113.107325),
+    ("114", 114.110680),
+    ("115", 115.107715),
+    ("116", 116.111069),
+    ("117", 117.114424),
+    ("118", 118.111459),
+    ("119", 119.114814),
+    ("121", 121.121524),
+];
+
+/// Isotopic impurities of a reporter channel, as reported by the manufacturer (in percent)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReporterImpurities {
+    pub minus_2: f64,
+    pub minus_1: f64,
+    pub plus_1: f64,
+    pub plus_2: f64,
+}
+
+/// Correction matrix M where M[i][j] is the fraction of the signal of channel j observed in channel i
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImpurityCorrectionMatrix {
+    pub matrix: Vec<Vec<f64>>,
+}
+
+impl ImpurityCorrectionMatrix {
+    pub fn new(matrix: Vec<Vec<f64>>) -> Result<ImpurityCorrectionMatrix> {
+        let n = matrix.len();
+        if matrix.iter().any(|row| row.len() != n) {
+            bail!("the impurity correction matrix must be a square matrix")
+        }
+        if matrix.iter().flatten().any(|v| !v.is_finite() || *v < 0.0) {
+            bail!("the impurity correction matrix must contain positive finite values only")
+        }
+        Ok(ImpurityCorrectionMatrix { matrix })
+    }
+
+    /// Build the matrix from the manufacturer's product data sheet. Each impurity is assigned to the channel located
+    /// at the corresponding number of 13C mass differences, or lost if this channel is not part of the plex.
+    pub fn from_impurities(reporter_ions: &[ReporterIon], impurities: &[ReporterImpurities]) -> Result<ImpurityCorrectionMatrix> {
+        let n = reporter_ions.len();
+        if impurities.len() != n {
+            bail!("expected impurities for {} channels but got {}", n, impurities.len())
+        }
+
+        let mut matrix = vec![vec![0.0; n]; n];
+        for (channel_idx, channel_impurities) in impurities.iter().enumerate() {
+            let offsets = [
+                (-2.0, channel_impurities.minus_2),
+                (-1.0, channel_impurities.minus_1),
+                (1.0, channel_impurities.plus_1),
+                (2.0, channel_impurities.plus_2),
+            ];
+
+            let total_impurity: f64 = offsets.iter().map(|(_, percent)| percent).sum::<f64>() / 100.0;
+            if !(0.0..1.0).contains(&total_impurity) {
+                bail!("invalid impurities for channel {}", reporter_ions[channel_idx].channel)
+            }
+            matrix[channel_idx][channel_idx] = 1.0 - total_impurity;
+
+            for (offset, percent) in offsets {
+                let target_mz = reporter_ions[channel_idx].mz + offset * C13_MASS_DIFF;
+                let target_channel_idx = reporter_ions.iter().enumerate()
+                    .map(|(idx, ion)| (idx, (ion.mz - target_mz).abs()))
+                    .filter(|(_, mz_diff)| *mz_diff <= IMPURITY_CHANNEL_MZ_TOL)
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(idx, _)| idx);
+
+                if let Some(target_channel_idx) = target_channel_idx {
+                    matrix[target_channel_idx][channel_idx] += percent / 100.0;
+                }
+            }
+        }
+
+        Ok(ImpurityCorrectionMatrix { matrix })
+    }
+
+    /// Estimate the true channel intensities by solving the correction system with a non-negativity constraint
+    /// (non-negative least squares solved by coordinate descent)
+    pub fn correct(&self, observed_intensities: &[f32]) -> Result<Vec<f32>> {
+        let n = self.matrix.len();
+        if observed_intensities.len() != n {
+            bail!("expected {} reporter intensities but got {}", n, observed_intensities.len())
+        }
+
+        let observed: Vec<f64> = observed_intensities.iter().map(|ab| *ab as f64).collect();
+        let column_sq_norms: Vec<f64> = (0..n).map(|j| self.matrix.iter().map(|row| row[j] * row[j]).sum()).collect();
+
+        let mut solution: Vec<f64> = observed.iter().map(|ab| ab.max(0.0)).collect();
+        let mut residuals: Vec<f64> = (0..n).map(|i| {
+            observed[i] - self.matrix[i].iter().zip(&solution).map(|(m, x)| m * x).sum::<f64>()
+        }).collect();
+
+        let scale = observed.iter().map(|ab| ab.abs()).fold(0.0, f64::max).max(f64::MIN_POSITIVE);
+        for _ in 0..1000 {
+            let mut max_update: f64 = 0.0;
+            for j in 0..n {
+                if column_sq_norms[j] == 0.0 { continue }
+
+                let gradient: f64 = (0..n).map(|i| self.matrix[i][j] * residuals[i]).sum();
+                let new_value = (solution[j] + gradient / column_sq_norms[j]).max(0.0);
+                let delta = new_value - solution[j];
+                if delta != 0.0 {
+                    for (residual, row) in residuals.iter_mut().zip(&self.matrix) {
+                        *residual -= row[j] * delta;
+                    }
+                    solution[j] = new_value;
+                    max_update = max_update.max(delta.abs());
+                }
+            }
+
+            if max_update <= 1e-9 * scale {
+                break
+            }
+        }
+
+        Ok(solution.into_iter().map(|v| v as f32).collect())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IsobaricQuantConfig {
+    pub label: IsobaricLabel,
+    pub mz_tolerance: MassTolWindow,
+    pub impurity_correction: Option<ImpurityCorrectionMatrix>,
+    /// Noise estimation used when the instrument does not provide peak noise levels
+    pub noise_estimation: NoiseEstimation,
+    pub min_sum_signal_to_noise: f32,
+    pub min_isolation_purity: f32,
+    /// Minimum fraction of SPS precursors matching fragments of the identified peptide (MS3 only)
+    pub min_sps_match_fraction: f32,
+}
+
+impl IsobaricQuantConfig {
+    pub fn new(label: IsobaricLabel) -> IsobaricQuantConfig {
+        IsobaricQuantConfig {
+            label,
+            mz_tolerance: MassTolWindow::ppm(-20.0, 20.0),
+            impurity_correction: None,
+            noise_estimation: NoiseEstimation::Median,
+            min_sum_signal_to_noise: 0.0,
+            min_isolation_purity: 0.0,
+            min_sps_match_fraction: 0.0,
+        }
+    }
+}
+
+/// Spectrum used to quantify a PSM
+#[derive(Clone, Copy, Debug)]
+pub enum ReporterSpectrum<'a> {
+    /// Reporter ions measured in the MS2 spectrum used for identification
+    Ms2 { peaks: &'a [Peak], noise_levels: Option<&'a [f32]> },
+    /// Reporter ions measured in an MS3 spectrum obtained by synchronous precursor selection (SPS) of MS2 fragments.
+    /// `theo_fragment_mz_values` are the m/z values of the fragments of the identified peptide.
+    Ms3 {
+        peaks: &'a [Peak],
+        noise_levels: Option<&'a [f32]>,
+        sps_mz_values: &'a [f64],
+        theo_fragment_mz_values: &'a [f64],
+    },
+}
+
+/// Reporter intensity vector of a PSM, ready for peptide and protein rollup
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReporterQuantification {
+    pub raw_intensities: Vec<f32>,
+    /// Intensities after impurity correction (equal to raw intensities if no correction is configured)
+    pub intensities: Vec<f32>,
+    pub signal_to_noise: Vec<f32>,
+    pub sum_signal_to_noise: f32,
+    pub isolation_purity: Option<f32>,
+    pub sps_match_fraction: Option<f32>,
+}
+
+/// Extract the most intense peak of each reporter channel (peaks sorted by m/z),
+/// returned as (intensity, noise level) pairs. Missing reporters get a zero intensity.
+pub fn extract_reporter_ions(peaks: &[Peak], noise_levels: &[f32], reporter_ions: &[ReporterIon], mz_tolerance: MassTolWindow) -> Vec<(f32, f32)> {
+    reporter_ions.iter().map(|reporter_ion| {
+        let (lo, hi) = mz_tolerance.bounds(reporter_ion.mz);
+        let (i, j) = binary_search_slice(peaks, |peak, query| peak.mz.total_cmp(query), lo, hi);
+
+        (i..j).filter(|&idx| peaks[idx].mz >= lo && peaks[idx].mz <= hi)
+            .max_by(|&a, &b| peaks[a].intensity.total_cmp(&peaks[b].intensity))
+            .map(|idx| (peaks[idx].intensity, noise_levels.get(idx).copied().unwrap_or(0.0)))
+            .unwrap_or((0.0, 0.0))
+    }).collect()
+}
+
+/// Fraction of the SPS precursors matching a theoretical fragment of the identified peptide
+pub fn sps_match_fraction(sps_mz_values: &[f64], theo_fragment_mz_values: &[f64], mz_tolerance: MassTolWindow) -> f32 {
+    if sps_mz_values.is_empty() {
+        return 0.0
+    }
+
+    let n_matches = sps_mz_values.iter()
+        .filter(|&&sps_mz| theo_fragment_mz_values.iter().any(|&theo_mz| mz_tolerance.contains(theo_mz, sps_mz)))
+        .count();
+
+    n_matches as f32 / sps_mz_values.len() as f32
+}
+
+/// Quantify the reporter ions of a PSM. Returns None if the PSM does not pass the configured filters.
+pub fn quantify_psm(spectrum: ReporterSpectrum, isolation_purity: Option<f32>, config: &IsobaricQuantConfig) -> Result<Option<ReporterQuantification>> {
+    let (peaks, noise_levels, sps_match_fraction) = match spectrum {
+        ReporterSpectrum::Ms2 { peaks, noise_levels } => (peaks, noise_levels, None),
+        ReporterSpectrum::Ms3 { peaks, noise_levels, sps_mz_values, theo_fragment_mz_values } => {
+            let fraction = self::sps_match_fraction(sps_mz_values, theo_fragment_mz_values, config.mz_tolerance);
+            (peaks, noise_levels, Some(fraction))
+        }
+    };
+
+    if let Some(noise_levels) = noise_levels {
+        if noise_levels.len() != peaks.len() {
+            bail!("noise levels and peaks must have the same length")
+        }
+    }
+
+    if isolation_purity.is_some_and(|purity| purity < config.min_isolation_purity)
+        || sps_match_fraction.is_some_and(|fraction| fraction < config.min_sps_match_fraction) {
+        return Ok(None)
+    }
+
+    let estimated_noise_levels;
+    let noise_levels = match noise_levels {
+        Some(noise_levels) => noise_levels,
+        None => {
+            estimated_noise_levels = estimate_noise_levels(peaks, config.noise_estimation);
+            &estimated_noise_levels
+        }
+    };
+
+    let reporter_ions = config.label.reporter_ions();
+    let extracted_reporters = extract_reporter_ions(peaks, noise_levels, &reporter_ions, config.mz_tolerance);
+
+    let raw_intensities: Vec<f32> = extracted_reporters.iter().map(|(intensity, _)| *intensity).collect();
+    let signal_to_noise: Vec<f32> = extracted_reporters.iter()
+        .map(|&(intensity, noise)| if noise > 0.0 { intensity / noise } else { 0.0 })
+        .collect();
+    let sum_signal_to_noise: f32 = signal_to_noise.iter().sum();
+
+    if sum_signal_to_noise < config.min_sum_signal_to_noise {
+        return Ok(None)
+    }
+
+    let intensities = match &config.impurity_correction {
+        Some(correction_matrix) => correction_matrix.correct(&raw_intensities)?,
+        None => raw_intensities.clone(),
+    };
+
+    Ok(Some(ReporterQuantification {
+        raw_intensities,
+        intensities,
+        signal_to_noise,
+        sum_signal_to_noise,
+        isolation_purity,
+        sps_match_fraction,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tmt10_impurity_correction() -> Result<()> {
+        let reporter_ions = IsobaricLabel::Tmt10.reporter_ions();
+        let mut impurities = vec![ReporterImpurities::default(); 10];
+        impurities[0] = ReporterImpurities { plus_1: 5.0, ..Default::default() }; // 126 -> 127C
+        impurities[1] = ReporterImpurities { minus_1: 2.0, plus_1: 3.0, ..Default::default() }; // 127N -> 126 and 128N
+
+        let correction_matrix = ImpurityCorrectionMatrix::from_impurities(&reporter_ions, &impurities)?;
+        assert!((correction_matrix.matrix[2][0] - 0.05).abs() < 1e-12);
+        assert!((correction_matrix.matrix[0][1] - 0.02).abs() < 1e-12);
+        assert!((correction_matrix.matrix[3][1] - 0.03).abs() < 1e-12);
+
+        // Channel 127N is empty: the observed signal only comes from the 126 impurities
+        let true_intensities = [1000.0, 0.0, 500.0, 800.0, 0.0, 0.0, 0.0, 0.0, 0.0, 100.0];
+        let mut peaks: Vec<Peak> = reporter_ions.iter().enumerate().map(|(i, ion)| {
+            let observed: f64 = (0..10).map(|j| correction_matrix.matrix[i][j] * true_intensities[j]).sum();
+            Peak { mz: ion.mz + 0.0005, intensity: observed as f32 }
+        }).collect();
+        peaks.push(Peak { mz: 300.0, intensity: 10.0 });
+
+        let config = IsobaricQuantConfig {
+            impurity_correction: Some(correction_matrix),
+            ..IsobaricQuantConfig::new(IsobaricLabel::Tmt10)
+        };
+
+        let noise_levels = vec![10.0; peaks.len()];
+        let quant = quantify_psm(ReporterSpectrum::Ms2 { peaks: &peaks, noise_levels: Some(&noise_levels) }, Some(0.9), &config)?.unwrap();
+        assert_eq!(quant.raw_intensities[2], 550.0);
+        for (corrected, expected) in quant.intensities.iter().zip(true_intensities) {
+            assert!((corrected - expected as f32).abs() < 0.01, "expected {} but got {}", expected, corrected);
+        }
+        assert!((quant.signal_to_noise[0] - 95.0).abs() < 1e-3);
+
+        let sps_spectrum = ReporterSpectrum::Ms3 { peaks: &peaks, noise_levels: None, sps_mz_values: &[500.0, 600.0], theo_fragment_mz_values: &[500.001] };
+        let strict_config = IsobaricQuantConfig { min_sps_match_fraction: 0.7, ..config };
+        assert!(quantify_psm(sps_spectrum, None, &strict_config)?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::api::AminoAcidFactory;
+use crate::chemistry::composition::ElementalComposition;
+use crate::chemistry::constants::AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF;
+use crate::chemistry::element::Element as El;
+use crate::chemistry::isotope_distribution::IsotopeDistribution;
+use crate::chemistry::peptide::LinearPeptide;
+use crate::chemistry::table::{biomolecule_atom_table, AminoAcidTable};
+use crate::chemistry::unimod::format_unimod_composition;
+use crate::common::stats::median;
+use crate::ms::processing::select_most_intense_peak;
+use crate::ms::spectrum::Peak;
+use crate::ms::utils::{mass_to_mz, mz_to_mass, MassTolWindow};
+
+/// Label of a given residue: isotope substitutions of the residue atoms (e.g. 13C6 15N2 for SILAC Lys8)
+/// and/or a composition added by chemical labeling (e.g. dimethylation of the lysine side chain)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResidueLabel {
+    pub residue: u8,
+    pub isotope_substitutions: Vec<(El, u8)>,
+    pub added_composition: ElementalComposition,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelingChannel {
+    pub name: String,
+    /// Isotope substitutions applied to all the atoms of the peptide (metabolic labeling, e.g. 15N)
+    pub global_isotope_substitutions: Vec<(El, u8)>,
+    pub residue_labels: Vec<ResidueLabel>,
+    /// Composition added to the peptide N-terminus (chemical labeling)
+    pub n_term_label: Option<ElementalComposition>,
+}
+
+impl LabelingChannel {
+    pub fn unlabeled(name: &str) -> LabelingChannel {
+        LabelingChannel {
+            name: name.to_string(),
+            global_isotope_substitutions: Vec::new(),
+            residue_labels: Vec::new(),
+            n_term_label: None,
+        }
+    }
+
+    /// Light, medium (Lys4/Arg6) and heavy (Lys8/Arg10) SILAC channels
+    pub fn silac_channels() -> Vec<LabelingChannel> {
+        let lys4 = ResidueLabel {
+            residue: b'K',
+            isotope_substitutions: Vec::new(),
+            added_composition: ElementalComposition::from_tuples(&[(El::H, 0, -4), (El::H, 1, 4)]),
+        };
+        let arg6 = ResidueLabel {
+            residue: b'R',
+            isotope_substitutions: vec![(El::C, 1)],
+            added_composition: ElementalComposition::default(),
+        };
+        let lys8 = ResidueLabel {
+            residue: b'K',
+            isotope_substitutions: vec![(El::C, 1), (El::N, 1)],
+            added_composition: ElementalComposition::default(),
+        };
+        let arg10 = ResidueLabel {
+            residue: b'R',
+            isotope_substitutions: vec![(El::C, 1), (El::N, 1)],
+            added_composition: ElementalComposition::default(),
+        };
+
+        vec![
+            Self::unlabeled("light"),
+            LabelingChannel { residue_labels: vec![lys4, arg6], ..Self::unlabeled("medium") },
+            LabelingChannel { residue_labels: vec![lys8, arg10], ..Self::unlabeled("heavy") },
+        ]
+    }
+
+    /// Natural and fully 15N-labeled channels
+    pub fn n15_channels() -> Vec<LabelingChannel> {
+        vec![
+            Self::unlabeled("14N"),
+            LabelingChannel { global_isotope_substitutions: vec![(El::N, 1)], ..Self::unlabeled("15N") },
+        ]
+    }
+
+    /// Light (+C2H4), medium (+C2D4) and heavy (+13C2D6H-2) dimethyl labeling of peptide N-termini and lysines
+    pub fn dimethyl_channels() -> Vec<LabelingChannel> {
+        let dimethyl_channel = |name: &str, dimethyl: ElementalComposition| LabelingChannel {
+            residue_labels: vec![ResidueLabel { residue: b'K', isotope_substitutions: Vec::new(), added_composition: dimethyl.clone() }],
+            n_term_label: Some(dimethyl),
+            ..Self::unlabeled(name)
+        };
+
+        vec![
+            dimethyl_channel("light", ElementalComposition::from_tuples(&[(El::C, 0, 2), (El::H, 0, 4)])),
+            dimethyl_channel("medium", ElementalComposition::from_tuples(&[(El::C, 0, 2), (El::H, 1, 4)])),
+            dimethyl_channel("heavy", ElementalComposition::from_tuples(&[(El::C, 1, 2), (El::H, 1, 6), (El::H, 0, -2)])),
+        ]
+    }
+
+    /// Composition of a residue in this channel
+    pub fn residue_composition(&self, residue: u8, aa_table: &AminoAcidTable) -> Result<ElementalComposition> {
+        let mut composition = aa_table.aa_from_byte(&residue)?.composition()?;
+        for residue_label in self.residue_labels.iter().filter(|label| label.residue == residue) {
+            composition = composition.with_global_isotope_modifications(&residue_label.isotope_substitutions) + &residue_label.added_composition;
+        }
+
+        Ok(composition.with_global_isotope_modifications(&self.global_isotope_substitutions))
+    }
+
+    /// Composition of a peptide in this channel (modifications are only taken into account as additional masses)
+    pub fn peptide_composition(&self, peptide: &LinearPeptide, aa_table: &AminoAcidTable) -> Result<ElementalComposition> {
+        let mut composition = ElementalComposition::from_monoisotope_tuples(&[(El::H, 2), (El::O, 1)]);
+        if let Some(n_term_label) = &self.n_term_label {
+            composition += n_term_label;
+        }
+
+        let mut composition = composition.with_global_isotope_modifications(&self.global_isotope_substitutions);
+        for residue in peptide.sequence.iter() {
+            composition += &self.residue_composition(*residue, aa_table)?;
+        }
+        composition.additional_mass += peptide.mods.iter().map(|m| m.mono_mass).sum::<f64>();
+
+        Ok(composition)
+    }
+
+    pub fn peptide_mono_mass(&self, peptide: &LinearPeptide, aa_table: &AminoAcidTable) -> Result<f64> {
+        biomolecule_atom_table().calc_mono_mass(&self.peptide_composition(peptide, aa_table)?)
+    }
+
+    /// Copy of an amino acid table where the residues are replaced by their labeled variants
+    /// (the N-terminal label is not part of the table). Residues without a formula are kept unchanged.
+    pub fn labeled_amino_acid_table(&self, aa_table: &AminoAcidTable) -> Result<AminoAcidTable> {
+        let atom_table = biomolecule_atom_table();
+
+        let amino_acids = aa_table.amino_acids.iter().map(|aa_def| {
+            if aa_def.formula.is_none() {
+                return Ok(aa_def.clone())
+            }
+
+            let composition = self.residue_composition(aa_def.code1, aa_table)?;
+            let mut labeled_aa_def = aa_def.clone();
+            labeled_aa_def.formula = Some(format_unimod_composition(&composition)?);
+            labeled_aa_def.mono_mass = atom_table.calc_mono_mass(&composition)?;
+            labeled_aa_def.average_mass = atom_table.calc_average_mass(&composition)?;
+
+            Ok(labeled_aa_def)
+        }).collect::<Result<Vec<_>>>()?;
+
+        AminoAcidTable::new(amino_acids)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabeledPairConfig {
+    pub mz_tolerance: MassTolWindow,
+    pub min_charge: i8,
+    pub max_charge: i8,
+    /// Number of isotopic peaks summed to compute the intensity of a channel
+    pub n_isotopes: usize,
+    /// Minimum number of consecutive isotopic peaks observed in each channel
+    pub min_isotopes: usize,
+}
+
+impl Default for LabeledPairConfig {
+    fn default() -> Self {
+        LabeledPairConfig {
+            mz_tolerance: MassTolWindow::ppm(-10.0, 10.0),
+            min_charge: 1,
+            max_charge: 5,
+            n_isotopes: 3,
+            min_isotopes: 2,
+        }
+    }
+}
+
+/// Isotopic envelopes of the same peptide observed in the different labeling channels
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabeledPair {
+    pub light_mono_mz: f64,
+    pub charge: i8,
+    /// Mass shifts of the heavier channels relative to the light channel
+    pub mass_shifts: Vec<f64>,
+    /// Intensities of the light channel followed by the heavier channels
+    pub channel_intensities: Vec<f32>,
+    /// Ratios of the heavier channels to the light channel
+    pub ratios: Vec<f64>,
+}
+
+/// Intensity of each channel of a peptide (monoisotopic masses of the channels) at a given charge state.
+/// Channels whose minimum number of isotopic peaks is not observed get a zero intensity.
+pub fn quantify_labeled_peptide(peaks: &[Peak], channel_mono_masses: &[f64], charge: i8, config: &LabeledPairConfig) -> Vec<f32> {
+    channel_mono_masses.iter().map(|&mono_mass| {
+        _envelope_intensity(peaks, mass_to_mz(mono_mass, charge as i32), charge, config).unwrap_or(0.0)
+    }).collect()
+}
+
+/// Detect groups of labeled isotopic envelopes in an MS1 spectrum (peaks sorted by m/z).
+/// Each candidate in `candidate_mass_shifts` lists the mass shifts of the heavier channels relative to the light one
+/// (e.g. `[8.0142]` for a peptide containing one Lys8). Envelopes are seeded by decreasing peak intensity, the monoisotopic
+/// peak being searched below the seed peak since it is not the most intense one for large peptides.
+pub fn detect_labeled_pairs(peaks: &[Peak], candidate_mass_shifts: &[Vec<f64>], config: &LabeledPairConfig) -> Vec<LabeledPair> {
+    let mut intensity_order: Vec<usize> = (0..peaks.len()).collect();
+    intensity_order.sort_by(|&a, &b| peaks[b].intensity.total_cmp(&peaks[a].intensity));
+
+    let mut is_used = vec![false; peaks.len()];
+    let mut pairs = Vec::new();
+
+    for peak_idx in intensity_order {
+        if is_used[peak_idx] {
+            continue
+        }
+
+        // Best hypothesis: (total intensity, labeled pair)
+        let mut best_hypothesis: Option<(f32, LabeledPair)> = None;
+        for charge in config.min_charge.max(1)..=config.max_charge {
+            let Some(light_mono_mz) = _find_monoisotopic_mz(peaks, &is_used, peaks[peak_idx].mz, charge, config) else { continue };
+            if _envelope_has_used_peaks(peaks, &is_used, light_mono_mz, charge, config) {
+                continue
+            }
+            let Some(light_intensity) = _envelope_intensity(peaks, light_mono_mz, charge, config) else { continue };
+
+            for mass_shifts in candidate_mass_shifts {
+                if mass_shifts.iter().any(|shift| _envelope_has_used_peaks(peaks, &is_used, light_mono_mz + shift / charge as f64, charge, config)) {
+                    continue
+                }
+                let heavy_intensities: Option<Vec<f32>> = mass_shifts.iter()
+                    .map(|shift| _envelope_intensity(peaks, light_mono_mz + shift / charge as f64, charge, config))
+                    .collect();
+                let Some(heavy_intensities) = heavy_intensities else { continue };
+
+                let channel_intensities: Vec<f32> = std::iter::once(light_intensity).chain(heavy_intensities).collect();
+                let total_intensity: f32 = channel_intensities.iter().sum();
+                if best_hypothesis.as_ref().is_none_or(|best| total_intensity > best.0) {
+                    let light_intensity = channel_intensities[0] as f64;
+                    best_hypothesis = Some((total_intensity, LabeledPair {
+                        light_mono_mz,
+                        charge,
+                        mass_shifts: mass_shifts.clone(),
+                        ratios: channel_intensities[1..].iter().map(|ab| *ab as f64 / light_intensity).collect(),
+                        channel_intensities,
+                    }));
+                }
+            }
+        }
+
+        let Some((_, pair)) = best_hypothesis else { continue };
+
+        // Mark the peaks of all envelopes as used, including the observed isotopes following the quantified ones
+        // so that the tail of an envelope doesn't seed another one
+        let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / pair.charge as f64;
+        for mono_mz in std::iter::once(pair.light_mono_mz).chain(pair.mass_shifts.iter().map(|shift| pair.light_mono_mz + shift / pair.charge as f64)) {
+            for isotope_idx in 0.. {
+                let peak_indices = _peak_index_range(peaks, mono_mz + isotope_idx as f64 * isotope_spacing, config.mz_tolerance);
+                if peak_indices.is_empty() && isotope_idx >= config.n_isotopes.max(config.min_isotopes) {
+                    break
+                }
+                for idx in peak_indices {
+                    is_used[idx] = true;
+                }
+            }
+        }
+
+        pairs.push(pair);
+    }
+
+    pairs.sort_by(|a, b| a.light_mono_mz.total_cmp(&b.light_mono_mz));
+    pairs
+}
+
+/// Robust ratio estimation from the intensities of two channels measured over several scans
+/// (median of the scan ratios computed in log space, scans where a channel is missing are ignored)
+pub fn estimate_ratio(light_intensities: &[f64], heavy_intensities: &[f64]) -> Option<f64> {
+    let log_ratios: Vec<f64> = light_intensities.iter().zip(heavy_intensities)
+        .filter(|(light, heavy)| **light > 0.0 && **heavy > 0.0)
+        .map(|(light, heavy)| (heavy / light).ln())
+        .collect();
+
+    median(&log_ratios).map(|log_ratio| log_ratio.exp())
+}
+
+/// Monoisotopic m/z of the envelope containing a given peak: unused peaks are searched at lower isotope positions,
+/// up to the position of the most abundant isotope expected from the averagine model.
+/// Returns None if the envelope extends an already detected one (a used peak is found at the previous isotope position).
+fn _find_monoisotopic_mz(peaks: &[Peak], is_used: &[bool], peak_mz: f64, charge: i8, config: &LabeledPairConfig) -> Option<f64> {
+    let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+    let peak_mass = mz_to_mass(peak_mz, charge as i32);
+    // The distribution is computed with enough isotopes to contain the most abundant one
+    let max_shift = IsotopeDistribution::averagine(peak_mass, (peak_mass / 500.0) as usize + 3)
+        .map(|distrib| distrib.most_abundant_index())
+        .unwrap_or(0);
+
+    let mut mono_mz = peak_mz;
+    for shift in 0..=max_shift {
+        let previous_peak_indices = _peak_index_range(peaks, mono_mz - isotope_spacing, config.mz_tolerance);
+        if previous_peak_indices.clone().any(|idx| is_used[idx]) {
+            return None
+        }
+        if shift == max_shift {
+            break
+        }
+
+        let previous_peak_idx = previous_peak_indices.into_iter().max_by(|&a, &b| peaks[a].intensity.total_cmp(&peaks[b].intensity));
+        let Some(previous_peak_idx) = previous_peak_idx else { break };
+        mono_mz = peaks[previous_peak_idx].mz;
+    }
+
+    Some(mono_mz)
+}
+
+/// Check if one of the first isotopic peaks of an envelope has already been assigned to another envelope
+fn _envelope_has_used_peaks(peaks: &[Peak], is_used: &[bool], mono_mz: f64, charge: i8, config: &LabeledPairConfig) -> bool {
+    let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+
+    (0..config.n_isotopes.max(config.min_isotopes)).any(|isotope_idx| {
+        _peak_index_range(peaks, mono_mz + isotope_idx as f64 * isotope_spacing, config.mz_tolerance).any(|idx| is_used[idx])
+    })
+}
+
+/// Range of the indices of the peaks (sorted by m/z) matching a given m/z
+fn _peak_index_range(peaks: &[Peak], mz: f64, tolerance: MassTolWindow) -> std::ops::Range<usize> {
+    let (lo, hi) = tolerance.bounds(mz);
+    peaks.partition_point(|peak| peak.mz < lo)..peaks.partition_point(|peak| peak.mz <= hi)
+}
+
+/// Summed intensity of the first isotopic peaks of an envelope (None if less than min_isotopes consecutive peaks are observed)
+fn _envelope_intensity(peaks: &[Peak], mono_mz: f64, charge: i8, config: &LabeledPairConfig) -> Option<f32> {
+    let isotope_spacing = AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF / charge as f64;
+
+    let mut intensity = 0.0;
+    for isotope_idx in 0..config.n_isotopes.max(config.min_isotopes) {
+        match select_most_intense_peak(peaks, mono_mz + isotope_idx as f64 * isotope_spacing, config.mz_tolerance, None) {
+            Some(peak) => intensity += peak.intensity,
+            None if isotope_idx < config.min_isotopes.max(1) => return None,
+            None => break,
+        }
+    }
+
+    Some(intensity)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::chemistry::table::proteinogenic_amino_acid_table;
+
+    #[test]
+    fn silac_and_dimethyl_channels() -> Result<()> {
+        let aa_table = proteinogenic_amino_acid_table();
+        let sequence: Arc<[u8]> = Arc::from("PEPTIDEK".as_bytes());
+        let peptide = LinearPeptide::new(sequence, Vec::new(), 927.4549, None)?;
+
+        let silac_masses: Vec<f64> = LabelingChannel::silac_channels().iter()
+            .map(|channel| channel.peptide_mono_mass(&peptide, aa_table))
+            .collect::<Result<Vec<f64>>>()?;
+        assert!((silac_masses[0] - 927.4549).abs() < 0.001, "unexpected light mass {}", silac_masses[0]);
+        assert!((silac_masses[1] - silac_masses[0] - 4.0251).abs() < 0.001);
+        assert!((silac_masses[2] - silac_masses[0] - 8.0142).abs() < 0.001);
+
+        let dimethyl_masses: Vec<f64> = LabelingChannel::dimethyl_channels().iter()
+            .map(|channel| channel.peptide_mono_mass(&peptide, aa_table))
+            .collect::<Result<Vec<f64>>>()?;
+        assert!((dimethyl_masses[0] - 927.4549 - 2.0 * 28.0313).abs() < 0.001);
+        assert!((dimethyl_masses[2] - dimethyl_masses[0] - 2.0 * 8.0444).abs() < 0.001);
+
+        let n15_mass = LabelingChannel::n15_channels()[1].peptide_mono_mass(&peptide, aa_table)?;
+        assert!((n15_mass - 927.4549 - 9.0 * 0.997035).abs() < 0.001);
+
+        // Labeled residues as amino acid table variants
+        let heavy_table = LabelingChannel::silac_channels()[2].labeled_amino_acid_table(aa_table)?;
+        let heavy_lys = heavy_table.aa_from_byte(&b'K')?;
+        assert_eq!(heavy_lys.formula.as_deref(), Some("H(12) 13C(6) 15N(2) O"));
+        assert!((heavy_lys.mono_mass - aa_table.aa_from_byte(&b'K')?.mono_mass - 8.0142).abs() < 0.001);
+        assert_eq!(heavy_lys.composition()?, LabelingChannel::silac_channels()[2].residue_composition(b'K', aa_table)?);
+
+        // Heavy/light pair detection (heavy is 2x more abundant)
+        let distrib = IsotopeDistribution::averagine(silac_masses[0], 3)?;
+        let mut peaks: Vec<Peak> = Vec::new();
+        for (mono_mass, scale) in [(silac_masses[0], 1000.0), (silac_masses[2], 2000.0)] {
+            for (isotope_idx, ab) in distrib.abundances.iter().enumerate() {
+                let mz = mass_to_mz(mono_mass + isotope_idx as f64 * AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF, 2);
+                peaks.push(Peak { mz, intensity: (scale * ab) as f32 });
+            }
+        }
+        peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+
+        let config = LabeledPairConfig::default();
+        let pairs = detect_labeled_pairs(&peaks, &[vec![8.0142], vec![10.0083]], &config);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].charge, 2);
+        assert!((pairs[0].ratios[0] - 2.0).abs() < 0.01);
+
+        let intensities = quantify_labeled_peptide(&peaks, &silac_masses, 2, &config);
+        assert_eq!(intensities[1], 0.0);
+        assert_eq!(estimate_ratio(&[intensities[0] as f64], &[intensities[2] as f64]).map(|r| (r * 100.0).round()), Some(200.0));
+
+        // Above ~1800 Da the most intense peak is not the monoisotopic one: a single pair is reported at the monoisotopic m/z
+        let large_mono_mass = 2456.2103;
+        let large_distrib = IsotopeDistribution::averagine(large_mono_mass, 6)?;
+        assert!(large_distrib.most_abundant_index() > 0);
+        let mut large_peaks: Vec<Peak> = Vec::new();
+        for (mono_mass, scale) in [(large_mono_mass, 1000.0), (large_mono_mass + 8.0142, 2000.0)] {
+            for (isotope_idx, ab) in large_distrib.abundances.iter().enumerate() {
+                let mz = mass_to_mz(mono_mass + isotope_idx as f64 * AVERAGE_PEPTIDE_ISOTOPE_MASS_DIFF, 3);
+                large_peaks.push(Peak { mz, intensity: (scale * ab) as f32 });
+            }
+        }
+        large_peaks.sort_by(|a, b| a.mz.total_cmp(&b.mz));
+
+        let large_pairs = detect_labeled_pairs(&large_peaks, &[vec![8.0142]], &config);
+        assert_eq!(large_pairs.len(), 1);
+        assert_eq!(large_pairs[0].charge, 3);
+        assert!((large_pairs[0].light_mono_mz - mass_to_mz(large_mono_mass, 3)).abs() < 1e-6);
+        assert!((large_pairs[0].ratios[0] - 2.0).abs() < 0.01);
+
+        Ok(())
+    }
+}
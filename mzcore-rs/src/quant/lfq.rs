@@ -0,0 +1,298 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::chemistry::digestion::DigestionConfig;
+use crate::common::regression::solve_linear_system;
+use crate::common::stats::{median, sorted_quantile};
+
+/// Peptide intensities of several LC-MS runs (rows are peptides, columns are runs), None for missing values
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeptideIntensityMatrix {
+    pub peptides: Vec<String>,
+    pub run_names: Vec<String>,
+    pub intensities: Vec<Vec<Option<f64>>>,
+}
+
+impl PeptideIntensityMatrix {
+    /// Non-positive or non-finite intensities are considered as missing values
+    pub fn new(peptides: Vec<String>, run_names: Vec<String>, mut intensities: Vec<Vec<Option<f64>>>) -> Result<PeptideIntensityMatrix> {
+        if intensities.len() != peptides.len() {
+            bail!("expected intensities for {} peptides but got {}", peptides.len(), intensities.len())
+        }
+        if intensities.iter().any(|row| row.len() != run_names.len()) {
+            bail!("each peptide must have one intensity value per run")
+        }
+
+        for intensity in intensities.iter_mut().flatten() {
+            if intensity.is_some_and(|ab| !ab.is_finite() || ab <= 0.0) {
+                *intensity = None;
+            }
+        }
+
+        Ok(PeptideIntensityMatrix { peptides, run_names, intensities })
+    }
+
+    pub fn n_runs(&self) -> usize {
+        self.run_names.len()
+    }
+
+    pub fn run_intensities(&self, run_idx: usize) -> Vec<f64> {
+        self.intensities.iter().filter_map(|row| row[run_idx]).collect()
+    }
+
+    pub fn normalize(&self, normalization: RunNormalization) -> PeptideIntensityMatrix {
+        let intensities = match normalization {
+            RunNormalization::Median => self._median_normalized_intensities(),
+            RunNormalization::Quantile => self._quantile_normalized_intensities(),
+        };
+
+        PeptideIntensityMatrix { intensities, ..self.clone() }
+    }
+
+    /// Scale each run so that the median log intensities of all runs are equal to their average
+    fn _median_normalized_intensities(&self) -> Vec<Vec<Option<f64>>> {
+        let log_medians: Vec<Option<f64>> = (0..self.n_runs()).map(|run_idx| {
+            let log_intensities: Vec<f64> = self.run_intensities(run_idx).iter().map(|ab| ab.ln()).collect();
+            median(&log_intensities)
+        }).collect();
+
+        let defined_medians: Vec<f64> = log_medians.iter().flatten().copied().collect();
+        if defined_medians.is_empty() {
+            return self.intensities.clone()
+        }
+        let target_log_median = defined_medians.iter().sum::<f64>() / defined_medians.len() as f64;
+
+        self.intensities.iter().map(|row| {
+            row.iter().zip(&log_medians).map(|(intensity, log_median)| {
+                intensity.map(|ab| ab * (target_log_median - log_median.unwrap_or(target_log_median)).exp())
+            }).collect()
+        }).collect()
+    }
+
+    /// Replace each value by the average of the runs quantiles at the same relative rank
+    /// (runs may have different numbers of missing values)
+    fn _quantile_normalized_intensities(&self) -> Vec<Vec<Option<f64>>> {
+        let sorted_runs: Vec<Vec<f64>> = (0..self.n_runs()).map(|run_idx| {
+            let mut run_intensities = self.run_intensities(run_idx);
+            run_intensities.sort_by(|a, b| a.total_cmp(b));
+            run_intensities
+        }).filter(|run_intensities| !run_intensities.is_empty()).collect();
+
+        let normalized_columns: Vec<Vec<Option<f64>>> = (0..self.n_runs()).map(|run_idx| {
+            let mut value_order: Vec<usize> = (0..self.intensities.len()).filter(|&i| self.intensities[i][run_idx].is_some()).collect();
+            value_order.sort_by(|&a, &b| self.intensities[a][run_idx].unwrap().total_cmp(&self.intensities[b][run_idx].unwrap())); // safe because missing values are filtered out
+
+            let n_values = value_order.len();
+            let mut normalized_column = vec![None; self.intensities.len()];
+            for (rank, &pep_idx) in value_order.iter().enumerate() {
+                let q = if n_values > 1 { rank as f64 / (n_values - 1) as f64 } else { 0.5 };
+                let mean_quantile = sorted_runs.iter().map(|run| sorted_quantile(run, q)).sum::<f64>() / sorted_runs.len() as f64;
+                normalized_column[pep_idx] = Some(mean_quantile);
+            }
+            normalized_column
+        }).collect();
+
+        (0..self.intensities.len()).map(|pep_idx| {
+            normalized_columns.iter().map(|column| column[pep_idx]).collect()
+        }).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum RunNormalization {
+    Median,
+    Quantile,
+}
+
+/// Protein group obtained from protein inference
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProteinGroup {
+    pub accessions: Vec<String>,
+    /// Indices of the quantified peptides in the peptide intensity matrix
+    pub peptide_indices: Vec<usize>,
+    /// Sequence of the leading protein (required for iBAQ)
+    pub leading_protein_sequence: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProteinRollup {
+    Sum,
+    /// Average of the N most intense peptides of each run
+    TopN { n: usize },
+    /// Summed intensity divided by the number of theoretical peptides of the leading protein
+    Ibaq { digestion: DigestionConfig },
+    /// Protein profile reconstructed from the median peptide ratios of all pairs of runs (Cox et al., MCP, 2014)
+    MaxLfq { min_ratio_count: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProteinIntensityMatrix {
+    pub protein_groups: Vec<ProteinGroup>,
+    pub run_names: Vec<String>,
+    pub intensities: Vec<Vec<Option<f64>>>,
+}
+
+pub fn rollup_protein_intensities(protein_groups: &[ProteinGroup], peptide_matrix: &PeptideIntensityMatrix, rollup: ProteinRollup) -> Result<ProteinIntensityMatrix> {
+    let n_peptides = peptide_matrix.intensities.len();
+
+    let intensities = protein_groups.iter().map(|protein_group| {
+        if let Some(&pep_idx) = protein_group.peptide_indices.iter().find(|&&idx| idx >= n_peptides) {
+            bail!("invalid peptide index {} for protein group {}", pep_idx, protein_group.accessions.join(";"))
+        }
+        let peptide_rows: Vec<&Vec<Option<f64>>> = protein_group.peptide_indices.iter().map(|&idx| &peptide_matrix.intensities[idx]).collect();
+
+        let protein_intensities = match rollup {
+            ProteinRollup::Sum => _sum_rollup(&peptide_rows, peptide_matrix.n_runs()),
+            ProteinRollup::TopN { n } => _top_n_rollup(&peptide_rows, peptide_matrix.n_runs(), n),
+            ProteinRollup::Ibaq { digestion } => {
+                let Some(sequence) = protein_group.leading_protein_sequence.as_ref() else {
+                    bail!("iBAQ requires the sequence of the leading protein of group {}", protein_group.accessions.join(";"))
+                };
+                let n_theo_peptides = digestion.digest(sequence)?.len().max(1);
+
+                _sum_rollup(&peptide_rows, peptide_matrix.n_runs()).into_iter()
+                    .map(|intensity| intensity.map(|ab| ab / n_theo_peptides as f64))
+                    .collect()
+            }
+            ProteinRollup::MaxLfq { min_ratio_count } => _max_lfq_rollup(&peptide_rows, peptide_matrix.n_runs(), min_ratio_count.max(1)),
+        };
+
+        Ok(protein_intensities)
+    }).collect::<Result<Vec<Vec<Option<f64>>>>>()?;
+
+    Ok(ProteinIntensityMatrix {
+        protein_groups: protein_groups.to_vec(),
+        run_names: peptide_matrix.run_names.clone(),
+        intensities,
+    })
+}
+
+fn _sum_rollup(peptide_rows: &[&Vec<Option<f64>>], n_runs: usize) -> Vec<Option<f64>> {
+    (0..n_runs).map(|run_idx| {
+        let run_intensities: Vec<f64> = peptide_rows.iter().filter_map(|row| row[run_idx]).collect();
+        if run_intensities.is_empty() { None } else { Some(run_intensities.iter().sum()) }
+    }).collect()
+}
+
+fn _top_n_rollup(peptide_rows: &[&Vec<Option<f64>>], n_runs: usize, n: usize) -> Vec<Option<f64>> {
+    (0..n_runs).map(|run_idx| {
+        let mut run_intensities: Vec<f64> = peptide_rows.iter().filter_map(|row| row[run_idx]).collect();
+        run_intensities.sort_by(|a, b| b.total_cmp(a));
+        run_intensities.truncate(n.max(1));
+        if run_intensities.is_empty() { None } else { Some(run_intensities.iter().sum::<f64>() / run_intensities.len() as f64) }
+    }).collect()
+}
+
+fn _max_lfq_rollup(peptide_rows: &[&Vec<Option<f64>>], n_runs: usize, min_ratio_count: usize) -> Vec<Option<f64>> {
+
+    // Median peptide log-ratio of each pair of runs
+    let mut pair_log_ratios: Vec<(usize, usize, f64)> = Vec::new();
+    for i in 0..n_runs {
+        for j in i + 1..n_runs {
+            let log_ratios: Vec<f64> = peptide_rows.iter()
+                .filter_map(|row| Some((row[i]?.ln()) - row[j]?.ln()))
+                .collect();
+            if log_ratios.len() >= min_ratio_count {
+                pair_log_ratios.push((i, j, median(&log_ratios).unwrap())); // safe because log_ratios is not empty
+            }
+        }
+    }
+
+    // Connected components of the graph of runs linked by a valid ratio
+    let mut component_ids: Vec<usize> = (0..n_runs).collect();
+    fn _find_root(component_ids: &mut [usize], run_idx: usize) -> usize {
+        let mut root = run_idx;
+        while component_ids[root] != root {
+            root = component_ids[root];
+        }
+        component_ids[run_idx] = root;
+        root
+    }
+    for &(i, j, _) in pair_log_ratios.iter() {
+        let (root_i, root_j) = (_find_root(&mut component_ids, i), _find_root(&mut component_ids, j));
+        component_ids[root_i.max(root_j)] = root_i.min(root_j);
+    }
+    let component_roots: Vec<usize> = (0..n_runs).map(|run_idx| _find_root(&mut component_ids, run_idx)).collect();
+
+    let mut protein_intensities = vec![None; n_runs];
+    for root in 0..n_runs {
+        let component_runs: Vec<usize> = (0..n_runs).filter(|&run_idx| component_roots[run_idx] == root).collect();
+        if component_runs.len() < 2 {
+            continue
+        }
+
+        // Least squares estimation of the log intensities (constrained to a null sum): (L + 1) x = b
+        let n = component_runs.len();
+        let local_idx = |run_idx: usize| component_runs.iter().position(|&r| r == run_idx).unwrap();
+        let mut matrix = vec![vec![1.0; n]; n];
+        let mut rhs = vec![0.0; n];
+        for &(i, j, log_ratio) in pair_log_ratios.iter().filter(|(i, _, _)| component_roots[*i] == root) {
+            let (li, lj) = (local_idx(i), local_idx(j));
+            matrix[li][li] += 1.0;
+            matrix[lj][lj] += 1.0;
+            matrix[li][lj] -= 1.0;
+            matrix[lj][li] -= 1.0;
+            rhs[li] += log_ratio;
+            rhs[lj] -= log_ratio;
+        }
+        let log_intensities = solve_linear_system(matrix, rhs);
+
+        // Rescale the profile so that its sum equals the summed peptide intensities of the component
+        let total_intensity: f64 = component_runs.iter()
+            .map(|&run_idx| peptide_rows.iter().filter_map(|row| row[run_idx]).sum::<f64>())
+            .sum();
+        let profile: Vec<f64> = log_intensities.iter().map(|x| x.exp()).collect();
+        let profile_sum: f64 = profile.iter().sum();
+
+        for (&run_idx, value) in component_runs.iter().zip(profile) {
+            protein_intensities[run_idx] = Some(value / profile_sum * total_intensity);
+        }
+    }
+
+    protein_intensities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protein_rollups() -> Result<()> {
+        // Protein is 2x more abundant in run 2, run 3 has a missing peptide
+        let peptide_matrix = PeptideIntensityMatrix::new(
+            vec!["PEPTIDEA".to_string(), "PEPTIDEB".to_string(), "PEPTIDEC".to_string()],
+            vec!["run1".to_string(), "run2".to_string(), "run3".to_string()],
+            vec![
+                vec![Some(100.0), Some(200.0), Some(100.0)],
+                vec![Some(1000.0), Some(2000.0), None],
+                vec![Some(10.0), Some(20.0), Some(10.0)],
+            ],
+        )?;
+
+        let protein_group = ProteinGroup {
+            accessions: vec!["P1".to_string()],
+            peptide_indices: vec![0, 1, 2],
+            leading_protein_sequence: Some("MKAPEPTIDEKBPEPTIDEKCPEPTIDEK".to_string()),
+        };
+
+        let max_lfq = rollup_protein_intensities(std::slice::from_ref(&protein_group), &peptide_matrix, ProteinRollup::MaxLfq { min_ratio_count: 2 })?;
+        let lfq_values: Vec<f64> = max_lfq.intensities[0].iter().map(|v| v.unwrap()).collect();
+        assert!((lfq_values[1] / lfq_values[0] - 2.0).abs() < 1e-9);
+        assert!((lfq_values[2] / lfq_values[0] - 1.0).abs() < 1e-9);
+
+        let top2 = rollup_protein_intensities(std::slice::from_ref(&protein_group), &peptide_matrix, ProteinRollup::TopN { n: 2 })?;
+        assert_eq!(top2.intensities[0], vec![Some(550.0), Some(1100.0), Some(55.0)]);
+
+        let digestion = DigestionConfig { min_length: 1, max_missed_cleavages: 0, ..Default::default() };
+        let ibaq = rollup_protein_intensities(&[protein_group], &peptide_matrix, ProteinRollup::Ibaq { digestion })?;
+        assert_eq!(ibaq.intensities[0][0], Some(1110.0 / 4.0));
+
+        let normalized_matrix = peptide_matrix.normalize(RunNormalization::Median);
+        assert!((normalized_matrix.intensities[0][0].unwrap() - normalized_matrix.intensities[0][1].unwrap()).abs() < 1e-9);
+
+        let quantile_matrix = peptide_matrix.normalize(RunNormalization::Quantile);
+        assert_eq!(quantile_matrix.intensities[2][0], quantile_matrix.intensities[2][1]);
+
+        Ok(())
+    }
+}
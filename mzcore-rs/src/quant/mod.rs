@@ -0,0 +1,3 @@
+pub mod isobaric;
+pub mod labeling;
+pub mod lfq;